@@ -0,0 +1,106 @@
+//! Compares reading values out as `InlineArray`s, which are shared, with
+//! copying them into fresh `Vec<u8>`s, which is what backends used to do.
+//!
+//! Run with `cargo bench --bench inline_array`.
+
+use std::collections::BTreeMap;
+
+use aymr::aymr_db::{
+    db::AymrDb,
+    traits::{
+        AymrDatabase,
+        InlineArray,
+    },
+};
+use criterion::{
+    black_box,
+    criterion_group,
+    criterion_main,
+    BenchmarkId,
+    Criterion,
+    Throughput,
+};
+
+/// Value sizes to compare, from inline up to a page and beyond.
+const SIZES: [usize; 5] = [8, 22, 64, 1024, 16 * 1024];
+
+/// Number of keys stored in each map.
+const KEYS: u32 = 1024;
+
+fn clone(c: &mut Criterion) {
+    let mut group = c.benchmark_group("clone");
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let shared = InlineArray::from(vec![7; size]);
+        group.bench_with_input(BenchmarkId::new("inline_array", size), &shared, |b, v| {
+            b.iter(|| black_box(v.clone()))
+        });
+
+        let owned = vec![7u8; size];
+        group.bench_with_input(BenchmarkId::new("to_vec", size), &owned, |b, v| {
+            b.iter(|| black_box(v.as_slice().to_vec()))
+        });
+    }
+    group.finish();
+}
+
+fn map_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("map_get");
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let shared: BTreeMap<InlineArray, InlineArray> = (0..KEYS)
+            .map(|k| (k.to_be_bytes().into(), vec![7; size].into()))
+            .collect();
+        group.bench_with_input(BenchmarkId::new("inline_array", size), &shared, |b, map| {
+            let mut k = 0u32;
+            b.iter(|| {
+                k = (k + 1) % KEYS;
+                black_box(map.get(&k.to_be_bytes()[..]).cloned())
+            })
+        });
+
+        let owned: BTreeMap<Vec<u8>, Vec<u8>> = (0..KEYS)
+            .map(|k| (k.to_be_bytes().to_vec(), vec![7; size]))
+            .collect();
+        group.bench_with_input(BenchmarkId::new("to_vec", size), &owned, |b, map| {
+            let mut k = 0u32;
+            b.iter(|| {
+                k = (k + 1) % KEYS;
+                black_box(map.get(&k.to_be_bytes()[..]).map(|v| v.to_vec()))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn db_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("db_get");
+    for size in SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let mut db = AymrDb::<[u8; 4], Vec<u8>>::temporary().unwrap();
+        for k in 0..KEYS {
+            db.insert(k.to_be_bytes(), vec![7; size]).unwrap();
+        }
+        group.bench_with_input(BenchmarkId::new("inline_array", size), &db, |b, db| {
+            let mut k = 0u32;
+            b.iter(|| {
+                k = (k + 1) % KEYS;
+                black_box(db.get(&k.to_be_bytes()).unwrap())
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("to_vec", size), &db, |b, db| {
+            let mut k = 0u32;
+            b.iter(|| {
+                k = (k + 1) % KEYS;
+                black_box(db.get(&k.to_be_bytes()).unwrap().map(Vec::from))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, clone, map_get, db_get);
+criterion_main!(benches);
@@ -1,29 +1,34 @@
 use aymr::aymr_db::{
-    db,
-    traits::AymrDatabase,
+    codec::{
+        Tuple,
+        Zerocopy,
+    },
+    config::BackendConfig,
+    typed::TypedDb,
 };
-use zerocopy::AsBytes;
 
 fn main() {
-    // One can open a new Aymr DB by calling the open fn.
+    // One can open a new Aymr DB by calling the open fn with a config.
     //
-    // Keep in mind that the way of declaring the config changes
-    // with each database.
-    let mut aymr = db::AymrDb::open();
+    // Keep in mind that the settings a config has change with each
+    // database. The default config picks an in-memory one.
+    //
+    // A `TypedDb` encodes keys and values with codecs, so we can use our
+    // integers directly. `Tuple` stores keys so they sort numerically, while
+    // `Zerocopy` stores values as they are laid out in memory.
+    let mut aymr = TypedDb::<i32, i32, Tuple, Zerocopy>::open(BackendConfig::default()).unwrap();
 
     // Insert inserts a value into our database with a key.
     // It returns the previous value at that key if it existed.
-    let _ = aymr.insert(123.as_bytes(), 456.as_bytes());
+    let _ = aymr.insert(&123, &456);
 
     // We can get our value by using `get` and a key.
-    let our_value = aymr.get(&123.as_bytes()).unwrap().unwrap();
-    let our_value: i32 = i32::from_le_bytes(our_value.as_slice().try_into().unwrap());
+    let our_value = aymr.get(&123).unwrap().unwrap();
     println!("Hello from aymr! The value at key `123` is {}", our_value);
 
     // If we want to remove a value, just call remove!
-    let old_value = aymr.remove(&123.as_bytes()).unwrap().unwrap();
-    let old_value: i32 = i32::from_le_bytes(old_value.as_slice().try_into().unwrap());
-    let removed_value = aymr.get(&123.as_bytes()).unwrap();
+    let old_value = aymr.remove(&123).unwrap().unwrap();
+    let removed_value = aymr.get(&123).unwrap();
 
     println!(
         "We got bored of the value {} at key `123`. The value at key `123` is now {:?}",
@@ -0,0 +1,803 @@
+//! `Aymr backends`
+//!
+//! Every backend compiled into the crate can be picked at runtime. Features
+//! only decide which backends get compiled in, and enabling several of them
+//! at once is fine.
+//!
+//! `AymrBackend` holds any of the compiled in backends and dispatches every
+//! call to it. Backends are named by `BackendKind`, which can also be parsed
+//! from the scheme of a URL such as `btreemap://` or `sled:///var/db`.
+
+use std::{
+    fmt,
+    io,
+    ops::RangeBounds,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+#[cfg(feature = "config-toml")]
+use serde::Deserialize;
+
+#[cfg(feature = "btreemap")]
+use crate::backends::btreemap::db::{
+    AymrBtreeMap,
+    AymrBtreeMapSnapshot,
+};
+#[cfg(feature = "fjall")]
+use crate::backends::fjall::db::{
+    AymrFjall,
+    AymrFjallSnapshot,
+};
+#[cfg(feature = "hashmap")]
+use crate::backends::hashmap::db::{
+    AymrHashMap,
+    AymrHashMapSnapshot,
+};
+#[cfg(feature = "lmdb")]
+use crate::backends::lmdb::db::{
+    AymrLmdb,
+    AymrLmdbSnapshot,
+};
+#[cfg(feature = "redb")]
+use crate::backends::redb::db::{
+    AymrRedb,
+    AymrRedbSnapshot,
+};
+#[cfg(feature = "sled_alpha")]
+use crate::backends::sled_alpha::db::{
+    AymrSledAlpha,
+    AymrSledAlphaSnapshot,
+};
+#[cfg(feature = "sled_pre")]
+use crate::backends::sled_pre::db::{
+    AymrSled,
+    AymrSledSnapshot,
+};
+
+#[cfg(any(feature = "btreemap", feature = "hashmap"))]
+use crate::aymr_db::tree::AymrTree;
+use crate::aymr_db::{
+    config::{
+        AymrDbBuilder,
+        BackendConfig,
+    },
+    error::{
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::Iter,
+    merge::MergeOperator,
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrFlush,
+        AymrOpenable,
+        AymrSnapshot,
+        AymrTransactional,
+        AymrTrees,
+        InlineArray,
+    },
+    transaction::{
+        self,
+        ConflictableTransactionResult,
+        Transaction,
+        TransactionResult,
+    },
+    watch::Subscriber,
+};
+
+/// Names a backend Aymr knows about, whether or not it is compiled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config-toml", derive(Deserialize))]
+#[cfg_attr(feature = "config-toml", serde(rename_all = "lowercase"))]
+pub enum BackendKind {
+    /// In-memory `BTreeMap`, needs the `btreemap` feature.
+    BtreeMap,
+    /// In-memory `HashMap`, needs the `hashmap` feature.
+    HashMap,
+    /// On-disk `sled` 0.34, needs the `sled_pre` feature.
+    Sled,
+    /// On-disk `sled` 1.0-alpha, needs the `sled_alpha` feature.
+    #[cfg_attr(feature = "config-toml", serde(rename = "sled_alpha"))]
+    SledAlpha,
+    /// On-disk `redb`, needs the `redb` feature.
+    Redb,
+    /// On-disk `fjall`, needs the `fjall` feature.
+    Fjall,
+    /// On-disk `lmdb`, needs the `lmdb` feature.
+    Lmdb,
+}
+
+impl BackendKind {
+    /// Every backend kind, in no particular order.
+    pub const ALL: [BackendKind; 7] = [
+        BackendKind::BtreeMap,
+        BackendKind::HashMap,
+        BackendKind::Sled,
+        BackendKind::SledAlpha,
+        BackendKind::Redb,
+        BackendKind::Fjall,
+        BackendKind::Lmdb,
+    ];
+
+    /// Returns the name used for this backend in URLs.
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::BtreeMap => "btreemap",
+            BackendKind::HashMap => "hashmap",
+            BackendKind::Sled => "sled",
+            BackendKind::SledAlpha => "sled_alpha",
+            BackendKind::Redb => "redb",
+            BackendKind::Fjall => "fjall",
+            BackendKind::Lmdb => "lmdb",
+        }
+    }
+
+    /// Returns true if this backend is compiled in.
+    pub fn is_available(self) -> bool {
+        match self {
+            BackendKind::BtreeMap => cfg!(feature = "btreemap"),
+            BackendKind::HashMap => cfg!(feature = "hashmap"),
+            BackendKind::Sled => cfg!(feature = "sled_pre"),
+            BackendKind::SledAlpha => cfg!(feature = "sled_alpha"),
+            BackendKind::Redb => cfg!(feature = "redb"),
+            BackendKind::Fjall => cfg!(feature = "fjall"),
+            BackendKind::Lmdb => cfg!(feature = "lmdb"),
+        }
+    }
+
+    /// Returns true if this backend keeps its data in memory only.
+    pub fn is_in_memory(self) -> bool {
+        matches!(self, BackendKind::BtreeMap | BackendKind::HashMap)
+    }
+
+    /// Returns every backend that is compiled in.
+    pub fn available() -> Vec<BackendKind> {
+        BackendKind::ALL
+            .into_iter()
+            .filter(|kind| kind.is_available())
+            .collect()
+    }
+}
+
+/// `btreemap` if it is compiled in, since it supports every operation,
+/// and `hashmap` otherwise.
+impl Default for BackendKind {
+    fn default() -> Self {
+        if cfg!(feature = "btreemap") {
+            BackendKind::BtreeMap
+        } else {
+            BackendKind::HashMap
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Error> {
+        BackendKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| Error::Unsupported(format!("unknown backend `{}`", name)))
+    }
+}
+
+/// Splits a backend URL like `sled:///var/db` into its kind and path.
+///
+/// The path is `None` if the URL has nothing after the scheme.
+pub(crate) fn parse_url(url: &str) -> Result<(BackendKind, Option<&Path>), Error> {
+    let (scheme, path) = url.split_once("://").ok_or_else(|| {
+        Error::Unsupported(format!("`{}` is not a backend URL like `btreemap://`", url))
+    })?;
+    let path = (!path.is_empty()).then(|| Path::new(path));
+
+    Ok((scheme.parse()?, path))
+}
+
+/// Error returned when opening a backend that is not compiled in.
+fn unavailable(kind: BackendKind) -> Error {
+    Error::Unsupported(format!(
+        "the `{}` backend is not available in this build",
+        kind
+    ))
+}
+
+/// Error returned when asked to open an existing database of an in-memory
+/// backend, as those only exist while they are open.
+#[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+pub(crate) fn not_persisted(kind: BackendKind) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("`{}` databases only exist while they are open", kind),
+    ))
+}
+
+/// Opens the backend `$config` is for through `$open`, one of the
+/// constructors of `AymrOpenable`.
+macro_rules! open_backend {
+    ($config:expr, $open:ident) => {
+        match $config {
+            #[cfg(feature = "btreemap")]
+            BackendConfig::BtreeMap(config) => {
+                Ok(AymrBackend::BtreeMap(AymrBtreeMap::$open(config)?))
+            }
+            #[cfg(feature = "hashmap")]
+            BackendConfig::HashMap(config) => Ok(AymrBackend::HashMap(AymrHashMap::$open(config)?)),
+            #[cfg(feature = "sled_pre")]
+            BackendConfig::Sled(config) => Ok(AymrBackend::Sled(AymrSled::$open(config)?)),
+            #[cfg(feature = "sled_alpha")]
+            BackendConfig::SledAlpha(config) => {
+                Ok(AymrBackend::SledAlpha(AymrSledAlpha::$open(config)?))
+            }
+            #[cfg(feature = "redb")]
+            BackendConfig::Redb(config) => Ok(AymrBackend::Redb(AymrRedb::$open(config)?)),
+            #[cfg(feature = "fjall")]
+            BackendConfig::Fjall(config) => Ok(AymrBackend::Fjall(AymrFjall::$open(config)?)),
+            #[cfg(feature = "lmdb")]
+            BackendConfig::Lmdb(config) => Ok(AymrBackend::Lmdb(AymrLmdb::$open(config)?)),
+            #[allow(unreachable_patterns)]
+            config => Err(unavailable(config.kind())),
+        }
+    };
+}
+
+/// Dispatches to the backend held by `$value`, binding it to `$inner`.
+///
+/// `AymrBackend`, `AymrBackendSnapshot` and `AymrBackendTree` share their
+/// variant names, so this works for all three of them.
+macro_rules! dispatch {
+    ($value:expr, $inner:ident => $body:expr) => {
+        match $value {
+            #[cfg(feature = "btreemap")]
+            Self::BtreeMap($inner) => $body,
+            #[cfg(feature = "hashmap")]
+            Self::HashMap($inner) => $body,
+            #[cfg(feature = "sled_pre")]
+            Self::Sled($inner) => $body,
+            #[cfg(feature = "sled_alpha")]
+            Self::SledAlpha($inner) => $body,
+            #[cfg(feature = "redb")]
+            Self::Redb($inner) => $body,
+            #[cfg(feature = "fjall")]
+            Self::Fjall($inner) => $body,
+            #[cfg(feature = "lmdb")]
+            Self::Lmdb($inner) => $body,
+        }
+    };
+}
+
+/// Forwards every `AymrDatabase` method besides `snapshot` through `dispatch!`.
+macro_rules! forward_database {
+    () => {
+        fn clear(&mut self) -> Result<(), Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::clear(db))
+        }
+
+        fn len(&self) -> usize {
+            dispatch!(self, db => AymrDatabase::<K, V>::len(db))
+        }
+
+        fn is_empty(&self) -> Result<bool, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::is_empty(db))
+        }
+
+        fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::get(db, key))
+        }
+
+        fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::insert(db, key, value))
+        }
+
+        fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::remove(db, key))
+        }
+
+        fn insert_with_ttl(
+            &mut self,
+            key: K,
+            value: V,
+            ttl: Duration,
+        ) -> Result<Option<InlineArray>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::insert_with_ttl(db, key, value, ttl))
+        }
+
+        fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::ttl(db, key))
+        }
+
+        fn purge_expired(&mut self) -> Result<usize, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::purge_expired(db))
+        }
+
+        fn compare_and_swap<OV: AsRef<[u8]>>(
+            &mut self,
+            key: K,
+            old: Option<OV>,
+            new: Option<V>,
+        ) -> CompareAndSwapResult {
+            dispatch!(self, db => AymrDatabase::<K, V>::compare_and_swap(db, key, old, new))
+        }
+
+        fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+            dispatch!(self, db => AymrDatabase::<K, V>::set_merge_operator(db, merge_operator))
+        }
+
+        fn merge<O: AsRef<[u8]>>(
+            &mut self,
+            key: K,
+            operand: O,
+        ) -> Result<Option<InlineArray>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::merge(db, key, operand))
+        }
+
+        fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::apply_batch(db, batch))
+        }
+
+        fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::contains_key(db, key))
+        }
+
+        fn iter(&self) -> Result<Iter<'_>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::iter(db))
+        }
+
+        fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+        where
+            Q: AsRef<[u8]> + ?Sized,
+            R: RangeBounds<Q>,
+        {
+            dispatch!(self, db => AymrDatabase::<K, V>::range(db, range))
+        }
+
+        fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::scan_prefix(db, prefix))
+        }
+
+        fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::remove_prefix(db, prefix))
+        }
+
+        fn get_lt<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::get_lt(db, key))
+        }
+
+        fn get_gt<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::get_gt(db, key))
+        }
+
+        fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::first(db))
+        }
+
+        fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::last(db))
+        }
+
+        fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::pop_min(db))
+        }
+
+        fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::pop_max(db))
+        }
+
+        fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+            dispatch!(self, db => AymrDatabase::<K, V>::watch_prefix(db, prefix))
+        }
+    };
+}
+
+/// Any of the backends compiled into the crate, picked at runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AymrBackend {
+    #[cfg(feature = "btreemap")]
+    BtreeMap(AymrBtreeMap),
+    #[cfg(feature = "hashmap")]
+    HashMap(AymrHashMap),
+    #[cfg(feature = "sled_pre")]
+    Sled(AymrSled),
+    #[cfg(feature = "sled_alpha")]
+    SledAlpha(AymrSledAlpha),
+    #[cfg(feature = "redb")]
+    Redb(AymrRedb),
+    #[cfg(feature = "fjall")]
+    Fjall(AymrFjall),
+    #[cfg(feature = "lmdb")]
+    Lmdb(AymrLmdb),
+}
+
+impl AymrBackend {
+    /// Opens a backend of the given kind with its default config.
+    pub fn open_with(kind: BackendKind) -> Result<Self, Error> {
+        <Self as AymrOpenable>::open(kind.into())
+    }
+
+    /// Opens the backend named by a URL such as `btreemap://` or `sled:///var/db`.
+    pub fn open_url(url: &str) -> Result<Self, Error> {
+        let (kind, path) = parse_url(url)?;
+        let mut builder = AymrDbBuilder::new().backend(kind);
+        if let Some(path) = path {
+            builder = builder.path(path);
+        }
+
+        <Self as AymrOpenable>::open(builder.build()?)
+    }
+
+    /// Returns the kind of the backend in use.
+    pub fn kind(&self) -> BackendKind {
+        match self {
+            #[cfg(feature = "btreemap")]
+            AymrBackend::BtreeMap(_) => BackendKind::BtreeMap,
+            #[cfg(feature = "hashmap")]
+            AymrBackend::HashMap(_) => BackendKind::HashMap,
+            #[cfg(feature = "sled_pre")]
+            AymrBackend::Sled(_) => BackendKind::Sled,
+            #[cfg(feature = "sled_alpha")]
+            AymrBackend::SledAlpha(_) => BackendKind::SledAlpha,
+            #[cfg(feature = "redb")]
+            AymrBackend::Redb(_) => BackendKind::Redb,
+            #[cfg(feature = "fjall")]
+            AymrBackend::Fjall(_) => BackendKind::Fjall,
+            #[cfg(feature = "lmdb")]
+            AymrBackend::Lmdb(_) => BackendKind::Lmdb,
+        }
+    }
+}
+
+/// Opening a backend that is not compiled in fails with `Error::Unsupported`.
+impl AymrOpenable for AymrBackend {
+    type Config = BackendConfig;
+
+    fn open(config: BackendConfig) -> Result<Self, Error> {
+        open_backend!(config, open)
+    }
+
+    fn create_new(config: BackendConfig) -> Result<Self, Error> {
+        open_backend!(config, create_new)
+    }
+
+    fn open_existing(config: BackendConfig) -> Result<Self, Error> {
+        open_backend!(config, open_existing)
+    }
+
+    /// Opens a temporary database of the default backend.
+    fn temporary() -> Result<Self, Error> {
+        match BackendKind::default() {
+            #[cfg(feature = "btreemap")]
+            BackendKind::BtreeMap => Ok(AymrBackend::BtreeMap(AymrBtreeMap::temporary()?)),
+            #[cfg(feature = "hashmap")]
+            BackendKind::HashMap => Ok(AymrBackend::HashMap(AymrHashMap::temporary()?)),
+            #[allow(unreachable_patterns)]
+            kind => Err(unavailable(kind)),
+        }
+    }
+
+    fn close(self) -> Result<(), Error> {
+        dispatch!(self, db => db.close())
+    }
+}
+
+impl<K, V> AymrDatabase<K, V> for AymrBackend
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Snapshot = AymrBackendSnapshot;
+
+    forward_database!();
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        match self {
+            #[cfg(feature = "btreemap")]
+            AymrBackend::BtreeMap(db) => {
+                Ok(AymrBackendSnapshot::BtreeMap(
+                    AymrDatabase::<K, V>::snapshot(db)?,
+                ))
+            }
+            #[cfg(feature = "hashmap")]
+            AymrBackend::HashMap(db) => {
+                Ok(AymrBackendSnapshot::HashMap(
+                    AymrDatabase::<K, V>::snapshot(db)?,
+                ))
+            }
+            #[cfg(feature = "sled_pre")]
+            AymrBackend::Sled(db) => {
+                Ok(AymrBackendSnapshot::Sled(AymrDatabase::<K, V>::snapshot(
+                    db,
+                )?))
+            }
+            #[cfg(feature = "sled_alpha")]
+            AymrBackend::SledAlpha(db) => {
+                Ok(AymrBackendSnapshot::SledAlpha(
+                    AymrDatabase::<K, V>::snapshot(db)?,
+                ))
+            }
+            #[cfg(feature = "redb")]
+            AymrBackend::Redb(db) => {
+                Ok(AymrBackendSnapshot::Redb(AymrDatabase::<K, V>::snapshot(
+                    db,
+                )?))
+            }
+            #[cfg(feature = "fjall")]
+            AymrBackend::Fjall(db) => {
+                Ok(AymrBackendSnapshot::Fjall(AymrDatabase::<K, V>::snapshot(
+                    db,
+                )?))
+            }
+            #[cfg(feature = "lmdb")]
+            AymrBackend::Lmdb(db) => {
+                Ok(AymrBackendSnapshot::Lmdb(AymrDatabase::<K, V>::snapshot(
+                    db,
+                )?))
+            }
+        }
+    }
+}
+
+/// Backends that can commit on their own do, the others validate and apply
+/// the writes themselves.
+impl<K, V> AymrTransactional<K, V> for AymrBackend
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |backend, pending| {
+            match backend {
+                #[cfg(feature = "sled_pre")]
+                AymrBackend::Sled(db) => db.commit(pending),
+                #[cfg(feature = "sled_alpha")]
+                AymrBackend::SledAlpha(db) => db.commit(pending),
+                #[cfg(feature = "redb")]
+                AymrBackend::Redb(db) => db.commit(pending),
+                #[cfg(feature = "fjall")]
+                AymrBackend::Fjall(db) => db.commit(pending),
+                #[cfg(feature = "lmdb")]
+                AymrBackend::Lmdb(db) => db.commit(pending),
+                #[allow(unreachable_patterns)]
+                backend => pending.apply_to(backend),
+            }
+        })
+    }
+}
+
+impl<K, V> AymrTrees<K, V> for AymrBackend
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Tree = AymrBackendTree;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        match self {
+            #[cfg(feature = "btreemap")]
+            AymrBackend::BtreeMap(db) => {
+                Ok(AymrBackendTree::BtreeMap(AymrTrees::<K, V>::open_tree(
+                    db, name,
+                )?))
+            }
+            #[cfg(feature = "hashmap")]
+            AymrBackend::HashMap(db) => {
+                Ok(AymrBackendTree::HashMap(AymrTrees::<K, V>::open_tree(
+                    db, name,
+                )?))
+            }
+            #[cfg(feature = "sled_pre")]
+            AymrBackend::Sled(db) => {
+                Ok(AymrBackendTree::Sled(AymrTrees::<K, V>::open_tree(
+                    db, name,
+                )?))
+            }
+            #[cfg(feature = "sled_alpha")]
+            AymrBackend::SledAlpha(db) => {
+                Ok(AymrBackendTree::SledAlpha(AymrTrees::<K, V>::open_tree(
+                    db, name,
+                )?))
+            }
+            #[cfg(feature = "redb")]
+            AymrBackend::Redb(db) => {
+                Ok(AymrBackendTree::Redb(AymrTrees::<K, V>::open_tree(
+                    db, name,
+                )?))
+            }
+            #[cfg(feature = "fjall")]
+            AymrBackend::Fjall(db) => {
+                Ok(AymrBackendTree::Fjall(AymrTrees::<K, V>::open_tree(
+                    db, name,
+                )?))
+            }
+            #[cfg(feature = "lmdb")]
+            AymrBackend::Lmdb(db) => {
+                Ok(AymrBackendTree::Lmdb(AymrTrees::<K, V>::open_tree(
+                    db, name,
+                )?))
+            }
+        }
+    }
+
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        dispatch!(self, db => AymrTrees::<K, V>::drop_tree(db, name))
+    }
+
+    fn tree_names(&self) -> Vec<InlineArray> {
+        dispatch!(self, db => AymrTrees::<K, V>::tree_names(db))
+    }
+}
+
+/// In-memory backends have nothing to flush.
+impl AymrFlush for AymrBackend {
+    fn flush(&self) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "sled_pre")]
+            AymrBackend::Sled(db) => db.flush(),
+            #[cfg(feature = "sled_alpha")]
+            AymrBackend::SledAlpha(db) => db.flush(),
+            #[cfg(feature = "redb")]
+            AymrBackend::Redb(db) => db.flush(),
+            #[cfg(feature = "fjall")]
+            AymrBackend::Fjall(db) => db.flush(),
+            #[cfg(feature = "lmdb")]
+            AymrBackend::Lmdb(db) => db.flush(),
+            #[allow(unreachable_patterns)]
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Snapshot of an `AymrBackend`.
+#[derive(Debug, Clone)]
+pub enum AymrBackendSnapshot {
+    #[cfg(feature = "btreemap")]
+    BtreeMap(AymrBtreeMapSnapshot),
+    #[cfg(feature = "hashmap")]
+    HashMap(AymrHashMapSnapshot),
+    #[cfg(feature = "sled_pre")]
+    Sled(AymrSledSnapshot),
+    #[cfg(feature = "sled_alpha")]
+    SledAlpha(AymrSledAlphaSnapshot),
+    #[cfg(feature = "redb")]
+    Redb(AymrRedbSnapshot),
+    #[cfg(feature = "fjall")]
+    Fjall(AymrFjallSnapshot),
+    #[cfg(feature = "lmdb")]
+    Lmdb(AymrLmdbSnapshot),
+}
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrBackendSnapshot {
+    fn len(&self) -> usize {
+        dispatch!(self, snapshot => AymrSnapshot::<K>::len(snapshot))
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        dispatch!(self, snapshot => AymrSnapshot::<K>::is_empty(snapshot))
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        dispatch!(self, snapshot => AymrSnapshot::<K>::get(snapshot, key))
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        dispatch!(self, snapshot => AymrSnapshot::<K>::contains_key(snapshot, key))
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        dispatch!(self, snapshot => AymrSnapshot::<K>::iter(snapshot))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        dispatch!(self, snapshot => AymrSnapshot::<K>::range(snapshot, range))
+    }
+}
+
+/// Handle to a named tree of an `AymrBackend`.
+#[derive(Debug, Clone)]
+pub enum AymrBackendTree {
+    #[cfg(feature = "btreemap")]
+    BtreeMap(AymrTree<AymrBtreeMap>),
+    #[cfg(feature = "hashmap")]
+    HashMap(AymrTree<AymrHashMap>),
+    #[cfg(feature = "sled_pre")]
+    Sled(AymrSled),
+    #[cfg(feature = "sled_alpha")]
+    SledAlpha(AymrSledAlpha),
+    #[cfg(feature = "redb")]
+    Redb(AymrRedb),
+    #[cfg(feature = "fjall")]
+    Fjall(AymrFjall),
+    #[cfg(feature = "lmdb")]
+    Lmdb(AymrLmdb),
+}
+
+impl<K, V> AymrDatabase<K, V> for AymrBackendTree
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Snapshot = AymrBackendSnapshot;
+
+    forward_database!();
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        match self {
+            #[cfg(feature = "btreemap")]
+            AymrBackendTree::BtreeMap(tree) => {
+                Ok(AymrBackendSnapshot::BtreeMap(
+                    AymrDatabase::<K, V>::snapshot(tree)?,
+                ))
+            }
+            #[cfg(feature = "hashmap")]
+            AymrBackendTree::HashMap(tree) => {
+                Ok(AymrBackendSnapshot::HashMap(
+                    AymrDatabase::<K, V>::snapshot(tree)?,
+                ))
+            }
+            #[cfg(feature = "sled_pre")]
+            AymrBackendTree::Sled(tree) => {
+                Ok(AymrBackendSnapshot::Sled(AymrDatabase::<K, V>::snapshot(
+                    tree,
+                )?))
+            }
+            #[cfg(feature = "sled_alpha")]
+            AymrBackendTree::SledAlpha(tree) => {
+                Ok(AymrBackendSnapshot::SledAlpha(
+                    AymrDatabase::<K, V>::snapshot(tree)?,
+                ))
+            }
+            #[cfg(feature = "redb")]
+            AymrBackendTree::Redb(tree) => {
+                Ok(AymrBackendSnapshot::Redb(AymrDatabase::<K, V>::snapshot(
+                    tree,
+                )?))
+            }
+            #[cfg(feature = "fjall")]
+            AymrBackendTree::Fjall(tree) => {
+                Ok(AymrBackendSnapshot::Fjall(AymrDatabase::<K, V>::snapshot(
+                    tree,
+                )?))
+            }
+            #[cfg(feature = "lmdb")]
+            AymrBackendTree::Lmdb(tree) => {
+                Ok(AymrBackendSnapshot::Lmdb(AymrDatabase::<K, V>::snapshot(
+                    tree,
+                )?))
+            }
+        }
+    }
+}
+
+/// Commits under the tree's write lock, like `AymrTree` itself, or through
+/// the transactions of backends with native ones.
+impl<K, V> AymrTransactional<K, V> for AymrBackendTree
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(
+            self,
+            f,
+            |handle, pending| dispatch!(handle, tree => tree.commit(pending)),
+        )
+    }
+}
@@ -0,0 +1,151 @@
+//! `Aymr codecs`
+//!
+//! A codec turns values of some type into the bytes a database stores, and
+//! back. `TypedDb` uses one codec for its keys and one for its values.
+//!
+//! `Bincode` needs the `bincode` feature and `Json` the `json` feature.
+//! Besides the codecs below, any type implementing `Codec` can be used. Only
+//! codecs whose encoding sorts like the values themselves give meaningful
+//! ranges, which of the ones here only `Tuple` does for numbers.
+
+#[cfg(any(feature = "bincode", feature = "json"))]
+use serde::{
+    de::DeserializeOwned,
+    Serialize,
+};
+use zerocopy::{
+    AsBytes,
+    FromBytes,
+};
+
+use crate::aymr_db::{
+    error::Error,
+    traits::InlineArray,
+    tuple::{
+        self,
+        Pack,
+    },
+};
+
+/// Turns values of type `T` into bytes and back.
+pub trait Codec<T> {
+    /// Encodes `value` into the bytes that get stored.
+    fn encode(value: &T) -> Result<InlineArray, Error>;
+
+    /// Decodes stored bytes, failing with `Error::Decode` if they are malformed.
+    fn decode(bytes: InlineArray) -> Result<T, Error>;
+}
+
+/// Stores the in-memory representation of plain data types, like integers or
+/// `#[repr(C)]` structs deriving the `zerocopy` traits.
+///
+/// Integers are stored in native byte order, so they don't sort numerically.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Zerocopy;
+
+impl<T: AsBytes + FromBytes> Codec<T> for Zerocopy {
+    fn encode(value: &T) -> Result<InlineArray, Error> {
+        Ok(value.as_bytes().into())
+    }
+
+    fn decode(bytes: InlineArray) -> Result<T, Error> {
+        T::read_from(&bytes).ok_or_else(|| {
+            Error::Decode(format!(
+                "expected {} bytes, found {}",
+                std::mem::size_of::<T>(),
+                bytes.len()
+            ))
+        })
+    }
+}
+
+/// Stores any serde type in the compact `bincode` format.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for Bincode {
+    fn encode(value: &T) -> Result<InlineArray, Error> {
+        bincode::serialize(value)
+            .map(InlineArray::from)
+            .map_err(|e| Error::Unsupported(format!("can't encode value with bincode: {}", e)))
+    }
+
+    fn decode(bytes: InlineArray) -> Result<T, Error> {
+        bincode::deserialize(&bytes).map_err(|e| Error::Decode(e.to_string()))
+    }
+}
+
+/// Stores any serde type as JSON, which is bigger than `Bincode` but readable
+/// by other tools.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> Result<InlineArray, Error> {
+        serde_json::to_vec(value)
+            .map(InlineArray::from)
+            .map_err(|e| Error::Unsupported(format!("can't encode value as JSON: {}", e)))
+    }
+
+    fn decode(bytes: InlineArray) -> Result<T, Error> {
+        serde_json::from_slice(&bytes).map_err(|e| Error::Decode(e.to_string()))
+    }
+}
+
+/// Stores strings as their UTF-8 bytes. Decoding fails on invalid UTF-8.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Str;
+
+impl Codec<String> for Str {
+    fn encode(value: &String) -> Result<InlineArray, Error> {
+        Ok(value.as_str().into())
+    }
+
+    fn decode(bytes: InlineArray) -> Result<String, Error> {
+        String::from_utf8(bytes.into()).map_err(|e| Error::Decode(e.to_string()))
+    }
+}
+
+/// Stores byte strings as they are. Decoding never fails, and reading
+/// `InlineArray`s doesn't copy them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Raw;
+
+impl Codec<Vec<u8>> for Raw {
+    fn encode(value: &Vec<u8>) -> Result<InlineArray, Error> {
+        Ok(value.as_slice().into())
+    }
+
+    fn decode(bytes: InlineArray) -> Result<Vec<u8>, Error> {
+        Ok(bytes.into())
+    }
+}
+
+impl Codec<InlineArray> for Raw {
+    fn encode(value: &InlineArray) -> Result<InlineArray, Error> {
+        Ok(value.clone())
+    }
+
+    fn decode(bytes: InlineArray) -> Result<InlineArray, Error> {
+        Ok(bytes)
+    }
+}
+
+/// Stores tuples and single elements in the order-preserving encoding of
+/// `tuple`, so ranges over composite keys work.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tuple;
+
+impl<T: Pack> Codec<T> for Tuple {
+    fn encode(value: &T) -> Result<InlineArray, Error> {
+        Ok(tuple::pack(value).into())
+    }
+
+    fn decode(bytes: InlineArray) -> Result<T, Error> {
+        tuple::unpack(&bytes)
+    }
+}
@@ -1 +1,549 @@
+//! `Aymr config`
+//!
+//! Every backend is opened from a config implementing `AymrConfig`. The
+//! configs of the backends `AymrDb` can pick at runtime are gathered in
+//! `BackendConfig`.
+//!
+//! `AymrDbBuilder` puts a `BackendConfig` together, either fluently or from a
+//! TOML file and `AYMR_*` environment variables. Reading TOML needs the
+//! `config-toml` feature. A TOML file uses the same names as the builder
+//! methods:
+//!
+//! ```toml
+//! backend = "sled"
+//! path = "/var/lib/aymr"
+//! cache_capacity = 1073741824
+//! durability = "immediate"
+//! ```
 
+use std::{
+    env,
+    path::PathBuf,
+    str::FromStr,
+};
+#[cfg(feature = "config-toml")]
+use std::{
+    fs,
+    path::Path,
+};
+
+#[cfg(feature = "config-toml")]
+use serde::Deserialize;
+
+use crate::aymr_db::{
+    backend::BackendKind,
+    db::AymrDb,
+    error::Error,
+    traits::AymrConfig,
+};
+
+/// When writes of an on-disk backend reach the disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "config-toml", derive(Deserialize))]
+#[cfg_attr(feature = "config-toml", serde(rename_all = "lowercase"))]
+pub enum Durability {
+    /// Writes are buffered, and reach the disk on `flush` or in the background.
+    #[default]
+    Buffered,
+    /// Every write reaches the disk before it returns.
+    Immediate,
+}
+
+impl FromStr for Durability {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Error> {
+        match name {
+            "buffered" => Ok(Durability::Buffered),
+            "immediate" => Ok(Durability::Immediate),
+            _ => Err(Error::Config(format!("unknown durability `{}`", name))),
+        }
+    }
+}
+
+/// Config of the `btreemap` backend, which has no settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BtreeMapConfig {}
+
+/// Config of the `hashmap` backend.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashMapConfig {
+    /// Number of entries expected. The map grows a node at a time, so this is
+    /// only a hint and nothing gets allocated up front.
+    pub capacity: usize,
+}
+
+/// Config of the `sled` and `sled_alpha` backends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SledConfig {
+    /// Directory holding the database. Only temporary databases can go without.
+    pub path: Option<PathBuf>,
+    /// Size of the page cache in bytes, or sled's default if `None`.
+    pub cache_capacity: Option<u64>,
+    pub durability: Durability,
+    /// Deletes the database once it is dropped.
+    pub temporary: bool,
+}
+
+impl SledConfig {
+    /// Returns the default config for a database at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        SledConfig {
+            path: Some(path.into()),
+            cache_capacity: None,
+            durability: Durability::default(),
+            temporary: false,
+        }
+    }
+}
+
+/// Config of the `redb` backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedbConfig {
+    /// File holding the database. Only temporary databases can go without.
+    pub path: Option<PathBuf>,
+    /// Size of the page cache in bytes, or redb's default if `None`.
+    pub cache_capacity: Option<u64>,
+    pub durability: Durability,
+    /// Keeps the database in memory, so it is gone once dropped.
+    pub temporary: bool,
+}
+
+impl RedbConfig {
+    /// Returns the default config for a database at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        RedbConfig {
+            path: Some(path.into()),
+            cache_capacity: None,
+            durability: Durability::default(),
+            temporary: false,
+        }
+    }
+}
+
+/// Config of the `fjall` backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FjallConfig {
+    /// Directory holding the keyspace. Only temporary databases can go
+    /// without.
+    pub path: Option<PathBuf>,
+    /// Size of the block cache in bytes, or fjall's default if `None`.
+    pub cache_capacity: Option<u64>,
+    pub durability: Durability,
+    /// Removes the keyspace once the database is dropped.
+    pub temporary: bool,
+}
+
+impl FjallConfig {
+    /// Returns the default config for a keyspace at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FjallConfig {
+            path: Some(path.into()),
+            cache_capacity: None,
+            durability: Durability::default(),
+            temporary: false,
+        }
+    }
+}
+
+/// Config of the `lmdb` backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LmdbConfig {
+    /// Directory holding the environment. Only temporary databases can go
+    /// without.
+    pub path: Option<PathBuf>,
+    /// Size of the memory map in bytes, which caps the size of the database,
+    /// or LMDB's default if `None`. Writes fail with `Error::MapFull` once
+    /// it is reached.
+    pub map_size: Option<usize>,
+    /// Most trees the database can hold, besides the default one.
+    pub max_trees: u32,
+    pub durability: Durability,
+    /// Removes the environment once the database is dropped.
+    pub temporary: bool,
+}
+
+impl LmdbConfig {
+    /// Returns the default config for an environment at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        LmdbConfig {
+            path: Some(path.into()),
+            ..LmdbConfig::default()
+        }
+    }
+}
+
+/// The default config has no path, so only a temporary database can be
+/// opened with it.
+impl Default for LmdbConfig {
+    fn default() -> Self {
+        LmdbConfig {
+            path: None,
+            map_size: None,
+            max_trees: 126,
+            durability: Durability::default(),
+            temporary: false,
+        }
+    }
+}
+
+impl AymrConfig for BtreeMapConfig {}
+
+impl AymrConfig for HashMapConfig {}
+
+impl AymrConfig for SledConfig {}
+
+impl AymrConfig for RedbConfig {}
+
+impl AymrConfig for FjallConfig {}
+
+impl AymrConfig for LmdbConfig {}
+
+/// Config of any backend `AymrDb` can pick at runtime.
+///
+/// Every backend Aymr knows about has a variant, whether or not it is
+/// compiled in. Opening one that isn't fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendConfig {
+    BtreeMap(BtreeMapConfig),
+    HashMap(HashMapConfig),
+    Sled(SledConfig),
+    SledAlpha(SledConfig),
+    Redb(RedbConfig),
+    Fjall(FjallConfig),
+    Lmdb(LmdbConfig),
+}
+
+impl BackendConfig {
+    /// Returns the kind of backend this config is for.
+    pub fn kind(&self) -> BackendKind {
+        match self {
+            BackendConfig::BtreeMap(_) => BackendKind::BtreeMap,
+            BackendConfig::HashMap(_) => BackendKind::HashMap,
+            BackendConfig::Sled(_) => BackendKind::Sled,
+            BackendConfig::SledAlpha(_) => BackendKind::SledAlpha,
+            BackendConfig::Redb(_) => BackendKind::Redb,
+            BackendConfig::Fjall(_) => BackendKind::Fjall,
+            BackendConfig::Lmdb(_) => BackendKind::Lmdb,
+        }
+    }
+}
+
+impl AymrConfig for BackendConfig {}
+
+/// The default config of `BackendKind::default`.
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendKind::default().into()
+    }
+}
+
+/// The default config of a backend. On-disk backends get no path, so opening
+/// them fails.
+impl From<BackendKind> for BackendConfig {
+    fn from(kind: BackendKind) -> Self {
+        match kind {
+            BackendKind::BtreeMap => BackendConfig::BtreeMap(BtreeMapConfig::default()),
+            BackendKind::HashMap => BackendConfig::HashMap(HashMapConfig::default()),
+            BackendKind::Sled | BackendKind::SledAlpha => {
+                let config = SledConfig {
+                    path: None,
+                    cache_capacity: None,
+                    durability: Durability::default(),
+                    temporary: false,
+                };
+                match kind {
+                    BackendKind::Sled => BackendConfig::Sled(config),
+                    _ => BackendConfig::SledAlpha(config),
+                }
+            }
+            BackendKind::Redb => {
+                BackendConfig::Redb(RedbConfig {
+                    path: None,
+                    cache_capacity: None,
+                    durability: Durability::default(),
+                    temporary: false,
+                })
+            }
+            BackendKind::Fjall => {
+                BackendConfig::Fjall(FjallConfig {
+                    path: None,
+                    cache_capacity: None,
+                    durability: Durability::default(),
+                    temporary: false,
+                })
+            }
+            BackendKind::Lmdb => BackendConfig::Lmdb(LmdbConfig::default()),
+        }
+    }
+}
+
+impl From<BtreeMapConfig> for BackendConfig {
+    fn from(config: BtreeMapConfig) -> Self {
+        BackendConfig::BtreeMap(config)
+    }
+}
+
+impl From<HashMapConfig> for BackendConfig {
+    fn from(config: HashMapConfig) -> Self {
+        BackendConfig::HashMap(config)
+    }
+}
+
+/// A `SledConfig` on its own is for sled 0.34, `BackendConfig::SledAlpha`
+/// takes the same config for sled 1.0.
+impl From<SledConfig> for BackendConfig {
+    fn from(config: SledConfig) -> Self {
+        BackendConfig::Sled(config)
+    }
+}
+
+impl From<RedbConfig> for BackendConfig {
+    fn from(config: RedbConfig) -> Self {
+        BackendConfig::Redb(config)
+    }
+}
+
+impl From<FjallConfig> for BackendConfig {
+    fn from(config: FjallConfig) -> Self {
+        BackendConfig::Fjall(config)
+    }
+}
+
+impl From<LmdbConfig> for BackendConfig {
+    fn from(config: LmdbConfig) -> Self {
+        BackendConfig::Lmdb(config)
+    }
+}
+
+/// Puts together a `BackendConfig`, and opens an `AymrDb` with it.
+///
+/// Settings that aren't given fall back to the defaults of the chosen backend.
+/// Settings the chosen backend doesn't have are rejected by `build`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "config-toml", derive(Deserialize))]
+#[cfg_attr(feature = "config-toml", serde(deny_unknown_fields))]
+pub struct AymrDbBuilder {
+    backend: Option<BackendKind>,
+    path: Option<PathBuf>,
+    cache_capacity: Option<u64>,
+    map_size: Option<usize>,
+    durability: Option<Durability>,
+    temporary: Option<bool>,
+    capacity: Option<usize>,
+}
+
+impl AymrDbBuilder {
+    /// Returns a builder with no settings.
+    pub fn new() -> Self {
+        AymrDbBuilder::default()
+    }
+
+    /// Reads settings from a TOML file.
+    #[cfg(feature = "config-toml")]
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Reads settings from a TOML string.
+    #[cfg(feature = "config-toml")]
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        toml::from_str(toml).map_err(|e| Error::Config(e.message().to_string()))
+    }
+
+    /// Reads settings from the environment, see `with_env`.
+    pub fn from_env() -> Result<Self, Error> {
+        Self::new().with_env()
+    }
+
+    /// Overrides settings with the ones set in the environment.
+    ///
+    /// Every setting is read from the variable of the same name, upper cased
+    /// and prefixed with `AYMR_`, like `AYMR_CACHE_CAPACITY`. Unknown `AYMR_*`
+    /// variables are rejected.
+    pub fn with_env(self) -> Result<Self, Error> {
+        self.with_vars(
+            env::vars_os().filter_map(|(key, value)| {
+                Some((key.into_string().ok()?, value.into_string().ok()?))
+            }),
+        )
+    }
+
+    pub(crate) fn with_vars<I>(mut self, vars: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (key, value) in vars {
+            let Some(setting) = key.strip_prefix("AYMR_") else {
+                continue;
+            };
+            match setting {
+                "BACKEND" => self.backend = Some(parse_var(&key, &value)?),
+                "PATH" => self.path = Some(value.into()),
+                "CACHE_CAPACITY" => self.cache_capacity = Some(parse_var(&key, &value)?),
+                "MAP_SIZE" => self.map_size = Some(parse_var(&key, &value)?),
+                "DURABILITY" => self.durability = Some(parse_var(&key, &value)?),
+                "TEMPORARY" => self.temporary = Some(parse_var(&key, &value)?),
+                "CAPACITY" => self.capacity = Some(parse_var(&key, &value)?),
+                _ => return Err(Error::Config(format!("unknown setting `{}`", key))),
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Picks the backend, `BackendKind::default` if not set.
+    pub fn backend(mut self, kind: BackendKind) -> Self {
+        self.backend = Some(kind);
+        self
+    }
+
+    /// Sets where an on-disk backend keeps its data.
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Sets the size of the cache of an on-disk backend, in bytes.
+    pub fn cache_capacity(mut self, bytes: u64) -> Self {
+        self.cache_capacity = Some(bytes);
+        self
+    }
+
+    /// Sets the size of the memory map of the `lmdb` backend, in bytes.
+    pub fn map_size(mut self, bytes: usize) -> Self {
+        self.map_size = Some(bytes);
+        self
+    }
+
+    /// Sets when the writes of an on-disk backend reach the disk.
+    pub fn durability(mut self, durability: Durability) -> Self {
+        self.durability = Some(durability);
+        self
+    }
+
+    /// Makes an on-disk backend delete its data once dropped.
+    pub fn temporary(mut self, temporary: bool) -> Self {
+        self.temporary = Some(temporary);
+        self
+    }
+
+    /// Sets the number of entries the `hashmap` backend allocates room for.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Puts the settings together into the config of the chosen backend.
+    pub fn build(&self) -> Result<BackendConfig, Error> {
+        let kind = self.backend.unwrap_or_default();
+
+        if let (true, Some(path)) = (kind.is_in_memory(), &self.path) {
+            return Err(Error::Unsupported(format!(
+                "`{}` is an in-memory backend and can't be opened at `{}`",
+                kind,
+                path.display()
+            )));
+        }
+
+        let settings = [
+            (
+                "cache_capacity",
+                self.cache_capacity.is_some(),
+                !kind.is_in_memory() && kind != BackendKind::Lmdb,
+            ),
+            (
+                "map_size",
+                self.map_size.is_some(),
+                kind == BackendKind::Lmdb,
+            ),
+            (
+                "durability",
+                self.durability.is_some(),
+                !kind.is_in_memory(),
+            ),
+            ("temporary", self.temporary.is_some(), !kind.is_in_memory()),
+            (
+                "capacity",
+                self.capacity.is_some(),
+                kind == BackendKind::HashMap,
+            ),
+        ];
+        if let Some((name, ..)) = settings.iter().find(|(_, set, applies)| *set && !applies) {
+            return Err(Error::Config(format!(
+                "`{}` does not apply to the `{}` backend",
+                name, kind
+            )));
+        }
+
+        let temporary = self.temporary.unwrap_or_default();
+        if !kind.is_in_memory() && self.path.is_none() && !temporary {
+            return Err(Error::Config(format!(
+                "the `{}` backend needs a `path` unless it is `temporary`",
+                kind
+            )));
+        }
+
+        match kind {
+            BackendKind::BtreeMap => Ok(BtreeMapConfig {}.into()),
+            BackendKind::HashMap => {
+                Ok(HashMapConfig {
+                    capacity: self.capacity.unwrap_or_default(),
+                }
+                .into())
+            }
+            BackendKind::Sled | BackendKind::SledAlpha => {
+                let config = SledConfig {
+                    path: self.path.clone(),
+                    cache_capacity: self.cache_capacity,
+                    durability: self.durability.unwrap_or_default(),
+                    temporary,
+                };
+                match kind {
+                    BackendKind::Sled => Ok(BackendConfig::Sled(config)),
+                    _ => Ok(BackendConfig::SledAlpha(config)),
+                }
+            }
+            BackendKind::Redb => {
+                Ok(RedbConfig {
+                    path: self.path.clone(),
+                    cache_capacity: self.cache_capacity,
+                    durability: self.durability.unwrap_or_default(),
+                    temporary,
+                }
+                .into())
+            }
+            BackendKind::Fjall => {
+                Ok(FjallConfig {
+                    path: self.path.clone(),
+                    cache_capacity: self.cache_capacity,
+                    durability: self.durability.unwrap_or_default(),
+                    temporary,
+                }
+                .into())
+            }
+            BackendKind::Lmdb => {
+                Ok(LmdbConfig {
+                    path: self.path.clone(),
+                    map_size: self.map_size,
+                    durability: self.durability.unwrap_or_default(),
+                    temporary,
+                    ..LmdbConfig::default()
+                }
+                .into())
+            }
+        }
+    }
+
+    /// Builds the config and opens a database with it.
+    pub fn open<K, V>(&self) -> Result<AymrDb<K, V>, Error> {
+        AymrDb::open(self.build()?)
+    }
+}
+
+/// Parses the value of the environment variable `key`.
+fn parse_var<T: FromStr>(key: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(format!("`{}` can't be set to `{}`", key, value)))
+}
@@ -2,9 +2,8 @@
 //!
 //! The `AymrDb` type is a wrapper used to access databases that implement
 //! the Aymr set of traits. Opening is achieved via calling the open fn which
-//! will return a referance to the Db. Due to different databases having different
-//! ways of being opened, this is managed by feature flags that correspond with your
-//! desired database.
+//! will return a referance to the Db. Feature flags decide which backends get
+//! compiled in, and `open_with` or `open_url` pick one of them at runtime.
 //!
 //! The database cannot be dereferanced and may not be `Sync`. Aymr being `Sync`
 //! depends on if the underlying database that's used is `Sync`. If you require
@@ -15,63 +14,171 @@
 //! Aymr does not offer any additional data safety or guarantees not provided by
 //! the underlying database.
 
-#[cfg(feature = "btreemap")]
-use crate::backends::btreemap::db::AymrBtreeMap;
-
-#[cfg(feature = "hashmap")]
-use crate::backends::hashmap::db::AymrHashMap;
+use std::{
+    marker::PhantomData,
+    ops::RangeBounds,
+    time::Duration,
+};
 
 use super::{
-    error::Error,
+    backend::{
+        AymrBackend,
+        BackendKind,
+    },
+    config::{
+        AymrDbBuilder,
+        BackendConfig,
+    },
+    error::{
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::Iter,
+    merge::MergeOperator,
     traits::{
+        AymrBatch,
         AymrDatabase,
+        AymrFlush,
         AymrOpenable,
+        AymrSnapshot,
+        AymrTransactional,
+        AymrTrees,
         InlineArray,
     },
+    transaction::{
+        ConflictableTransactionResult,
+        Transaction,
+        TransactionResult,
+    },
+    watch::Subscriber,
 };
 
+/// Wrapper around a backend `B`, which defaults to picking one at runtime.
+///
+/// Backends store bytes, `K` and `V` only set the types the database is
+/// written with. Reads hand out the stored `InlineArray`s without copying.
+/// Snapshots and trees are wrapped as well, so they keep the same types.
+///
+/// Naming a concrete backend, like `AymrDb<K, V, AymrBtreeMap>`, gets rid
+/// of the runtime dispatch. Databases with different backends can be used side
+/// by side either way.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct AymrDb<K, V>
-where
-    K: Eq + std::hash::Hash,
-{
-    #[cfg(feature = "btreemap")]
-    db: AymrBtreeMap<K, V>,
+pub struct AymrDb<K, V, B = AymrBackend> {
+    db: B,
+    _types: PhantomData<fn() -> (K, V)>,
+}
 
-    #[cfg(feature = "hashmap")]
-    db: AymrHashMap<K, V>,
+impl<K, V, B> AymrDb<K, V, B> {
+    /// Wraps an already opened backend.
+    pub fn from_backend(db: B) -> Self {
+        AymrDb {
+            db,
+            _types: PhantomData,
+        }
+    }
+
+    /// Returns the backend in use.
+    pub fn backend(&self) -> &B {
+        &self.db
+    }
+
+    /// Unwraps the backend in use.
+    pub fn into_backend(self) -> B {
+        self.db
+    }
 }
 
-impl<K, V> AymrDb<K, V>
-where
-    K: Eq + std::hash::Hash,
-{
-    #[cfg(feature = "btreemap")]
-    #[allow(dead_code)]
-    pub fn open() -> Self {
-        let rax = AymrDb {
-            db: AymrBtreeMap::open(),
-        };
+/// Opens a concrete backend, e.g.
+/// `<AymrDb<K, V, AymrHashMap>>::open(HashMapConfig::default())`.
+impl<K, V, B: AymrOpenable> AymrOpenable for AymrDb<K, V, B> {
+    type Config = B::Config;
 
-        rax
+    fn open(config: B::Config) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(B::open(config)?))
     }
 
-    #[cfg(feature = "hashmap")]
-    #[allow(dead_code)]
-    pub fn open() -> Self {
-        let rax = AymrDb {
-            db: AymrHashMap::open(),
-        };
+    fn create_new(config: B::Config) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(B::create_new(config)?))
+    }
+
+    fn open_existing(config: B::Config) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(B::open_existing(config)?))
+    }
+
+    fn temporary() -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(B::temporary()?))
+    }
 
-        rax
+    fn close(self) -> Result<(), Error> {
+        self.db.close()
     }
 }
 
-impl<K, V> AymrDatabase<K, V> for AymrDb<K, V>
+impl<K, V> AymrDb<K, V> {
+    /// Opens a backend from its config, failing if it is not compiled in.
+    ///
+    /// `BackendConfig::default()` opens the default backend, see
+    /// `BackendKind::default`.
+    pub fn open(config: impl Into<BackendConfig>) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(AymrBackend::open(config.into())?))
+    }
+
+    /// Creates a new database, failing with `io::ErrorKind::AlreadyExists`
+    /// if there already is one.
+    pub fn create_new(config: impl Into<BackendConfig>) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(AymrBackend::create_new(
+            config.into(),
+        )?))
+    }
+
+    /// Opens an existing database, failing with `io::ErrorKind::NotFound`
+    /// if there is none. Databases of in-memory backends never exist before
+    /// being opened.
+    pub fn open_existing(config: impl Into<BackendConfig>) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(AymrBackend::open_existing(
+            config.into(),
+        )?))
+    }
+
+    /// Opens a temporary database of the default backend, deleted once dropped.
+    pub fn temporary() -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(AymrBackend::temporary()?))
+    }
+
+    /// Flushes the database and releases its resources.
+    pub fn close(self) -> Result<(), Error> {
+        self.db.close()
+    }
+
+    /// Returns a builder to put the config together with.
+    pub fn builder() -> AymrDbBuilder {
+        AymrDbBuilder::new()
+    }
+
+    /// Opens a backend of the given kind with its default config.
+    pub fn open_with(kind: BackendKind) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(AymrBackend::open_with(kind)?))
+    }
+
+    /// Opens the backend named by a URL such as `btreemap://` or `sled:///var/db`.
+    pub fn open_url(url: &str) -> Result<Self, Error> {
+        Ok(AymrDb::from_backend(AymrBackend::open_url(url)?))
+    }
+
+    /// Returns the kind of the backend in use.
+    pub fn kind(&self) -> BackendKind {
+        self.db.kind()
+    }
+}
+
+impl<K, V, B> AymrDatabase<K, V> for AymrDb<K, V, B>
 where
-    K: AsRef<[u8]> + Ord + Eq + std::hash::Hash,
+    K: AsRef<[u8]>,
     V: AsRef<[u8]>,
+    B: AymrDatabase<K, V>,
 {
+    type Snapshot = AymrDb<K, V, B::Snapshot>;
+
     fn clear(&mut self) -> Result<(), Error> {
         self.db.clear()
     }
@@ -84,7 +191,7 @@ where
         self.db.is_empty()
     }
 
-    fn get(&self, key: &K) -> Result<Option<InlineArray>, Error> {
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
         self.db.get(key)
     }
 
@@ -92,96 +199,1475 @@ where
         self.db.insert(key, value)
     }
 
-    fn remove(&mut self, key: K) -> Result<Option<InlineArray>, Error> {
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
         self.db.remove(key)
     }
 
-    fn apply_batch<B: super::traits::Batch>(&self, batch: B) -> Result<(), Error> {
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        self.db.insert_with_ttl(key, value, ttl)
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        self.db.ttl(key)
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        self.db.purge_expired()
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.db.compare_and_swap(key, old, new)
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.db.set_merge_operator(merge_operator)
+    }
+
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        self.db.merge(key, operand)
+    }
+
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
         self.db.apply_batch(batch)
     }
 
-    fn contains_key(&self, key: &K) -> Result<bool, Error> {
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        self.db.contains_key(key)
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.db.iter()
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.db.range(range)
+    }
+
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        self.db.scan_prefix(prefix)
+    }
+
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        self.db.remove_prefix(prefix)
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.db.get_lt(key)
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.db.get_gt(key)
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.db.first()
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.db.last()
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.db.pop_min()
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.db.pop_max()
+    }
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        Ok(AymrDb::from_backend(self.db.snapshot()?))
+    }
+
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        self.db.watch_prefix(prefix)
+    }
+}
+
+impl<K, V, S> AymrSnapshot<K> for AymrDb<K, V, S>
+where
+    K: AsRef<[u8]>,
+    S: AymrSnapshot<K>,
+{
+    fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        self.db.is_empty()
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.db.get(key)
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
         self.db.contains_key(key)
     }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.db.iter()
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.db.range(range)
+    }
+}
+
+/// Runs on the backend's own transactions, so commits are as atomic as the
+/// backend makes them, even against clones of this database.
+impl<K, V, B> AymrTransactional<K, V> for AymrDb<K, V, B>
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+    B: AymrTransactional<K, V>,
+{
+    fn transaction<F, A, E>(&mut self, mut f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        self.db.transaction(|tx| {
+            let mut rebound = tx.rebind();
+            let res = f(&mut rebound);
+            tx.restore(rebound);
+            res
+        })
+    }
+}
+
+impl<K, V, B> AymrTrees<K, V> for AymrDb<K, V, B>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+    B: AymrTrees<K, V>,
+{
+    type Tree = AymrDb<K, V, B::Tree>;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        Ok(AymrDb::from_backend(self.db.open_tree(name)?))
+    }
+
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        self.db.drop_tree(name)
+    }
+
+    fn tree_names(&self) -> Vec<InlineArray> {
+        self.db.tree_names()
+    }
+}
+
+impl<K, V, B: AymrFlush> AymrFlush for AymrDb<K, V, B> {
+    fn flush(&self) -> Result<(), Error> {
+        self.db.flush()
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use std::ops::Bound;
+
     use super::*;
+    use crate::aymr_db::{
+        config::{
+            Durability,
+            FjallConfig,
+            HashMapConfig,
+            LmdbConfig,
+            RedbConfig,
+            SledConfig,
+        },
+        error::CompareAndSwapError,
+        traits::Batch,
+        transaction::{
+            ConflictableTransactionError,
+            TransactionError,
+        },
+    };
+
+    /// The backend of tests that don't depend on one: the first one compiled
+    /// in, which is in memory whenever one of those is.
+    pub(crate) fn test_kind() -> BackendKind {
+        BackendKind::available()[0]
+    }
+
+    /// Config of a throwaway DB of `kind`, in a temporary directory if it is
+    /// kept on disk.
+    pub(crate) fn test_config(kind: BackendKind) -> BackendConfig {
+        let builder = AymrDbBuilder::new().backend(kind);
+        if kind.is_in_memory() {
+            builder.build().unwrap()
+        } else {
+            builder.temporary(true).build().unwrap()
+        }
+    }
 
     // Helper function to create an instance of AymrDb for testing
-    fn create_test_db<K: AsRef<[u8]> + Ord + std::hash::Hash, V: AsRef<[u8]>>() -> AymrDb<K, V> {
-        AymrDb {
-            #[cfg(feature = "btreemap")]
-            db: AymrBtreeMap::open(),
+    pub(crate) fn create_test_db<K, V>() -> AymrDb<K, V> {
+        AymrDb::open(test_config(test_kind())).unwrap()
+    }
+
+    #[test]
+    fn test_iter_surfaces_read_errors() {
+        let entries = vec![
+            Ok((InlineArray::from("a"), InlineArray::from("1"))),
+            Err(Error::corruption(None)),
+            Ok((InlineArray::from("c"), InlineArray::from("3"))),
+        ];
+
+        let res: Result<Vec<_>, _> = Iter::fallible(entries.into_iter()).collect();
+        assert!(matches!(res, Err(Error::Corruption { .. })));
+    }
+
+    fn concatenate(_key: &[u8], old: Option<&[u8]>, operand: &[u8]) -> Option<Vec<u8>> {
+        let mut value = old.map(|v| v.to_vec()).unwrap_or_default();
+        value.extend_from_slice(operand);
+        Some(value)
+    }
+
+    /// Generates a module of tests for each backend, named after it and only
+    /// compiled along with it. Every backend runs the `core` tests, and lists
+    /// the groups of tests for the operations it supports, along with `no_`
+    /// groups checking the ones it doesn't fail with `Error::Unsupported`.
+    macro_rules! backend_tests {
+        ($($backend:ident: $kind:expr, $feature:literal, [$($group:ident),*];)*) => {
+            $(
+                #[cfg(feature = $feature)]
+                mod $backend {
+                    use super::*;
+
+                    const KIND: BackendKind = $kind;
+
+                    fn create_test_db<K, V>() -> AymrDb<K, V> {
+                        let db = AymrDb::open(test_config(KIND)).unwrap();
+                        assert_eq!(db.kind(), KIND);
+                        db
+                    }
+
+                    backend_tests!(@core);
+                    $(backend_tests!(@$group);)*
+                }
+            )*
+        };
+        (@core) => {
+            #[test]
+            fn test_clear() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                assert!(db.clear().is_ok());
+            }
+
+            #[test]
+            fn test_len() {
+                let db = create_test_db::<Vec<u8>, Vec<u8>>();
+                assert_eq!(db.len(), 0);
+            }
+
+            #[test]
+            fn test_is_empty() {
+                let db = create_test_db::<Vec<u8>, Vec<u8>>();
+                assert!(db.is_empty().unwrap());
+            }
+
+            #[test]
+            fn test_get() {
+                let db = create_test_db::<Vec<u8>, Vec<u8>>();
+                assert!(db.get(&vec![1, 2, 3]).unwrap().is_none());
+            }
+
+            #[test]
+            fn test_insert_and_get() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1, 2, 3];
+                let value = vec![4, 5, 6];
+                assert!(db.insert(key.clone(), value).is_ok());
+                assert!(db.get(&key).unwrap().is_some());
+            }
+
+            #[test]
+            fn test_remove() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1, 2, 3];
+                let value = vec![4, 5, 6];
+                db.insert(key.clone(), value).unwrap();
+                assert_eq!(db.len(), 1);
+                assert!(db.remove(&key).unwrap().is_some());
+                assert_eq!(db.len(), 0);
+            }
+
+            #[test]
+            fn test_borrowed_keys() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert("key".into(), vec![1]).unwrap();
+
+                assert_eq!(db.get(&b"key"[..]).unwrap(), Some([1].into()));
+                assert!(db.contains_key("key").unwrap());
+                assert!(!db.contains_key(&[0u8]).unwrap());
+                assert_eq!(db.remove("key").unwrap(), Some([1].into()));
+                assert!(db.is_empty().unwrap());
+            }
+
+            #[test]
+            fn test_contains_key() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1, 2, 3];
+                let value = vec![4, 5, 6];
+                db.insert(key.clone(), value).unwrap();
+                assert!(db.contains_key(&key).unwrap());
+            }
+
+            #[test]
+            fn test_clear_after_insert() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+
+                let _ = db.insert("key".into(), "value".into());
+                assert_eq!(db.len(), 1);
+
+                assert!(db.clear().is_ok());
+                assert_eq!(db.len(), 0);
+                assert!(db.is_empty().unwrap());
+            }
+
+            #[test]
+            fn test_apply_batch() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![1], vec![1]).unwrap();
+                db.insert(vec![2], vec![2]).unwrap();
+
+                let mut batch = AymrBatch::new();
+                batch.remove(vec![1]);
+                batch.insert(vec![2], vec![20]);
+                batch.insert(vec![3], vec![30]);
+                db.apply_batch(batch).unwrap();
+
+                assert_eq!(db.len(), 2);
+                assert!(db.get(&vec![1]).unwrap().is_none());
+                assert_eq!(db.get(&vec![2]).unwrap(), Some(vec![20].into()));
+                assert_eq!(db.get(&vec![3]).unwrap(), Some(vec![30].into()));
+            }
+
+            #[test]
+            fn test_apply_batch_clear_keeps_later_ops() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![1], vec![1]).unwrap();
+
+                let mut batch = AymrBatch::new();
+                batch.insert(vec![2], vec![2]);
+                batch.clear();
+                batch.insert(vec![3], vec![3]);
+                db.apply_batch(batch).unwrap();
+
+                assert_eq!(db.len(), 1);
+                assert!(db.contains_key(&vec![3]).unwrap());
+            }
+
+            #[test]
+            fn test_apply_batch_keeps_last_op_per_key() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+
+                let mut batch = AymrBatch::new();
+                batch.insert(vec![1], vec![1]);
+                batch.remove(vec![1]);
+                batch.insert(vec![1], vec![2]);
+                batch.insert(vec![2], vec![2]);
+                batch.remove(vec![2]);
+                db.apply_batch(batch).unwrap();
 
-            #[cfg(feature = "hashmap")]
-            db: AymrHashMap::open(),
+                assert_eq!(db.get(&vec![1]).unwrap(), Some(vec![2].into()));
+                assert!(!db.contains_key(&vec![2]).unwrap());
+            }
+
+            #[test]
+            fn test_iter_is_ordered() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                for k in [3u8, 1, 2] {
+                    db.insert(vec![k], vec![k * 10]).unwrap();
+                }
+
+                let keys: Vec<_> = db
+                    .iter()
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .map(|(k, _)| k)
+                    .collect();
+                assert_eq!(keys, vec![vec![1], vec![2], vec![3]]);
+
+                let (last_key, last_value) = db.iter().unwrap().next_back().unwrap().unwrap();
+                assert_eq!((last_key, last_value), (vec![3].into(), vec![30].into()));
+            }
+
+            #[test]
+            fn test_iter_from_both_ends() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                for k in [3u8, 1, 5, 2, 4] {
+                    db.insert(vec![k], vec![k]).unwrap();
+                }
+
+                // Both ends meet in the middle without yielding anything twice.
+                let mut iter = db.iter().unwrap();
+                let mut keys = Vec::new();
+                while let Some(entry) = iter.next() {
+                    keys.push(entry.unwrap().0);
+                    if let Some(entry) = iter.next_back() {
+                        keys.push(entry.unwrap().0);
+                    }
+                }
+                assert_eq!(keys, vec![vec![1], vec![5], vec![2], vec![4], vec![3]]);
+                assert!(iter.next().is_none());
+                assert!(iter.next_back().is_none());
+            }
+
+            #[test]
+            fn test_range() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                for k in 0u8..10 {
+                    db.insert(vec![k], vec![k]).unwrap();
+                }
+
+                let keys: Vec<_> = db
+                    .range(vec![3u8]..vec![6u8])
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .map(|(k, _)| k[0])
+                    .collect();
+                assert_eq!(keys, vec![3, 4, 5]);
+
+                let keys: Vec<_> = db
+                    .range::<[u8], _>((Bound::Excluded(&[7u8][..]), Bound::Unbounded))
+                    .unwrap()
+                    .rev()
+                    .map(Result::unwrap)
+                    .map(|(k, _)| k[0])
+                    .collect();
+                assert_eq!(keys, vec![9, 8]);
+
+                assert_eq!(db.range(vec![6u8]..vec![3u8]).unwrap().count(), 0);
+            }
+
+            #[test]
+            fn test_scan_prefix() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                for key in ["table/1/a", "table/1/b", "table/2/a", "tablet", "other"] {
+                    db.insert(key.into(), key.into()).unwrap();
+                }
+
+                let keys: Vec<_> = db
+                    .scan_prefix("table/1/")
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .map(|(k, _)| k)
+                    .collect();
+                assert_eq!(keys, vec![b"table/1/a".to_vec(), b"table/1/b".to_vec()]);
+
+                assert_eq!(db.scan_prefix("table/").unwrap().count(), 3);
+                assert_eq!(db.scan_prefix("").unwrap().count(), 5);
+                assert_eq!(db.scan_prefix("missing").unwrap().count(), 0);
+            }
+
+            #[test]
+            fn test_scan_prefix_max_bytes() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![0xff], vec![1]).unwrap();
+                db.insert(vec![0xff, 0xff, 0x01], vec![2]).unwrap();
+                db.insert(vec![0xfe, 0xff], vec![3]).unwrap();
+
+                assert_eq!(db.scan_prefix([0xff]).unwrap().count(), 2);
+                assert_eq!(db.scan_prefix([0xfe]).unwrap().count(), 1);
+            }
+
+            #[test]
+            fn test_remove_prefix() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                for key in ["table/1/a", "table/1/b", "table/2/a", "tablet"] {
+                    db.insert(key.into(), key.into()).unwrap();
+                }
+
+                assert_eq!(db.remove_prefix("table/1/").unwrap(), 2);
+                assert_eq!(db.len(), 2);
+                assert!(db.contains_key(&b"table/2/a".to_vec()).unwrap());
+                assert!(db.contains_key(&b"tablet".to_vec()).unwrap());
+                assert_eq!(db.remove_prefix("table/1/").unwrap(), 0);
+            }
+
+            #[test]
+            fn test_compare_and_swap() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1];
+
+                // Absent -> present.
+                assert_eq!(
+                    db.compare_and_swap(key.clone(), None::<Vec<u8>>, Some(vec![1])),
+                    Ok(Ok(()))
+                );
+                // Present -> updated.
+                assert_eq!(
+                    db.compare_and_swap(key.clone(), Some(vec![1]), Some(vec![2])),
+                    Ok(Ok(()))
+                );
+                assert_eq!(db.get(&key).unwrap(), Some(vec![2].into()));
+                // Present -> removed.
+                assert_eq!(
+                    db.compare_and_swap(key.clone(), Some(vec![2]), None),
+                    Ok(Ok(()))
+                );
+                assert!(!db.contains_key(&key).unwrap());
+            }
+
+            #[test]
+            fn test_compare_and_swap_mismatch() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1];
+                db.insert(key.clone(), vec![5]).unwrap();
+
+                let res = db.compare_and_swap(key.clone(), Some(vec![4]), Some(vec![6]));
+                assert_eq!(
+                    res,
+                    Ok(Err(CompareAndSwapError {
+                        current: Some(vec![5].into()),
+                        proposed: Some(vec![6].into()),
+                    }))
+                );
+
+                let res = db.compare_and_swap(key.clone(), None::<Vec<u8>>, Some(vec![6]));
+                assert!(res.unwrap().is_err());
+                assert_eq!(db.get(&key).unwrap(), Some(vec![5].into()));
+            }
+
+            #[test]
+            fn test_update_and_fetch() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1];
+                let increment = |old: Option<&[u8]>| {
+                    let n = old.map_or(0, |v| v[0]);
+                    Some(vec![n + 1])
+                };
+
+                assert_eq!(
+                    db.update_and_fetch(key.clone(), increment).unwrap(),
+                    Some(vec![1].into())
+                );
+                assert_eq!(
+                    db.update_and_fetch(key.clone(), increment).unwrap(),
+                    Some(vec![2].into())
+                );
+                assert_eq!(
+                    db.fetch_and_update(key.clone(), increment).unwrap(),
+                    Some(vec![2].into())
+                );
+                assert_eq!(db.get(&key).unwrap(), Some(vec![3].into()));
+            }
+
+            #[test]
+            fn test_update_and_fetch_none_removes() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1];
+                db.insert(key.clone(), vec![7]).unwrap();
+
+                assert_eq!(
+                    db.fetch_and_update(key.clone(), |_| None).unwrap(),
+                    Some(vec![7].into())
+                );
+                assert!(!db.contains_key(&key).unwrap());
+                assert_eq!(db.update_and_fetch(key.clone(), |_| None).unwrap(), None);
+                assert!(db.is_empty().unwrap());
+            }
+
+            #[test]
+            fn test_merge() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1];
+                db.set_merge_operator(concatenate);
+
+                assert_eq!(db.merge(key.clone(), [1]).unwrap(), None);
+                assert_eq!(db.merge(key.clone(), [2, 3]).unwrap(), Some(vec![1].into()));
+                assert_eq!(db.get(&key).unwrap(), Some(vec![1, 2, 3].into()));
+            }
+
+            #[test]
+            fn test_merge_none_removes() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let key = vec![1];
+                db.insert(key.clone(), vec![9]).unwrap();
+                db.set_merge_operator(|_: &[u8], _: Option<&[u8]>, _: &[u8]| None);
+
+                assert_eq!(db.merge(key.clone(), []).unwrap(), Some(vec![9].into()));
+                assert!(!db.contains_key(&key).unwrap());
+            }
+
+            #[test]
+            fn test_merge_without_operator() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                assert!(matches!(db.merge(vec![1], [1]), Err(Error::Unsupported(_))));
+                assert!(db.is_empty().unwrap());
+            }
+
+            #[test]
+            fn test_trees_are_separate_keyspaces() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let mut users = db.open_tree("users").unwrap();
+                let mut posts = db.open_tree("posts").unwrap();
+
+                db.insert(vec![1], vec![0]).unwrap();
+                users.insert(vec![1], vec![1]).unwrap();
+                posts.insert(vec![1], vec![2]).unwrap();
+
+                assert_eq!(db.get(&vec![1]).unwrap(), Some(vec![0].into()));
+                assert_eq!(users.get(&vec![1]).unwrap(), Some(vec![1].into()));
+                assert_eq!(posts.get(&vec![1]).unwrap(), Some(vec![2].into()));
+                assert_eq!(db.tree_names(), vec![b"posts".to_vec(), b"users".to_vec()]);
+
+                // Reopening a tree returns a handle to the same keyspace.
+                let users_again = db.open_tree("users").unwrap();
+                assert_eq!(users_again.get(&vec![1]).unwrap(), Some(vec![1].into()));
+            }
+
+            #[test]
+            fn test_tree_scans() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let mut tree = db.open_tree("scans").unwrap();
+                for k in [3u8, 1, 5, 2, 4] {
+                    tree.insert(vec![k], vec![k]).unwrap();
+                }
+                tree.insert(vec![9, 0], vec![9]).unwrap();
+
+                // Both ends meet in the middle without yielding anything twice.
+                let mut iter = tree.iter().unwrap();
+                let mut keys = Vec::new();
+                while let Some(entry) = iter.next() {
+                    keys.push(entry.unwrap().0);
+                    if let Some(entry) = iter.next_back() {
+                        keys.push(entry.unwrap().0);
+                    }
+                }
+                assert_eq!(
+                    keys,
+                    vec![vec![1], vec![9, 0], vec![2], vec![5], vec![3], vec![4]]
+                );
+
+                let keys: Vec<_> = tree
+                    .range(vec![2]..vec![4])
+                    .unwrap()
+                    .map(|entry| entry.unwrap().0)
+                    .collect();
+                assert_eq!(keys, vec![vec![2], vec![3]]);
+
+                let keys: Vec<_> = tree
+                    .scan_prefix([9])
+                    .unwrap()
+                    .map(|entry| entry.unwrap().0)
+                    .collect();
+                assert_eq!(keys, vec![vec![9, 0]]);
+            }
+
+            #[test]
+            fn test_drop_tree() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let mut users = db.open_tree("users").unwrap();
+                users.insert(vec![1], vec![1]).unwrap();
+
+                assert!(db.drop_tree("users").unwrap());
+                assert!(!db.drop_tree("users").unwrap());
+                assert!(db.tree_names().is_empty());
+
+                assert_eq!(
+                    users.get(&vec![1]),
+                    Err(Error::CollectionNotFound("users".to_string()))
+                );
+                assert_eq!(
+                    users.insert(vec![2], vec![2]),
+                    Err(Error::CollectionNotFound("users".to_string()))
+                );
+
+                // fjall can only open a dropped tree again once its handles are gone.
+                drop(users);
+                let users = db.open_tree("users").unwrap();
+                assert!(users.is_empty().unwrap());
+            }
+
+            #[test]
+            fn test_transaction() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let (alice, bob) = (b"alice".to_vec(), b"bob".to_vec());
+                db.insert(alice.clone(), vec![10]).unwrap();
+                db.insert(bob.clone(), vec![0]).unwrap();
+
+                let moved = db
+                    .transaction::<_, _, ()>(|tx| {
+                        let from = tx.get(&alice)?.unwrap()[0];
+                        let to = tx.get(&bob)?.unwrap()[0];
+                        tx.insert(alice.clone(), vec![from - 4])?;
+                        tx.insert(bob.clone(), vec![to + 4])?;
+
+                        // Reads see the transaction's own writes.
+                        assert_eq!(tx.get(&bob)?, Some(vec![4].into()));
+                        Ok(4)
+                    })
+                    .unwrap();
+
+                assert_eq!(moved, 4);
+                assert_eq!(db.get(&alice).unwrap(), Some(vec![6].into()));
+                assert_eq!(db.get(&bob).unwrap(), Some(vec![4].into()));
+            }
+
+            #[test]
+            fn test_transaction_abort() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![1], vec![1]).unwrap();
+
+                let res = db.transaction(|tx| {
+                    tx.remove(vec![1])?;
+                    tx.insert(vec![2], vec![2])?;
+                    Err::<(), _>(ConflictableTransactionError::Abort("nope"))
+                });
+
+                assert_eq!(res, Err(TransactionError::Abort("nope")));
+                assert_eq!(db.get(&vec![1]).unwrap(), Some(vec![1].into()));
+                assert!(!db.contains_key(&vec![2]).unwrap());
+            }
+
+            #[test]
+            fn test_transaction_retries_on_conflict() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let mut tree = db.open_tree("counters").unwrap();
+                let mut other = tree.clone();
+                tree.insert(vec![1], vec![0]).unwrap();
+
+                let mut attempts = 0;
+                tree.transaction::<_, _, ()>(|tx| {
+                    attempts += 1;
+                    let n = tx.get(&vec![1])?.unwrap()[0];
+                    if attempts == 1 {
+                        // A concurrent writer sneaks in after our read.
+                        other.insert(vec![1], vec![100]).unwrap();
+                    }
+                    tx.insert(vec![1], vec![n + 1])?;
+                    Ok(())
+                })
+                .unwrap();
+
+                assert_eq!(attempts, 2);
+                assert_eq!(tree.get(&vec![1]).unwrap(), Some(vec![101].into()));
+            }
+
+            #[test]
+            fn test_transactions_are_serializable_across_handles() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let tree = db.open_tree("counters").unwrap();
+
+                // Every increment reads and writes the same key, so any commit that
+                // isn't atomic loses some of them.
+                let threads: Vec<_> = (0..4)
+                    .map(|_| {
+                        let mut tree = tree.clone();
+                        std::thread::spawn(move || {
+                            for _ in 0..25 {
+                                tree.transaction::<_, _, ()>(|tx| {
+                                    let n = tx.get(&vec![1])?.map_or(0, |n| n[0]);
+                                    tx.insert(vec![1], vec![n + 1])?;
+                                    Ok(())
+                                })
+                                .unwrap();
+                            }
+                        })
+                    })
+                    .collect();
+                for thread in threads {
+                    thread.join().unwrap();
+                }
+
+                assert_eq!(tree.get(&vec![1]).unwrap(), Some(vec![100].into()));
+            }
+        };
+
+        (@ordered) => {
+            #[test]
+            fn test_neighbors() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                for k in [10u8, 20, 30] {
+                    db.insert(vec![k], vec![k]).unwrap();
+                }
+
+                assert_eq!(
+                    db.get_lt(&vec![20]).unwrap(),
+                    Some((vec![10].into(), vec![10].into()))
+                );
+                assert_eq!(
+                    db.get_lt(&vec![25]).unwrap(),
+                    Some((vec![20].into(), vec![20].into()))
+                );
+                assert_eq!(db.get_lt(&vec![10]).unwrap(), None);
+                assert_eq!(
+                    db.get_gt(&vec![20]).unwrap(),
+                    Some((vec![30].into(), vec![30].into()))
+                );
+                assert_eq!(db.get_gt(&vec![30]).unwrap(), None);
+                assert_eq!(
+                    db.first().unwrap(),
+                    Some((vec![10].into(), vec![10].into()))
+                );
+                assert_eq!(db.last().unwrap(), Some((vec![30].into(), vec![30].into())));
+            }
+
+            #[test]
+            fn test_pop_min_max() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                for k in [2u8, 1, 3] {
+                    db.insert(vec![k], vec![k]).unwrap();
+                }
+
+                assert_eq!(
+                    db.pop_min().unwrap(),
+                    Some((vec![1].into(), vec![1].into()))
+                );
+                assert_eq!(
+                    db.pop_max().unwrap(),
+                    Some((vec![3].into(), vec![3].into()))
+                );
+                assert_eq!(db.len(), 1);
+                assert_eq!(
+                    db.pop_max().unwrap(),
+                    Some((vec![2].into(), vec![2].into()))
+                );
+                assert_eq!(db.pop_min().unwrap(), None);
+            }
+        };
+
+        (@no_ordered) => {
+            #[test]
+            fn test_neighbors_unsupported() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![1], vec![1]).unwrap();
+
+                assert!(matches!(db.get_lt(&vec![2]), Err(Error::Unsupported(_))));
+                assert!(matches!(db.first(), Err(Error::Unsupported(_))));
+                assert!(matches!(db.pop_min(), Err(Error::Unsupported(_))));
+                assert_eq!(db.len(), 1);
+            }
+        };
+
+        (@snapshot) => {
+            #[test]
+            fn test_snapshot_is_isolated_from_writes() {
+                use crate::aymr_db::traits::AymrSnapshot;
+
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![1], vec![1]).unwrap();
+                db.insert(vec![2], vec![2]).unwrap();
+
+                let snapshot = db.snapshot().unwrap();
+                db.insert(vec![1], vec![10]).unwrap();
+                db.remove(&[2]).unwrap();
+                db.insert(vec![3], vec![3]).unwrap();
+
+                assert_eq!(snapshot.len(), 2);
+                assert_eq!(snapshot.get(&vec![1]).unwrap(), Some(vec![1].into()));
+                assert!(snapshot.contains_key(&vec![2]).unwrap());
+                assert!(!snapshot.contains_key(&vec![3]).unwrap());
+
+                let entries: Vec<_> = snapshot.iter().unwrap().map(Result::unwrap).collect();
+                assert_eq!(
+                    entries,
+                    vec![
+                        (vec![1].into(), vec![1].into()),
+                        (vec![2].into(), vec![2].into())
+                    ]
+                );
+                assert_eq!(snapshot.range(vec![2u8]..).unwrap().count(), 1);
+
+                assert_eq!(db.get(&vec![1]).unwrap(), Some(vec![10].into()));
+                assert_eq!(db.len(), 2);
+            }
+
+            #[test]
+            fn test_snapshot_survives_clear() {
+                use crate::aymr_db::traits::AymrSnapshot;
+
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![1], vec![1]).unwrap();
+
+                let snapshot = db.snapshot().unwrap();
+                db.clear().unwrap();
+
+                assert!(db.is_empty().unwrap());
+                assert_eq!(snapshot.get(&vec![1]).unwrap(), Some(vec![1].into()));
+            }
+        };
+
+        (@no_snapshot) => {
+            #[test]
+            fn test_snapshot_unsupported() {
+                let db = create_test_db::<Vec<u8>, Vec<u8>>();
+                assert!(matches!(db.snapshot(), Err(Error::Unsupported(_))));
+            }
+        };
+
+        (@watch) => {
+            #[test]
+            fn test_watch_prefix() {
+                use crate::aymr_db::watch::Event;
+
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let mut events = db.watch_prefix([1u8]).unwrap();
+
+                db.insert(vec![1, 1], vec![10]).unwrap();
+                db.insert(vec![2, 1], vec![20]).unwrap();
+                db.remove(&[1, 1]).unwrap();
+                db.remove(&[1, 2]).unwrap();
+
+                assert_eq!(
+                    events.next(),
+                    Some(Event::Insert {
+                        key: vec![1, 1].into(),
+                        value: vec![10].into(),
+                    })
+                );
+                assert_eq!(
+                    events.next(),
+                    Some(Event::Remove {
+                        key: vec![1, 1].into()
+                    })
+                );
+                assert_eq!(events.try_next(), None);
+            }
+
+            #[test]
+            fn test_watch_prefix_clear_and_batch() {
+                use crate::aymr_db::watch::Event;
+
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert(vec![1], vec![1]).unwrap();
+                let mut events = db.watch_prefix([]).unwrap();
+
+                db.clear().unwrap();
+
+                // Native batches may write each key once, so they only get
+                // one op per key here.
+                let mut batch = AymrBatch::new();
+                batch.insert(vec![2], vec![2]);
+                batch.remove(vec![3]);
+                batch.insert(vec![4], vec![4]);
+                db.apply_batch(batch).unwrap();
+
+                // Events may be passed on by another thread, and the ones of
+                // a batch in any order.
+                let timeout = Duration::from_secs(5);
+                assert_eq!(
+                    events.next_timeout(timeout),
+                    Ok(Event::Remove {
+                        key: vec![1].into()
+                    })
+                );
+                let mut batch_events: Vec<_> = (0..2)
+                    .map(|_| events.next_timeout(timeout).unwrap())
+                    .collect();
+                batch_events.sort_by(|a, b| a.key().cmp(b.key()));
+                assert_eq!(
+                    batch_events,
+                    vec![
+                        Event::Insert {
+                            key: vec![2].into(),
+                            value: vec![2].into(),
+                        },
+                        Event::Insert {
+                            key: vec![4].into(),
+                            value: vec![4].into(),
+                        },
+                    ]
+                );
+            }
+
+            #[test]
+            fn test_watch_prefix_sees_every_handle() {
+                use crate::aymr_db::watch::Event;
+
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let tree = db.open_tree("watched").unwrap();
+                let mut other = db.open_tree("watched").unwrap();
+                let mut events = tree.watch_prefix([]).unwrap();
+
+                db.insert(vec![1], vec![1]).unwrap();
+                other.insert(vec![2], vec![2]).unwrap();
+                other
+                    .transaction(|tx| {
+                        tx.remove(vec![2])?;
+                        Ok::<_, ConflictableTransactionError<()>>(())
+                    })
+                    .unwrap();
+
+                let timeout = Duration::from_secs(5);
+                assert_eq!(
+                    events.next_timeout(timeout),
+                    Ok(Event::Insert {
+                        key: vec![2].into(),
+                        value: vec![2].into(),
+                    })
+                );
+                assert_eq!(
+                    events.next_timeout(timeout),
+                    Ok(Event::Remove {
+                        key: vec![2].into()
+                    })
+                );
+                assert_eq!(events.try_next(), None);
+            }
+
+            #[test]
+            fn test_watch_prefix_ends_when_db_is_dropped() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                let events = db.watch_prefix([]).unwrap();
+                db.insert(vec![1], vec![1]).unwrap();
+                drop(db);
+
+                assert_eq!(events.count(), 1);
+            }
+        };
+
+        (@ttl) => {
+            #[test]
+            fn test_insert_with_ttl() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert_with_ttl(vec![2], vec![2], Duration::from_secs(3600))
+                    .unwrap();
+                db.insert(vec![3], vec![3]).unwrap();
+                // Writes purge expired keys, so this one has to come last.
+                db.insert_with_ttl(vec![1], vec![1], Duration::ZERO)
+                    .unwrap();
+
+                assert_eq!(db.get(&vec![1]).unwrap(), None);
+                assert!(!db.contains_key(&vec![1]).unwrap());
+                assert_eq!(db.len(), 2);
+                assert_eq!(db.scan_prefix([]).unwrap().count(), 2);
+
+                let ttl = db.ttl(&vec![2]).unwrap().unwrap();
+                assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+                assert_eq!(db.ttl(&vec![1]).unwrap(), None);
+                assert_eq!(db.ttl(&vec![3]).unwrap(), None);
+
+                assert_eq!(db.purge_expired().unwrap(), 1);
+                assert_eq!(db.purge_expired().unwrap(), 0);
+                assert_eq!(db.len(), 2);
+            }
+
+            #[test]
+            fn test_insert_clears_ttl() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert_with_ttl(vec![1], vec![1], Duration::from_secs(3600))
+                    .unwrap();
+                db.insert(vec![1], vec![2]).unwrap();
+
+                assert_eq!(db.ttl(&vec![1]).unwrap(), None);
+            }
+
+            #[test]
+            fn test_expired_keys_are_gone_for_writes() {
+                let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+                db.insert_with_ttl(vec![1], vec![1], Duration::ZERO)
+                    .unwrap();
+
+                let res = db.compare_and_swap(vec![1], None as Option<&[u8]>, Some(vec![2]));
+                assert_eq!(res, Ok(Ok(())));
+                assert_eq!(db.get(&vec![1]).unwrap(), Some(vec![2].into()));
+                assert_eq!(db.ttl(&vec![1]).unwrap(), None);
+            }
+
+            #[test]
+            fn test_sweeper_purges_expired_keys() {
+                use std::sync::{
+                    Arc,
+                    Mutex,
+                };
+
+                use crate::aymr_db::{
+                    ttl::Sweeper,
+                    watch::Event,
+                };
+
+                let db = Arc::new(Mutex::new(create_test_db::<Vec<u8>, Vec<u8>>()));
+                let mut events = db.lock().unwrap().watch_prefix([]).unwrap();
+                db.lock()
+                    .unwrap()
+                    .insert_with_ttl(vec![1], vec![1], Duration::ZERO)
+                    .unwrap();
+
+                let _sweeper = Sweeper::spawn(&db, Duration::from_millis(1));
+
+                let timeout = Duration::from_secs(5);
+                assert!(matches!(
+                    events.next_timeout(timeout),
+                    Ok(Event::Insert { .. })
+                ));
+                assert_eq!(
+                    events.next_timeout(timeout),
+                    Ok(Event::Remove {
+                        key: vec![1].into()
+                    })
+                );
+            }
+        };
+
+        (@on_disk) => {
+            #[test]
+            fn test_lifecycle() {
+                use std::io::ErrorKind;
+
+                let dir = test_dir(&format!("{}-lifecycle", KIND));
+                let path = dir.join("db");
+                let config = || {
+                    AymrDbBuilder::new()
+                        .backend(KIND)
+                        .path(&path)
+                        .build()
+                        .unwrap()
+                };
+
+                let res = AymrDb::<Vec<u8>, Vec<u8>>::open_existing(config());
+                assert!(matches!(res, Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound));
+
+                let mut db = AymrDb::<Vec<u8>, Vec<u8>>::create_new(config()).unwrap();
+                db.insert(vec![1], vec![1]).unwrap();
+                let mut tree = db.open_tree(b"tree").unwrap();
+                tree.insert(vec![2], vec![2]).unwrap();
+                tree.insert_with_ttl(vec![3], vec![3], Duration::from_secs(3600))
+                    .unwrap();
+                db.flush().unwrap();
+                drop(tree);
+                db.close().unwrap();
+
+                let res = AymrDb::<Vec<u8>, Vec<u8>>::create_new(config());
+                assert!(matches!(res, Err(Error::Io(e)) if e.kind() == ErrorKind::AlreadyExists));
+
+                let url = format!("{}://{}", KIND, path.display());
+                let mut db = AymrDb::<Vec<u8>, Vec<u8>>::open_url(&url).unwrap();
+                assert_eq!(db.kind(), KIND);
+                assert_eq!(db.get(&[1]).unwrap(), Some(vec![1].into()));
+                assert_eq!(db.tree_names(), vec![InlineArray::from("tree")]);
+                let tree = db.open_tree(b"tree").unwrap();
+                assert_eq!(tree.get(&[2]).unwrap(), Some(vec![2].into()));
+                // Deadlines are kept on disk along with their keys.
+                assert!(tree.ttl(&[3]).unwrap().is_some());
+                drop(tree);
+                db.close().unwrap();
+
+                std::fs::remove_dir_all(&dir).unwrap();
+            }
+        };
+    }
+
+    backend_tests! {
+        hashmap: BackendKind::HashMap, "hashmap",
+            [no_ordered, snapshot, watch, ttl];
+        btreemap: BackendKind::BtreeMap, "btreemap",
+            [ordered, snapshot, watch, ttl];
+        sled: BackendKind::Sled, "sled_pre",
+            [on_disk, ordered, no_snapshot, watch, ttl];
+        sled_alpha: BackendKind::SledAlpha, "sled_alpha",
+            [on_disk, ordered, no_snapshot, watch, ttl];
+        redb: BackendKind::Redb, "redb",
+            [on_disk, ordered, snapshot, watch, ttl];
+        fjall: BackendKind::Fjall, "fjall",
+            [on_disk, ordered, snapshot, watch, ttl];
+        lmdb: BackendKind::Lmdb, "lmdb",
+            [on_disk, ordered, snapshot, watch, ttl];
+    }
+
+    #[test]
+    fn test_open_with_every_available_backend() {
+        // On-disk backends need a path, which their default config lacks.
+        for kind in BackendKind::available()
+            .into_iter()
+            .filter(|kind| kind.is_in_memory())
+        {
+            let mut db = AymrDb::<Vec<u8>, Vec<u8>>::open_with(kind).unwrap();
+            assert_eq!(db.kind(), kind);
+
+            db.insert(vec![1], vec![1]).unwrap();
+            assert_eq!(db.get(&vec![1]).unwrap(), Some(vec![1].into()));
         }
     }
 
     #[test]
-    fn test_clear() {
-        let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
-        assert!(db.clear().is_ok());
+    #[cfg(not(feature = "sled_pre"))]
+    fn test_open_with_missing_backend() {
+        let res = AymrDb::<Vec<u8>, Vec<u8>>::open_with(BackendKind::Sled);
+        assert!(matches!(res, Err(Error::Unsupported(_))));
     }
 
     #[test]
-    fn test_len() {
-        let db = create_test_db::<Vec<u8>, Vec<u8>>();
-        assert_eq!(db.len(), 0);
+    #[cfg(any(feature = "hashmap", feature = "btreemap"))]
+    fn test_open_url() {
+        let kind = BackendKind::default();
+        let db = AymrDb::<Vec<u8>, Vec<u8>>::open_url(&format!("{}://", kind)).unwrap();
+        assert_eq!(db.kind(), kind);
+
+        for url in ["btreemap://some/path", "nope://", "btreemap"] {
+            let res = AymrDb::<Vec<u8>, Vec<u8>>::open_url(url);
+            assert!(matches!(res, Err(Error::Unsupported(_))), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_open_config() {
+        if BackendKind::default().is_available() {
+            let db = AymrDb::<Vec<u8>, Vec<u8>>::open(BackendConfig::default()).unwrap();
+            assert_eq!(db.kind(), BackendKind::default());
+        }
+
+        if !BackendKind::Sled.is_available() {
+            let res = AymrDb::<Vec<u8>, Vec<u8>>::open(SledConfig::new("db"));
+            assert!(matches!(res, Err(Error::Unsupported(_))));
+        }
+        if !BackendKind::Redb.is_available() {
+            let res = AymrDb::<Vec<u8>, Vec<u8>>::open(RedbConfig::new("db.redb"));
+            assert!(matches!(res, Err(Error::Unsupported(_))));
+        }
+        if !BackendKind::Fjall.is_available() {
+            let res = AymrDb::<Vec<u8>, Vec<u8>>::open(FjallConfig::new("db"));
+            assert!(matches!(res, Err(Error::Unsupported(_))));
+        }
+        if !BackendKind::Lmdb.is_available() {
+            let res = AymrDb::<Vec<u8>, Vec<u8>>::open(LmdbConfig::new("db"));
+            assert!(matches!(res, Err(Error::Unsupported(_))));
+        }
     }
 
     #[test]
-    fn test_is_empty() {
-        let db = create_test_db::<Vec<u8>, Vec<u8>>();
-        assert!(db.is_empty().unwrap());
+    #[cfg(any(feature = "hashmap", feature = "btreemap"))]
+    fn test_lifecycle() {
+        let kind = BackendKind::default();
+
+        let mut db = AymrDb::<Vec<u8>, Vec<u8>>::create_new(kind).unwrap();
+        db.insert(vec![1], vec![1]).unwrap();
+        db.close().unwrap();
+
+        // In-memory databases are gone once closed.
+        let res = AymrDb::<Vec<u8>, Vec<u8>>::open_existing(kind);
+        assert!(matches!(res, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound));
+
+        let mut db = AymrDb::<Vec<u8>, Vec<u8>>::temporary().unwrap();
+        assert_eq!(db.kind(), kind);
+        db.insert(vec![1], vec![1]).unwrap();
+        let mut tree = db.open_tree(b"tree").unwrap();
+        tree.insert(vec![2], vec![2]).unwrap();
+        db.close().unwrap();
+
+        if !BackendKind::Sled.is_available() {
+            let res = AymrDb::<Vec<u8>, Vec<u8>>::create_new(SledConfig::new("db"));
+            assert!(matches!(res, Err(Error::Unsupported(_))));
+        }
     }
 
     #[test]
-    fn test_get() {
-        let db = create_test_db::<Vec<u8>, Vec<u8>>();
-        assert!(db.get(&vec![1, 2, 3]).unwrap().is_none());
+    fn test_builder() {
+        let config = AymrDb::<Vec<u8>, Vec<u8>>::builder()
+            .backend(BackendKind::HashMap)
+            .capacity(64)
+            .build()
+            .unwrap();
+        assert_eq!(
+            config,
+            BackendConfig::HashMap(HashMapConfig { capacity: 64 })
+        );
+
+        let config = AymrDbBuilder::new()
+            .backend(BackendKind::Sled)
+            .path("db")
+            .cache_capacity(1024)
+            .durability(Durability::Immediate)
+            .build()
+            .unwrap();
+        assert_eq!(
+            config,
+            BackendConfig::Sled(SledConfig {
+                cache_capacity: Some(1024),
+                durability: Durability::Immediate,
+                ..SledConfig::new("db")
+            })
+        );
+
+        let res = AymrDbBuilder::new()
+            .backend(BackendKind::BtreeMap)
+            .capacity(64)
+            .build();
+        assert!(matches!(res, Err(Error::Config(_))));
+
+        let res = AymrDbBuilder::new().backend(BackendKind::Sled).build();
+        assert!(matches!(res, Err(Error::Config(_))));
+
+        let config = AymrDbBuilder::new()
+            .backend(BackendKind::Lmdb)
+            .path("db")
+            .map_size(1 << 30)
+            .build()
+            .unwrap();
+        assert_eq!(
+            config,
+            BackendConfig::Lmdb(LmdbConfig {
+                map_size: Some(1 << 30),
+                ..LmdbConfig::new("db")
+            })
+        );
+
+        for builder in [
+            AymrDbBuilder::new().backend(BackendKind::Sled).map_size(1),
+            AymrDbBuilder::new()
+                .backend(BackendKind::Lmdb)
+                .cache_capacity(1),
+        ] {
+            let res = builder.temporary(true).build();
+            assert!(matches!(res, Err(Error::Config(_))));
+        }
+
+        if BackendKind::default().is_available() {
+            let mut db = AymrDbBuilder::new().open::<Vec<u8>, Vec<u8>>().unwrap();
+            db.insert(vec![1], vec![1]).unwrap();
+            assert_eq!(db.get(&vec![1]).unwrap(), Some(vec![1].into()));
+        }
     }
 
     #[test]
-    fn test_insert_and_get() {
-        let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
-        let key = vec![1, 2, 3];
-        let value = vec![4, 5, 6];
-        assert!(db.insert(key.clone(), value).is_ok());
-        assert!(db.get(&key).unwrap().is_some());
+    #[cfg(feature = "config-toml")]
+    fn test_builder_from_toml() {
+        let builder = AymrDbBuilder::from_toml(
+            r#"
+            backend = "sled"
+            path = "/var/lib/aymr"
+            durability = "immediate"
+            temporary = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            builder,
+            AymrDbBuilder::new()
+                .backend(BackendKind::Sled)
+                .path("/var/lib/aymr")
+                .durability(Durability::Immediate)
+                .temporary(true)
+        );
+
+        for toml in [
+            "backend = \"nope\"",
+            "size = 1",
+            "durability = \"sometimes\"",
+        ] {
+            let res = AymrDbBuilder::from_toml(toml);
+            assert!(matches!(res, Err(Error::Config(_))), "{}", toml);
+        }
+
+        let res = AymrDbBuilder::from_file("does/not/exist.toml");
+        assert!(matches!(res, Err(Error::Io(_))));
     }
 
     #[test]
-    fn test_remove() {
-        let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
-        let key = vec![1, 2, 3];
-        let value = vec![4, 5, 6];
-        db.insert(key.clone(), value).unwrap();
-        assert_eq!(db.len(), 1);
-        assert!(db.remove(key).unwrap().is_some());
-        assert_eq!(db.len(), 0);
+    fn test_builder_from_env() {
+        let vars = |vars: &[(&str, &str)]| {
+            AymrDbBuilder::new()
+                .backend(BackendKind::HashMap)
+                .with_vars(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+        };
+
+        let builder = vars(&[("AYMR_CAPACITY", "8"), ("HOME", "/root")]).unwrap();
+        assert_eq!(
+            builder.build().unwrap(),
+            BackendConfig::HashMap(HashMapConfig { capacity: 8 })
+        );
+
+        let builder = vars(&[("AYMR_BACKEND", "sled"), ("AYMR_TEMPORARY", "true")]).unwrap();
+        assert_eq!(
+            builder.build().unwrap(),
+            BackendConfig::Sled(SledConfig {
+                path: None,
+                temporary: true,
+                ..SledConfig::new("")
+            })
+        );
+
+        for var in [("AYMR_CAPACITY", "lots"), ("AYMR_SIZE", "1")] {
+            let res = vars(&[var]);
+            assert!(matches!(res, Err(Error::Config(_))), "{:?}", var);
+        }
     }
 
     #[test]
-    fn test_contains_key() {
-        let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
-        let key = vec![1, 2, 3];
-        let value = vec![4, 5, 6];
-        db.insert(key.clone(), value).unwrap();
-        assert!(db.contains_key(&key).unwrap());
+    #[cfg(feature = "btreemap")]
+    fn test_static_backend() {
+        use crate::{
+            aymr_db::config::BtreeMapConfig,
+            backends::btreemap::db::AymrBtreeMap,
+        };
+
+        let mut db = <AymrDb<Vec<u8>, Vec<u8>, AymrBtreeMap>>::open(BtreeMapConfig {}).unwrap();
+        db.insert(vec![2], vec![2]).unwrap();
+        db.insert(vec![1], vec![1]).unwrap();
+
+        assert_eq!(db.first().unwrap(), Some((vec![1].into(), vec![1].into())));
+        assert_eq!(
+            AymrDatabase::<[u8; 1], [u8; 1]>::get(db.backend(), &[2]).unwrap(),
+            Some([2].into())
+        );
+    }
+
+    /// Returns a fresh directory for an on-disk test database, named after
+    /// the test.
+    #[cfg(any(
+        feature = "sled_pre",
+        feature = "sled_alpha",
+        feature = "redb",
+        feature = "fjall",
+        feature = "lmdb"
+    ))]
+    pub(crate) fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("aymr-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
     }
 
     #[test]
-    fn test_clear_after_insert() {
-        let mut db = create_test_db::<Vec<u8>, Vec<u8>>();
+    #[cfg(any(feature = "hashmap", feature = "btreemap"))]
+    fn test_dyn_database_registry() {
+        use crate::aymr_db::dyn_db::DynDatabase;
 
-        let _ = db.insert("key".into(), "value".into());
-        assert_eq!(db.len(), 1);
+        let mut registry: Vec<Box<dyn DynDatabase>> = BackendKind::available()
+            .into_iter()
+            .filter(|kind| kind.is_in_memory())
+            .map(|kind| {
+                Box::new(AymrDb::<InlineArray, InlineArray>::open_with(kind).unwrap())
+                    as Box<dyn DynDatabase>
+            })
+            .collect();
+        registry.push(Box::new(create_test_db::<InlineArray, InlineArray>()));
 
-        assert!(db.clear().is_ok());
-        assert_eq!(db.len(), 0);
-        assert!(db.is_empty().unwrap());
+        for db in &mut registry {
+            db.insert(b"key", b"value").unwrap();
+            assert_eq!(db.get(b"key").unwrap(), Some(b"value".into()));
+            assert!(db.contains_key(b"key").unwrap());
+
+            let snapshot = db.snapshot().unwrap();
+            db.remove(b"key").unwrap();
+            assert_eq!(db.len(), 0);
+            assert_eq!(snapshot.get(b"key").unwrap(), Some(b"value".into()));
+        }
     }
 }
@@ -0,0 +1,298 @@
+//! `Aymr dynamic databases`
+//!
+//! `AymrDatabase` is generic over its key and value types and has generic
+//! methods, so it can't be used as a trait object. `DynDatabase` is an
+//! object-safe, byte-oriented version of it, implemented for every database
+//! that stores `InlineArray` keys and values. This lets databases of different
+//! backends live side by side behind a `Box<dyn DynDatabase>`.
+//!
+//! Transactions and trees are not part of `DynDatabase`, as both rely on
+//! closures and associated types. Use the static traits for those.
+
+use std::{
+    ops::Bound,
+    time::Duration,
+};
+
+use crate::aymr_db::{
+    error::{
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::Iter,
+    merge::MergeOperator,
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrSnapshot,
+        InlineArray,
+    },
+    watch::Subscriber,
+};
+
+/// Closure passed to `update_and_fetch` and `fetch_and_update`.
+pub type UpdateFn<'a> = dyn FnMut(Option<&[u8]>) -> Option<Vec<u8>> + 'a;
+
+/// Object-safe, byte-oriented version of `AymrDatabase`.
+///
+/// See `AymrDatabase` for what each method does.
+pub trait DynDatabase {
+    fn clear(&mut self) -> Result<(), Error>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> Result<bool, Error>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<InlineArray>, Error>;
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<InlineArray>, Error>;
+
+    fn remove(&mut self, key: &[u8]) -> Result<Option<InlineArray>, Error>;
+
+    fn insert_with_ttl(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error>;
+
+    fn ttl(&self, key: &[u8]) -> Result<Option<Duration>, Error>;
+
+    fn purge_expired(&mut self) -> Result<usize, Error>;
+
+    fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> CompareAndSwapResult;
+
+    fn update_and_fetch(
+        &mut self,
+        key: &[u8],
+        f: &mut UpdateFn<'_>,
+    ) -> Result<Option<InlineArray>, Error>;
+
+    fn fetch_and_update(
+        &mut self,
+        key: &[u8],
+        f: &mut UpdateFn<'_>,
+    ) -> Result<Option<InlineArray>, Error>;
+
+    fn set_merge_operator(&mut self, merge_operator: Box<dyn MergeOperator>);
+
+    fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<Option<InlineArray>, Error>;
+
+    fn apply_batch(&mut self, batch: AymrBatch<InlineArray, InlineArray>) -> Result<(), Error>;
+
+    fn contains_key(&self, key: &[u8]) -> Result<bool, Error>;
+
+    fn iter(&self) -> Result<Iter<'_>, Error>;
+
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Iter<'_>, Error>;
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Iter<'_>, Error>;
+
+    fn remove_prefix(&mut self, prefix: &[u8]) -> Result<usize, Error>;
+
+    fn get_lt(&self, key: &[u8]) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    fn get_gt(&self, key: &[u8]) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    fn snapshot(&self) -> Result<Box<dyn DynSnapshot>, Error>;
+
+    fn watch_prefix(&self, prefix: &[u8]) -> Result<Subscriber, Error>;
+}
+
+/// Object-safe, byte-oriented version of `AymrSnapshot`.
+pub trait DynSnapshot {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> Result<bool, Error>;
+
+    fn get(&self, key: &[u8]) -> Result<Option<InlineArray>, Error>;
+
+    fn contains_key(&self, key: &[u8]) -> Result<bool, Error>;
+
+    fn iter(&self) -> Result<Iter<'_>, Error>;
+
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Iter<'_>, Error>;
+}
+
+impl<D> DynDatabase for D
+where
+    D: AymrDatabase<InlineArray, InlineArray>,
+    D::Snapshot: 'static,
+{
+    fn clear(&mut self) -> Result<(), Error> {
+        AymrDatabase::clear(self)
+    }
+
+    fn len(&self) -> usize {
+        AymrDatabase::len(self)
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        AymrDatabase::is_empty(self)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<InlineArray>, Error> {
+        AymrDatabase::get(self, key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<Option<InlineArray>, Error> {
+        AymrDatabase::insert(self, InlineArray::from(key), InlineArray::from(value))
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<Option<InlineArray>, Error> {
+        AymrDatabase::remove(self, key)
+    }
+
+    fn insert_with_ttl(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        AymrDatabase::insert_with_ttl(self, InlineArray::from(key), InlineArray::from(value), ttl)
+    }
+
+    fn ttl(&self, key: &[u8]) -> Result<Option<Duration>, Error> {
+        AymrDatabase::ttl(self, key)
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        AymrDatabase::purge_expired(self)
+    }
+
+    fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> CompareAndSwapResult {
+        AymrDatabase::compare_and_swap(
+            self,
+            InlineArray::from(key),
+            old,
+            new.map(InlineArray::from),
+        )
+    }
+
+    fn update_and_fetch(
+        &mut self,
+        key: &[u8],
+        f: &mut UpdateFn<'_>,
+    ) -> Result<Option<InlineArray>, Error> {
+        AymrDatabase::update_and_fetch(self, InlineArray::from(key), f)
+    }
+
+    fn fetch_and_update(
+        &mut self,
+        key: &[u8],
+        f: &mut UpdateFn<'_>,
+    ) -> Result<Option<InlineArray>, Error> {
+        AymrDatabase::fetch_and_update(self, InlineArray::from(key), f)
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: Box<dyn MergeOperator>) {
+        AymrDatabase::set_merge_operator(self, merge_operator)
+    }
+
+    fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<Option<InlineArray>, Error> {
+        AymrDatabase::merge(self, InlineArray::from(key), operand)
+    }
+
+    fn apply_batch(&mut self, batch: AymrBatch<InlineArray, InlineArray>) -> Result<(), Error> {
+        AymrDatabase::apply_batch(self, batch)
+    }
+
+    fn contains_key(&self, key: &[u8]) -> Result<bool, Error> {
+        AymrDatabase::contains_key(self, key)
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        AymrDatabase::iter(self)
+    }
+
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Iter<'_>, Error> {
+        AymrDatabase::range::<[u8], _>(self, (start, end))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Iter<'_>, Error> {
+        AymrDatabase::scan_prefix(self, prefix)
+    }
+
+    fn remove_prefix(&mut self, prefix: &[u8]) -> Result<usize, Error> {
+        AymrDatabase::remove_prefix(self, prefix)
+    }
+
+    fn get_lt(&self, key: &[u8]) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::get_lt(self, key)
+    }
+
+    fn get_gt(&self, key: &[u8]) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::get_gt(self, key)
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::first(self)
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::last(self)
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::pop_min(self)
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::pop_max(self)
+    }
+
+    fn snapshot(&self) -> Result<Box<dyn DynSnapshot>, Error> {
+        Ok(Box::new(AymrDatabase::snapshot(self)?))
+    }
+
+    fn watch_prefix(&self, prefix: &[u8]) -> Result<Subscriber, Error> {
+        AymrDatabase::watch_prefix(self, prefix)
+    }
+}
+
+impl<S> DynSnapshot for S
+where
+    S: AymrSnapshot<InlineArray>,
+{
+    fn len(&self) -> usize {
+        AymrSnapshot::len(self)
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        AymrSnapshot::is_empty(self)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<InlineArray>, Error> {
+        AymrSnapshot::get(self, key)
+    }
+
+    fn contains_key(&self, key: &[u8]) -> Result<bool, Error> {
+        AymrSnapshot::contains_key(self, key)
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        AymrSnapshot::iter(self)
+    }
+
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Result<Iter<'_>, Error> {
+        AymrSnapshot::range::<[u8], _>(self, (start, end))
+    }
+}
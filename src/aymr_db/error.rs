@@ -11,6 +11,8 @@ use std::{
 #[cfg(feature = "testing")]
 use backtrace::Backtrace;
 
+use crate::aymr_db::traits::InlineArray;
+
 /// The top-level result type for dealing with
 /// fallible operations. The errors tend to
 /// be fail-stop, and nested results are used
@@ -21,6 +23,32 @@ use backtrace::Backtrace;
 /// is detailed [on the sled blog](https://sled.rs/errors).
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Compare and swap result.
+///
+/// It returns `Ok(Ok(()))` if the operation finishes successfully,
+/// `Ok(Err(CompareAndSwapError { .. }))` if the current value did not
+/// match the expected one, and `Err(Error)` if the underlying database
+/// failed to perform the operation at all.
+pub type CompareAndSwapResult = Result<std::result::Result<(), CompareAndSwapError>>;
+
+/// Compare and swap error, returned when the current value of a key
+/// did not match the expected one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompareAndSwapError {
+    /// The current value which caused your CAS to fail.
+    pub current: Option<InlineArray>,
+    /// Returned value that was proposed unsuccessfully.
+    pub proposed: Option<InlineArray>,
+}
+
+impl Display for CompareAndSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Compare and swap conflict")
+    }
+}
+
+impl StdError for CompareAndSwapError {}
+
 /// An Error type encapsulating various issues that may come up
 /// in the operation of a `Db`.
 #[derive(Debug)]
@@ -31,6 +59,20 @@ pub enum Error {
     Unsupported(String),
     /// An unexpected bug has happened. Please open an issue on github!
     ReportableBug(String),
+    /// The configuration is malformed or doesn't fit the chosen backend.
+    Config(String),
+    /// A stored key or value could not be decoded by its codec.
+    Decode(String),
+    /// The key is longer than the backend can store.
+    KeyTooLarge {
+        /// Length of the key in bytes.
+        len: usize,
+        /// Longest key the backend can store, in bytes.
+        max: usize,
+    },
+    /// The database has taken up all the space it was opened with, and has
+    /// to be reopened with more, like an LMDB map with a larger `map_size`.
+    MapFull,
     /// A read or write error has happened when interacting with the file
     /// system.
     Io(io::Error),
@@ -73,6 +115,15 @@ impl Clone for Error {
             CollectionNotFound(name) => CollectionNotFound(name.clone()),
             Unsupported(why) => Unsupported(why.clone()),
             ReportableBug(what) => ReportableBug(what.clone()),
+            Config(why) => Config(why.clone()),
+            Decode(why) => Decode(why.clone()),
+            KeyTooLarge { len, max } => {
+                KeyTooLarge {
+                    len: *len,
+                    max: *max,
+                }
+            }
+            MapFull => MapFull,
             Corruption { at, bt } => {
                 Corruption {
                     at: at.clone(),
@@ -113,6 +164,28 @@ impl PartialEq for Error {
                     false
                 }
             }
+            Config(ref l) => {
+                if let Config(ref r) = *other {
+                    l == r
+                } else {
+                    false
+                }
+            }
+            Decode(ref l) => {
+                if let Decode(ref r) = *other {
+                    l == r
+                } else {
+                    false
+                }
+            }
+            KeyTooLarge { len: l, max: lm } => {
+                if let KeyTooLarge { len: r, max: rm } = other {
+                    l == r && lm == rm
+                } else {
+                    false
+                }
+            }
+            MapFull => matches!(other, MapFull),
             #[cfg(feature = "failpoints")]
             FailPoint => {
                 if let FailPoint = *other {
@@ -159,14 +232,30 @@ impl From<Error> for io::Error {
                 )
             }
             ReportableBug(what) => {
+                io::Error::other(format!(
+                    "unexpected bug! please report this bug at <github.rs/spacejam/sled>: {:?}",
+                    what
+                ))
+            }
+            Config(why) => {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid configuration: {:?}", why),
+                )
+            }
+            Decode(why) => {
                 io::Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "unexpected bug! please report this bug at <github.rs/spacejam/sled>: {:?}",
-                        what
-                    ),
+                    ErrorKind::InvalidData,
+                    format!("failed to decode: {:?}", why),
+                )
+            }
+            KeyTooLarge { len, max } => {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("key of {} bytes is longer than the {} allowed", len, max),
                 )
             }
+            MapFull => io::Error::new(ErrorKind::StorageFull, "database map is full"),
             Corruption { .. } => {
                 io::Error::new(
                     ErrorKind::InvalidData,
@@ -174,7 +263,7 @@ impl From<Error> for io::Error {
                 )
             }
             #[cfg(feature = "failpoints")]
-            FailPoint => io::Error::new(ErrorKind::Other, "failpoint"),
+            FailPoint => io::Error::other("failpoint"),
         }
     }
 }
@@ -198,6 +287,18 @@ impl Display for Error {
                     e
                 )
             }
+            Config(ref e) => write!(f, "Invalid configuration: {}", e),
+            Decode(ref e) => write!(f, "Failed to decode: {}", e),
+            KeyTooLarge { len, max } => {
+                write!(
+                    f,
+                    "Key of {} bytes is longer than the {} bytes allowed",
+                    len, max
+                )
+            }
+            MapFull => {
+                write!(f, "Database map is full, reopen it with a larger map size")
+            }
             #[cfg(feature = "failpoints")]
             FailPoint => write!(f, "Fail point has been triggered."),
             Io(ref e) => write!(f, "IO error: {}", e),
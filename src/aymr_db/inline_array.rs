@@ -0,0 +1,247 @@
+//! `Aymr inline arrays`
+//!
+//! `InlineArray` is the byte string every backend stores and hands out, much
+//! like sled's `IVec`. Arrays of up to `INLINE_CAPACITY` bytes are kept inline
+//! without allocating, and larger ones live behind a reference count. Cloning
+//! either kind never copies more than the inline bytes, so handing out a
+//! stored value doesn't copy it.
+
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt,
+    hash::{
+        Hash,
+        Hasher,
+    },
+    ops::Deref,
+    sync::Arc,
+};
+
+/// Largest number of bytes kept inline, so that an `InlineArray` takes up
+/// as much space as a `Vec<u8>`.
+pub const INLINE_CAPACITY: usize = std::mem::size_of::<Vec<u8>>() - 2;
+
+/// Immutable, cheaply cloneable byte string.
+#[derive(Clone)]
+pub struct InlineArray(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Inline(u8, [u8; INLINE_CAPACITY]),
+    Remote(Arc<[u8]>),
+}
+
+impl InlineArray {
+    /// Returns an empty array.
+    pub const fn new() -> Self {
+        InlineArray(Repr::Inline(0, [0; INLINE_CAPACITY]))
+    }
+
+    /// Returns the bytes of the array.
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Inline(len, data) => &data[..*len as usize],
+            Repr::Remote(data) => data,
+        }
+    }
+
+    /// Returns true if the bytes are kept inline rather than behind a
+    /// reference count.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline(..))
+    }
+
+    fn inline(bytes: &[u8]) -> Option<Self> {
+        let mut data = [0; INLINE_CAPACITY];
+        data.get_mut(..bytes.len())?.copy_from_slice(bytes);
+        Some(InlineArray(Repr::Inline(bytes.len() as u8, data)))
+    }
+}
+
+impl Default for InlineArray {
+    fn default() -> Self {
+        InlineArray::new()
+    }
+}
+
+impl Deref for InlineArray {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for InlineArray {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+/// Hashes, compares and orders like `[u8]`, so maps keyed by `InlineArray`
+/// can be queried with plain byte slices.
+impl Borrow<[u8]> for InlineArray {
+    fn borrow(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl From<&[u8]> for InlineArray {
+    fn from(bytes: &[u8]) -> Self {
+        InlineArray::inline(bytes).unwrap_or_else(|| InlineArray(Repr::Remote(Arc::from(bytes))))
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for InlineArray {
+    fn from(bytes: &[u8; N]) -> Self {
+        InlineArray::from(&bytes[..])
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for InlineArray {
+    fn from(bytes: [u8; N]) -> Self {
+        InlineArray::from(&bytes[..])
+    }
+}
+
+impl From<&str> for InlineArray {
+    fn from(s: &str) -> Self {
+        InlineArray::from(s.as_bytes())
+    }
+}
+
+impl From<String> for InlineArray {
+    fn from(s: String) -> Self {
+        InlineArray::from(s.into_bytes())
+    }
+}
+
+impl From<Vec<u8>> for InlineArray {
+    fn from(bytes: Vec<u8>) -> Self {
+        InlineArray::inline(&bytes).unwrap_or_else(|| InlineArray(Repr::Remote(Arc::from(bytes))))
+    }
+}
+
+impl From<Box<[u8]>> for InlineArray {
+    fn from(bytes: Box<[u8]>) -> Self {
+        InlineArray::inline(&bytes).unwrap_or_else(|| InlineArray(Repr::Remote(Arc::from(bytes))))
+    }
+}
+
+/// Shares the allocation, even if it is small enough to be kept inline.
+impl From<Arc<[u8]>> for InlineArray {
+    fn from(bytes: Arc<[u8]>) -> Self {
+        InlineArray(Repr::Remote(bytes))
+    }
+}
+
+impl From<InlineArray> for Vec<u8> {
+    fn from(array: InlineArray) -> Self {
+        array.as_slice().to_vec()
+    }
+}
+
+impl FromIterator<u8> for InlineArray {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        InlineArray::from(iter.into_iter().collect::<Vec<u8>>())
+    }
+}
+
+impl PartialEq for InlineArray {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for InlineArray {}
+
+impl PartialOrd for InlineArray {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InlineArray {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl Hash for InlineArray {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state);
+    }
+}
+
+impl PartialEq<[u8]> for InlineArray {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl PartialEq<&[u8]> for InlineArray {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.as_slice() == *other
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for InlineArray {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl PartialEq<Vec<u8>> for InlineArray {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl PartialEq<InlineArray> for Vec<u8> {
+    fn eq(&self, other: &InlineArray) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl fmt::Debug for InlineArray {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aymr_db::{
+        db::tests::create_test_db,
+        traits::AymrDatabase,
+    };
+
+    #[test]
+    fn test_inline_array() {
+        assert_eq!(
+            std::mem::size_of::<InlineArray>(),
+            std::mem::size_of::<Vec<u8>>()
+        );
+
+        let small = InlineArray::from(&[1; INLINE_CAPACITY]);
+        let large = InlineArray::from(vec![2; INLINE_CAPACITY + 1]);
+        assert!(small.is_inline());
+        assert!(!large.is_inline());
+        assert_eq!(large.clone().as_ptr(), large.as_ptr());
+        assert!(small < large);
+        assert_eq!(small, [1; INLINE_CAPACITY]);
+        assert_eq!(Vec::from(large.clone()), vec![2; INLINE_CAPACITY + 1]);
+
+        // In-memory backends hand out the bytes they hold rather than copies.
+        let mut db = create_test_db::<Vec<u8>, InlineArray>();
+        db.insert(vec![1], large.clone()).unwrap();
+        let first = db.get(&vec![1]).unwrap().unwrap();
+        let second = db.get(&vec![1]).unwrap().unwrap();
+        assert_eq!(first, large);
+        if db.kind().is_in_memory() {
+            assert_eq!(first.as_ptr(), second.as_ptr());
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! `Aymr iterators`
+//!
+//! Iterators returned by the ordered scan methods of `AymrDatabase`, as
+//! well as helpers backends can use to turn user supplied ranges into
+//! byte ranges.
+
+use std::ops::{
+    Bound,
+    RangeBounds,
+};
+
+use crate::aymr_db::{
+    error::Error,
+    traits::InlineArray,
+};
+
+/// A key-value pair yielded by `Iter`.
+pub type Entry = (InlineArray, InlineArray);
+
+/// A double-ended iterator over the key-value pairs of a database,
+/// ordered by key.
+///
+/// Reading an entry can fail on backends that read from disk, so every item
+/// is a `Result`. An iterator that yielded an error should not be relied on
+/// to yield anything after it.
+pub struct Iter<'a> {
+    inner: Box<dyn DoubleEndedIterator<Item = Result<Entry, Error>> + 'a>,
+}
+
+impl<'a> Iter<'a> {
+    /// Wraps any double-ended iterator of key-value pairs that can't fail.
+    ///
+    /// The wrapped iterator must already yield its items in ascending key order.
+    pub fn new<I>(inner: I) -> Self
+    where
+        I: DoubleEndedIterator<Item = Entry> + 'a,
+    {
+        Iter::fallible(inner.map(Ok))
+    }
+
+    /// Wraps any double-ended iterator of key-value pairs whose reads can
+    /// fail.
+    ///
+    /// The wrapped iterator must already yield its items in ascending key order.
+    pub fn fallible<I>(inner: I) -> Self
+    where
+        I: DoubleEndedIterator<Item = Result<Entry, Error>> + 'a,
+    {
+        Iter {
+            inner: Box::new(inner),
+        }
+    }
+
+    /// Returns an iterator that yields nothing.
+    pub fn empty() -> Self {
+        Iter::new(std::iter::empty())
+    }
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl std::fmt::Debug for Iter<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Iter").finish_non_exhaustive()
+    }
+}
+
+/// A pair of borrowed byte bounds, as accepted by `BTreeMap::range`.
+pub(crate) type ByteBounds<'a> = (Bound<&'a [u8]>, Bound<&'a [u8]>);
+
+/// Converts any range over byte-like keys into a range over byte slices.
+pub(crate) fn byte_bounds<'a, Q, R>(range: &'a R) -> ByteBounds<'a>
+where
+    Q: AsRef<[u8]> + ?Sized + 'a,
+    R: RangeBounds<Q>,
+{
+    (
+        range.start_bound().map(AsRef::as_ref),
+        range.end_bound().map(AsRef::as_ref),
+    )
+}
+
+/// Returns true if no key can fall inside of `bounds`.
+///
+/// `BTreeMap::range` panics on inverted ranges, so backends should check
+/// this before handing the bounds over.
+pub(crate) fn is_empty_range(bounds: &ByteBounds<'_>) -> bool {
+    match *bounds {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// Returns the smallest key that is larger than every key starting with `prefix`.
+///
+/// Prefixes made up entirely of `0xff` bytes (and the empty prefix) have no
+/// such key, in which case the range is unbounded.
+#[allow(dead_code)]
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Bound<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Bound::Excluded(end);
+        }
+    }
+
+    Bound::Unbounded
+}
@@ -0,0 +1,65 @@
+//! `Aymr merge operators`
+//!
+//! Merge operators let a database combine a new operand with the value already
+//! stored at a key, without the caller having to read the value first. This is
+//! handy for counters, append-only lists and the like.
+//!
+//! Backends with native merge support hand the operator over to the underlying
+//! database. The in-memory backends emulate it with a read-modify-write, which
+//! looks exactly the same from the outside.
+
+use std::{
+    fmt,
+    sync::Arc,
+};
+
+use crate::aymr_db::error::Error;
+
+/// A function that merges an operand into the value stored at a key.
+///
+/// It gets called with the key, the current value (if any) and the operand,
+/// and returns the value to store. Returning `None` removes the key.
+pub trait MergeOperator: Fn(&[u8], Option<&[u8]>, &[u8]) -> Option<Vec<u8>> + Send + Sync {}
+
+impl<F> MergeOperator for F where F: Fn(&[u8], Option<&[u8]>, &[u8]) -> Option<Vec<u8>> + Send + Sync
+{}
+
+/// Holds the merge operator of a backend that emulates merges.
+#[derive(Clone, Default)]
+pub(crate) struct MergeOperatorSlot(Option<Arc<dyn MergeOperator>>);
+
+impl MergeOperatorSlot {
+    /// Replaces the current merge operator.
+    pub(crate) fn set(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.0 = Some(Arc::new(merge_operator));
+    }
+
+    /// Returns the merge operator, failing if none has been set.
+    pub(crate) fn get(&self) -> Result<&dyn MergeOperator, Error> {
+        self.0.as_deref().ok_or_else(|| {
+            Error::Unsupported("merge called without a merge operator set".to_string())
+        })
+    }
+}
+
+impl fmt::Debug for MergeOperatorSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "MergeOperator(..)"),
+            None => write!(f, "None"),
+        }
+    }
+}
+
+/// Two slots are equal if they hold the very same merge operator.
+impl PartialEq for MergeOperatorSlot {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (None, None) => true,
+            (Some(l), Some(r)) => Arc::ptr_eq(l, r),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MergeOperatorSlot {}
@@ -1,4 +1,16 @@
+pub mod backend;
+pub mod codec;
 pub mod config;
 pub mod db;
+pub mod dyn_db;
 pub mod error;
+pub mod inline_array;
+pub mod iter;
+pub mod merge;
 pub mod traits;
+pub mod transaction;
+pub mod tree;
+pub mod ttl;
+pub mod tuple;
+pub mod typed;
+pub mod watch;
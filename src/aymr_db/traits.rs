@@ -1,60 +1,420 @@
-use std::result::Result;
+use std::{
+    fmt,
+    ops::RangeBounds,
+    result::Result,
+    time::Duration,
+};
 
-use crate::aymr_db::error::Error;
+use crate::aymr_db::{
+    error::{
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::Iter,
+    merge::MergeOperator,
+    transaction::{
+        ConflictableTransactionResult,
+        Transaction,
+        TransactionResult,
+    },
+    watch::Subscriber,
+};
 
-/// The type representing an inline array of bytes.
-pub type InlineArray = Vec<u8>;
+pub use crate::aymr_db::inline_array::InlineArray;
 
 /// Trait for marking that the database we're opening is compatible with Aymr.
-pub trait AymrOpenable {
-    fn open() -> Self;
+pub trait AymrOpenable: Sized {
+    /// Config the database is opened with.
+    type Config: AymrConfig;
+
+    /// Opens the database described by `config`, creating it if needed.
+    fn open(config: Self::Config) -> Result<Self, Error>;
+
+    /// Creates a new database, failing with `io::ErrorKind::AlreadyExists`
+    /// if there already is one.
+    fn create_new(config: Self::Config) -> Result<Self, Error>;
+
+    /// Opens an existing database, failing with `io::ErrorKind::NotFound`
+    /// if there is none.
+    fn open_existing(config: Self::Config) -> Result<Self, Error>;
+
+    /// Opens a database that deletes itself once dropped.
+    fn temporary() -> Result<Self, Error>;
+
+    /// Flushes the database and releases its resources.
+    ///
+    /// Dropping the database releases them too, but swallows any error.
+    fn close(self) -> Result<(), Error> {
+        drop(self);
+        Ok(())
+    }
 }
 
 /// Aymr database trait. Represents an easily implementable,
 /// common API for various KV databases.
+///
+/// Methods that only look a key up take it by reference as anything that
+/// derefs to bytes, so a database keyed by `Vec<u8>` can be queried with a
+/// `&[u8]` or `&str` without allocating.
 pub trait AymrDatabase<K, V>
 where
     K: AsRef<[u8]>,
     V: AsRef<[u8]>,
 {
+    /// Read-only, point-in-time view of the database returned by `snapshot`.
+    type Snapshot: AymrSnapshot<K>;
+
     /// Clears the entire database, removing all values.
     fn clear(&mut self) -> Result<(), Error>;
 
     /// Returns the number of elements in the database.
+    ///
+    /// Counting can't fail, so this returns 0 if the database can't be read,
+    /// the same as for its snapshots. `is_empty` and `iter` return the error
+    /// instead.
     fn len(&self) -> usize;
 
     /// Returns true if the database is empty, false otherwise.
     fn is_empty(&self) -> Result<bool, Error>;
 
     /// Retrieves a value from the database if it exists.
-    fn get(&self, key: &K) -> Result<Option<InlineArray>, Error>;
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error>;
 
     /// Inserts a new key-value pair into the database, returning the old value if it was set.
     fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error>;
 
     /// Removes a key-value pair from the database, returning the old value if it existed.
-    fn remove(&mut self, key: K) -> Result<Option<InlineArray>, Error>;
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error>;
+
+    /// Inserts a key-value pair that expires once `ttl` has passed, returning
+    /// the old value if it was set.
+    ///
+    /// Expired keys are invisible to every read. Writing to a key through any
+    /// other method clears its time to live.
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error>;
+
+    /// Returns the time `key` has left to live, or `None` if it does not
+    /// exist or never expires.
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error>;
+
+    /// Removes every expired key, returning how many there were.
+    ///
+    /// In-memory backends also remove expired keys on the next write, so
+    /// calling this is only needed to release their memory early. On-disk
+    /// backends keep them until this is called. See `ttl::Sweeper`.
+    fn purge_expired(&mut self) -> Result<usize, Error>;
+
+    /// Atomically sets `key` to `new` if its current value is `old`.
+    ///
+    /// `None` as `old` means the key is expected to be absent, and `None` as `new`
+    /// removes the key. If the current value does not match `old`, the inner
+    /// result holds the current value alongside the proposed one.
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult;
+
+    /// Fetches the value at `key`, passes it to `f` and stores the result,
+    /// returning the newly stored value.
+    ///
+    /// Returning `None` from `f` removes the key. The update is done through
+    /// `compare_and_swap`, so if the value changed in the meantime `f` is
+    /// called again with the fresh value until the swap succeeds.
+    fn update_and_fetch<F>(&mut self, key: K, mut f: F) -> Result<Option<InlineArray>, Error>
+    where
+        F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+        K: Clone,
+        V: From<Vec<u8>>,
+    {
+        loop {
+            let current = self.get(&key)?;
+            let next = f(current.as_deref());
+            if self
+                .compare_and_swap(key.clone(), current, next.clone().map(V::from))?
+                .is_ok()
+            {
+                return Ok(next.map(InlineArray::from));
+            }
+        }
+    }
+
+    /// Fetches the value at `key`, passes it to `f` and stores the result,
+    /// returning the previous value.
+    ///
+    /// Behaves exactly like `update_and_fetch` apart from the returned value.
+    fn fetch_and_update<F>(&mut self, key: K, mut f: F) -> Result<Option<InlineArray>, Error>
+    where
+        F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+        K: Clone,
+        V: From<Vec<u8>>,
+    {
+        loop {
+            let current = self.get(&key)?;
+            let next = f(current.as_deref());
+            if self
+                .compare_and_swap(key.clone(), current.as_ref(), next.map(V::from))?
+                .is_ok()
+            {
+                return Ok(current);
+            }
+        }
+    }
+
+    /// Sets the merge operator used by `merge`, replacing any previous one.
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static);
+
+    /// Merges `operand` into the value at `key` using the configured merge operator,
+    /// returning the previous value.
+    ///
+    /// Returns `Error::Unsupported` if no merge operator has been set.
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error>;
 
     /// Applies a batch of operations to the database atomically.
-    fn apply_batch<B: Batch>(&self, batch: B) -> Result<(), Error>;
+    ///
+    /// Either every operation in the batch is applied, or none of them are.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error>;
 
     /// Returns true if the database contains a value for the specified key.
-    fn contains_key(&self, key: &K) -> Result<bool, Error>;
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error>;
+
+    /// Returns a double-ended iterator over every key-value pair in the database,
+    /// ordered by key.
+    fn iter(&self) -> Result<Iter<'_>, Error>;
+
+    /// Returns a double-ended iterator over the key-value pairs whose keys
+    /// fall within `range`, ordered by key.
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>;
+
+    /// Returns a double-ended iterator over the key-value pairs whose keys
+    /// start with `prefix`, ordered by key.
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error>;
+
+    /// Removes every key-value pair whose key starts with `prefix`,
+    /// returning the number of removed pairs.
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error>;
+
+    /// Returns the key-value pair with the largest key that is smaller than `key`.
+    ///
+    /// Backends without an ordering return `Error::Unsupported`.
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    /// Returns the key-value pair with the smallest key that is larger than `key`.
+    ///
+    /// Backends without an ordering return `Error::Unsupported`.
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    /// Returns the key-value pair with the smallest key in the database.
+    ///
+    /// Backends without an ordering return `Error::Unsupported`.
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    /// Returns the key-value pair with the largest key in the database.
+    ///
+    /// Backends without an ordering return `Error::Unsupported`.
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    /// Removes and returns the key-value pair with the smallest key in the database.
+    ///
+    /// Backends without an ordering return `Error::Unsupported`.
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    /// Removes and returns the key-value pair with the largest key in the database.
+    ///
+    /// Backends without an ordering return `Error::Unsupported`.
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error>;
+
+    /// Returns a read-only view of the database as it is right now.
+    ///
+    /// Writes made after the snapshot was taken are not visible through it.
+    fn snapshot(&self) -> Result<Self::Snapshot, Error>;
+
+    /// Subscribes to every insert and removal of keys starting with `prefix`.
+    ///
+    /// An empty prefix watches the whole database. The returned `Subscriber`
+    /// blocks while waiting for the next event.
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error>;
+}
+
+/// Read-only view of a database at a single point in time, returned by
+/// `AymrDatabase::snapshot`.
+pub trait AymrSnapshot<K>
+where
+    K: AsRef<[u8]>,
+{
+    /// Returns the number of elements in the snapshot.
+    fn len(&self) -> usize;
+
+    /// Returns true if the snapshot is empty, false otherwise.
+    fn is_empty(&self) -> Result<bool, Error>;
+
+    /// Retrieves a value from the snapshot if it exists.
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error>;
+
+    /// Returns true if the snapshot contains a value for the specified key.
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error>;
+
+    /// Returns a double-ended iterator over every key-value pair in the snapshot,
+    /// ordered by key.
+    fn iter(&self) -> Result<Iter<'_>, Error>;
+
+    /// Returns a double-ended iterator over the key-value pairs whose keys
+    /// fall within `range`, ordered by key.
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>;
+}
+
+/// Trait implemented by databases that support serializable multi-key transactions.
+pub trait AymrTransactional<K, V>: AymrDatabase<K, V> + Sized
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    /// Runs `f` as a single serializable transaction.
+    ///
+    /// If `f` conflicts with a concurrent writer it is run again, so it should
+    /// not have side effects outside of the transaction. Retries have no limit,
+    /// so an `f` that always returns `ConflictableTransactionError::Conflict`
+    /// never returns. Returning
+    /// `ConflictableTransactionError::Abort` from `f` stops the transaction
+    /// without writing anything. Backends that can't provide transactions
+    /// return `TransactionError::Storage(Error::Unsupported(..))`.
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>;
+}
+
+/// Trait implemented by databases that can hold multiple named keyspaces, called trees.
+///
+/// Every tree behaves like a separate database, accessed through its handle.
+pub trait AymrTrees<K, V>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    /// Handle to a single tree.
+    type Tree: AymrDatabase<K, V>;
+
+    /// Opens the tree called `name`, creating it if it does not exist yet.
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error>;
+
+    /// Drops the tree called `name` along with all of its contents, returning
+    /// true if it existed.
+    ///
+    /// Operations on handles to a dropped tree return `Error::CollectionNotFound`.
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error>;
+
+    /// Returns the names of every tree in the database.
+    fn tree_names(&self) -> Vec<InlineArray>;
 }
 
 /// A trait representing a batch of operations to be applied to a database.
-pub trait Batch {
+pub trait Batch<K, V> {
     /// Adds a clear operation to the batch.
     fn clear(&mut self);
 
     /// Adds an insert operation to the batch.
-    fn insert<K, V>(&mut self, key: K, value: V)
-    where
-        K: AsRef<[u8]>,
-        V: AsRef<[u8]>;
+    fn insert(&mut self, key: K, value: V);
 
     /// Adds a remove operation to the batch.
-    fn remove<K: AsRef<[u8]>>(&mut self, key: K);
+    fn remove(&mut self, key: K);
+}
+
+/// A single operation queued inside of an `AymrBatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp<K, V> {
+    /// Remove every value from the database.
+    Clear,
+    /// Insert `value` at `key`.
+    Insert(K, V),
+    /// Remove the value at `key`.
+    Remove(K),
+}
+
+/// Concrete batch type accepted by `AymrDatabase::apply_batch`.
+///
+/// Operations are recorded in the order they were added, and backends
+/// walk them in that same order when applying the batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AymrBatch<K, V> {
+    ops: Vec<BatchOp<K, V>>,
+}
+
+impl<K, V> AymrBatch<K, V> {
+    /// Creates a new, empty batch.
+    pub fn new() -> Self {
+        AymrBatch { ops: Vec::new() }
+    }
+
+    /// Returns the number of operations queued in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns true if no operations have been queued.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Returns an iterator over the queued operations, in order.
+    pub fn iter(&self) -> std::slice::Iter<'_, BatchOp<K, V>> {
+        self.ops.iter()
+    }
+}
+
+impl<K, V> Default for AymrBatch<K, V> {
+    fn default() -> Self {
+        AymrBatch::new()
+    }
+}
+
+impl<K, V> Batch<K, V> for AymrBatch<K, V> {
+    fn clear(&mut self) {
+        self.ops.push(BatchOp::Clear);
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        self.ops.push(BatchOp::Insert(key, value));
+    }
+
+    fn remove(&mut self, key: K) {
+        self.ops.push(BatchOp::Remove(key));
+    }
+}
+
+impl<K, V> IntoIterator for AymrBatch<K, V> {
+    type Item = BatchOp<K, V>;
+    type IntoIter = std::vec::IntoIter<BatchOp<K, V>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a AymrBatch<K, V> {
+    type Item = &'a BatchOp<K, V>;
+    type IntoIter = std::slice::Iter<'a, BatchOp<K, V>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.iter()
+    }
 }
 
 /// Trait implemented by databases that can flush to disk.
@@ -63,6 +423,5 @@ pub trait AymrFlush {
     fn flush(&self) -> Result<(), Error>;
 }
 
-/// Marker trait to denote that a type is designed to work
-/// with Aymr.
-pub trait AymrConfig {}
+/// Config a database is opened with, see `AymrOpenable`.
+pub trait AymrConfig: Clone + fmt::Debug {}
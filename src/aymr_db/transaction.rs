@@ -0,0 +1,281 @@
+//! `Aymr transactions`
+//!
+//! Serializable multi-key transactions, modeled after sled's `Transactional`.
+//!
+//! A transaction is a closure that reads and writes through a `Transaction`
+//! handle. Writes are buffered inside of the handle, and reads see the
+//! transaction's own writes. Once the closure returns, every value it read is
+//! checked against the database. If any of them changed in the meantime the
+//! transaction conflicted, and the closure is run again from scratch, with no
+//! limit on the number of retries. Otherwise the buffered writes are applied as
+//! a single atomic batch.
+//!
+//! The closure can end the transaction early by returning
+//! `ConflictableTransactionError::Abort`, in which case nothing is written.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{
+        self,
+        Display,
+    },
+    marker::PhantomData,
+    mem,
+};
+
+use crate::aymr_db::{
+    error::Error,
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        Batch,
+        InlineArray,
+    },
+};
+
+/// Result of a transaction closure.
+pub type ConflictableTransactionResult<T, E = ()> = Result<T, ConflictableTransactionError<E>>;
+
+/// Result of running a transaction to completion.
+pub type TransactionResult<T, E = ()> = Result<T, TransactionError<E>>;
+
+/// Error that can be returned from inside of a transaction closure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictableTransactionError<E = ()> {
+    /// The user aborted the transaction, nothing will be written.
+    Abort(E),
+    /// The transaction conflicted with a concurrent one and will be retried.
+    Conflict,
+    /// The underlying database failed.
+    Storage(Error),
+}
+
+/// Error returned once a transaction has stopped running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError<E = ()> {
+    /// The user aborted the transaction, nothing was written.
+    Abort(E),
+    /// The underlying database failed.
+    Storage(Error),
+}
+
+impl<E> From<Error> for ConflictableTransactionError<E> {
+    fn from(error: Error) -> Self {
+        ConflictableTransactionError::Storage(error)
+    }
+}
+
+impl<E> From<Error> for TransactionError<E> {
+    fn from(error: Error) -> Self {
+        TransactionError::Storage(error)
+    }
+}
+
+impl<E: Display> Display for TransactionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::Abort(e) => write!(f, "Transaction aborted: {}", e),
+            TransactionError::Storage(e) => write!(f, "Transaction failed: {}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug + Display> std::error::Error for TransactionError<E> {}
+
+/// Reads a key from the database a transaction runs against.
+type Read<'a> = dyn Fn(&[u8]) -> Result<Option<InlineArray>, Error> + 'a;
+
+/// Handle used to read and write inside of a transaction.
+///
+/// `D` is the database the transaction was started on. Reads go through
+/// `read`, so a wrapper like `AymrDb` can hand the transaction of the backend
+/// it wraps to its own callers.
+pub struct Transaction<'a, K, V, D> {
+    read: &'a Read<'a>,
+    /// Every key read from the database, with the value it had at the time.
+    reads: BTreeMap<InlineArray, (K, Option<InlineArray>)>,
+    /// Buffered writes, where `None` removes the key.
+    writes: BTreeMap<InlineArray, (K, Option<V>)>,
+    _db: PhantomData<fn() -> D>,
+}
+
+impl<'a, K, V, D> Transaction<'a, K, V, D>
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    pub(crate) fn new(read: &'a Read<'a>) -> Self {
+        Transaction {
+            read,
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+            _db: PhantomData,
+        }
+    }
+
+    /// Moves the read and write sets into a transaction on the database `W`
+    /// wrapping `D`, reading through the same database.
+    ///
+    /// The sets have to be handed back with `restore` once the wrapper's
+    /// caller is done with them.
+    pub(crate) fn rebind<W>(&mut self) -> Transaction<'a, K, V, W> {
+        Transaction {
+            read: self.read,
+            reads: mem::take(&mut self.reads),
+            writes: mem::take(&mut self.writes),
+            _db: PhantomData,
+        }
+    }
+
+    /// Takes back the read and write sets moved out by `rebind`.
+    pub(crate) fn restore<W>(&mut self, rebound: Transaction<'a, K, V, W>) {
+        self.reads = rebound.reads;
+        self.writes = rebound.writes;
+    }
+
+    /// Retrieves a value, seeing writes made earlier in this transaction.
+    pub fn get(&mut self, key: &K) -> Result<Option<InlineArray>, Error> {
+        if let Some((_, value)) = self.writes.get(key.as_ref()) {
+            return Ok(value.as_ref().map(|v| InlineArray::from(v.as_ref())));
+        }
+        if let Some((_, value)) = self.reads.get(key.as_ref()) {
+            return Ok(value.clone());
+        }
+
+        let value = (self.read)(key.as_ref())?;
+        self.reads
+            .insert(key.as_ref().into(), (key.clone(), value.clone()));
+        Ok(value)
+    }
+
+    /// Returns true if the key has a value, seeing writes made earlier in this transaction.
+    pub fn contains_key(&mut self, key: &K) -> Result<bool, Error> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    /// Inserts a key-value pair, returning the old value if it was set.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+        let old = self.get(&key)?;
+        self.writes.insert(key.as_ref().into(), (key, Some(value)));
+        Ok(old)
+    }
+
+    /// Removes a key-value pair, returning the old value if it existed.
+    pub fn remove(&mut self, key: K) -> Result<Option<InlineArray>, Error> {
+        let old = self.get(&key)?;
+        self.writes.insert(key.as_ref().into(), (key, None));
+        Ok(old)
+    }
+
+    pub(crate) fn into_commit(self) -> PendingCommit<K, V> {
+        PendingCommit {
+            reads: self.reads.into_values().collect(),
+            writes: self.writes.into_values().collect(),
+        }
+    }
+}
+
+/// Read and write sets of a transaction whose closure finished successfully.
+pub(crate) struct PendingCommit<K, V> {
+    reads: Vec<(K, Option<InlineArray>)>,
+    writes: Vec<(K, Option<V>)>,
+}
+
+impl<K, V> PendingCommit<K, V>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    /// Validates the read set against `db` and applies the writes if it still holds.
+    ///
+    /// Returns false if the transaction conflicted. Callers must make sure no
+    /// other writer can touch `db` for the duration of this call.
+    pub(crate) fn apply_to<D: AymrDatabase<K, V>>(self, db: &mut D) -> Result<bool, Error> {
+        for (key, seen) in &self.reads {
+            if db.get(key)? != *seen {
+                return Ok(false);
+            }
+        }
+
+        let mut batch = AymrBatch::new();
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => batch.insert(key, value),
+                None => batch.remove(key),
+            }
+        }
+        db.apply_batch(batch)?;
+
+        Ok(true)
+    }
+
+    /// Returns every key the transaction read, with the value it saw.
+    ///
+    /// Backends with native transactions validate these themselves.
+    #[cfg_attr(
+        not(any(
+            feature = "sled_pre",
+            feature = "sled_alpha",
+            feature = "redb",
+            feature = "fjall",
+            feature = "lmdb"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn reads(&self) -> &[(K, Option<InlineArray>)] {
+        &self.reads
+    }
+
+    /// Returns the buffered writes, where `None` removes the key.
+    #[cfg_attr(
+        not(any(
+            feature = "sled_pre",
+            feature = "sled_alpha",
+            feature = "redb",
+            feature = "fjall",
+            feature = "lmdb"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn writes(&self) -> &[(K, Option<V>)] {
+        &self.writes
+    }
+}
+
+/// Runs `f` until it either commits or fails with something other than a conflict.
+///
+/// `commit` gets called with the pending commit of every successful run of `f`,
+/// and returns false if it detected a conflict. Like sled, conflicts are
+/// retried without limit.
+pub(crate) fn run<K, V, D, F, A, E, C>(
+    db: &mut D,
+    mut f: F,
+    mut commit: C,
+) -> TransactionResult<A, E>
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+    D: AymrDatabase<K, V>,
+    F: FnMut(&mut Transaction<'_, K, V, D>) -> ConflictableTransactionResult<A, E>,
+    C: FnMut(&mut D, PendingCommit<K, V>) -> Result<bool, Error>,
+{
+    loop {
+        let read = |key: &[u8]| db.get(key);
+        let mut tx = Transaction::new(&read);
+        let res = f(&mut tx);
+        let pending = tx.into_commit();
+
+        match res {
+            Ok(ret) => {
+                if commit(db, pending)? {
+                    return Ok(ret);
+                }
+            }
+            Err(ConflictableTransactionError::Conflict) => {}
+            Err(ConflictableTransactionError::Abort(e)) => return Err(TransactionError::Abort(e)),
+            Err(ConflictableTransactionError::Storage(e)) => {
+                return Err(TransactionError::Storage(e))
+            }
+        }
+    }
+}
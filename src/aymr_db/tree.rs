@@ -0,0 +1,383 @@
+//! `Aymr trees`
+//!
+//! Trees are named keyspaces living inside of a single database. Each tree
+//! behaves like a database of its own, and is accessed through a handle
+//! implementing `AymrDatabase`.
+//!
+//! Backends with native support for multiple keyspaces map trees onto them.
+//! Backends without it, like the in-memory ones, keep one backend instance per
+//! name in a `TreeRegistry` and hand out `AymrTree` handles to them.
+//!
+//! Dropping a tree invalidates every handle to it. Any further operation on
+//! such a handle returns `Error::CollectionNotFound`.
+
+use std::{
+    collections::{
+        btree_map::Entry,
+        BTreeMap,
+    },
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    sync::{
+        Arc,
+        RwLock,
+    },
+    time::Duration,
+};
+
+use crate::aymr_db::{
+    error::{
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::{
+        self,
+        Iter,
+    },
+    merge::MergeOperator,
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrOpenable,
+        AymrTransactional,
+        InlineArray,
+    },
+    transaction::{
+        self,
+        ConflictableTransactionResult,
+        PendingCommit,
+        Transaction,
+        TransactionResult,
+    },
+    watch::Subscriber,
+};
+
+/// Backend whose trees are kept in a `TreeRegistry`.
+///
+/// Scans of a tree are started under the tree lock but must not borrow the
+/// tree, so they can go on once the lock is released.
+pub(crate) trait RegistryBackend {
+    /// Returns the live entries within `bounds`, in key order.
+    ///
+    /// The iterator sees the tree as it was when this was called, like a
+    /// snapshot.
+    fn scan(&self, bounds: (Bound<&[u8]>, Bound<&[u8]>)) -> Iter<'static>;
+}
+
+/// Shared slot holding the contents of a tree, or `None` once it was dropped.
+type TreeSlot<B> = Arc<RwLock<Option<B>>>;
+
+/// Handle to a named tree of an in-memory backend.
+///
+/// Handles are cheap to clone, and every clone refers to the same tree.
+#[derive(Debug, Clone)]
+pub struct AymrTree<B> {
+    name: InlineArray,
+    tree: TreeSlot<B>,
+}
+
+impl<B> AymrTree<B> {
+    /// Returns the name of this tree.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    fn not_found(&self) -> Error {
+        Error::CollectionNotFound(String::from_utf8_lossy(&self.name).into_owned())
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&B) -> Result<T, Error>) -> Result<T, Error> {
+        let guard = self.tree.read().map_err(|_| poisoned())?;
+        match guard.as_ref() {
+            Some(tree) => f(tree),
+            None => Err(self.not_found()),
+        }
+    }
+
+    fn with_mut<T>(&self, f: impl FnOnce(&mut B) -> Result<T, Error>) -> Result<T, Error> {
+        let mut guard = self.tree.write().map_err(|_| poisoned())?;
+        match guard.as_mut() {
+            Some(tree) => f(tree),
+            None => Err(self.not_found()),
+        }
+    }
+
+    /// Validates and applies the writes of a transaction under the write lock,
+    /// returning false if it conflicted.
+    pub(crate) fn commit<K, V>(&self, pending: PendingCommit<K, V>) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        B: AymrDatabase<K, V>,
+    {
+        self.with_mut(|tree| pending.apply_to(tree))
+    }
+}
+
+fn poisoned() -> Error {
+    Error::ReportableBug("tree lock poisoned by a panicking thread".to_string())
+}
+
+impl<K, V, B> AymrDatabase<K, V> for AymrTree<B>
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+    B: AymrDatabase<K, V> + RegistryBackend,
+{
+    type Snapshot = B::Snapshot;
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.with_mut(|tree| tree.clear())
+    }
+
+    /// Returns 0 if the tree has been dropped.
+    fn len(&self) -> usize {
+        self.with(|tree| Ok(tree.len())).unwrap_or(0)
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        self.with(|tree| tree.is_empty())
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.with(|tree| tree.get(key))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+        self.with_mut(|tree| tree.insert(key, value))
+    }
+
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.with_mut(|tree| tree.remove(key))
+    }
+
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        self.with_mut(|tree| tree.insert_with_ttl(key, value, ttl))
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        self.with(|tree| tree.ttl(key))
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        self.with_mut(|tree| tree.purge_expired())
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.with_mut(|tree| tree.compare_and_swap(key, old, new))
+    }
+
+    /// Does nothing if the tree has been dropped.
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        let _ = self.with_mut(|tree| {
+            tree.set_merge_operator(merge_operator);
+            Ok(())
+        });
+    }
+
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        self.with_mut(|tree| tree.merge(key, operand))
+    }
+
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        self.with_mut(|tree| tree.apply_batch(batch))
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        self.with(|tree| tree.contains_key(key))
+    }
+
+    // The tree lock can't outlive these calls, so scans iterate over the tree
+    // as it was when they started, the way a snapshot does.
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.with(|tree| Ok(tree.scan((Bound::Unbounded, Bound::Unbounded))))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.with(|tree| Ok(tree.scan(iter::byte_bounds(&range))))
+    }
+
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        let prefix = prefix.as_ref();
+        let end = iter::prefix_upper_bound(prefix);
+        self.with(|tree| Ok(tree.scan((Bound::Included(prefix), end.as_ref().map(Vec::as_slice)))))
+    }
+
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        self.with_mut(|tree| tree.remove_prefix(prefix))
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.with(|tree| tree.get_lt(key))
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.with(|tree| tree.get_gt(key))
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.with(|tree| tree.first())
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.with(|tree| tree.last())
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.with_mut(|tree| tree.pop_min())
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.with_mut(|tree| tree.pop_max())
+    }
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        self.with(|tree| tree.snapshot())
+    }
+
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        self.with(|tree| tree.watch_prefix(prefix))
+    }
+}
+
+/// Reads inside of the transaction only lock the tree for their own duration,
+/// so other handles can keep writing while it runs. The read set is validated
+/// under the write lock when committing, and the transaction is retried if any
+/// of the values it read changed.
+impl<K, V, B> AymrTransactional<K, V> for AymrTree<B>
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+    B: AymrDatabase<K, V> + RegistryBackend,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |handle, pending| handle.commit(pending))
+    }
+}
+
+/// Named trees of a backend without native support for them.
+///
+/// Every tree is a separate instance of the backend itself.
+#[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+pub(crate) struct TreeRegistry<B> {
+    trees: BTreeMap<InlineArray, TreeSlot<B>>,
+}
+
+#[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+impl<B: AymrOpenable> TreeRegistry<B> {
+    /// Returns a handle to the tree called `name`, creating it as a
+    /// temporary database if needed.
+    pub(crate) fn open(&mut self, name: &[u8]) -> Result<AymrTree<B>, Error> {
+        let tree = match self.trees.entry(name.into()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                entry
+                    .insert(Arc::new(RwLock::new(Some(B::temporary()?))))
+                    .clone()
+            }
+        };
+
+        Ok(AymrTree {
+            name: name.into(),
+            tree,
+        })
+    }
+}
+
+#[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+impl<B> TreeRegistry<B> {
+    /// Drops the tree called `name`, invalidating all of its handles.
+    ///
+    /// Returns true if the tree existed.
+    pub(crate) fn drop_tree(&mut self, name: &[u8]) -> Result<bool, Error> {
+        match self.trees.remove(name) {
+            Some(tree) => {
+                *tree.write().map_err(|_| poisoned())? = None;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the names of every tree, in order.
+    pub(crate) fn names(&self) -> Vec<InlineArray> {
+        self.trees.keys().cloned().collect()
+    }
+}
+
+impl<B> Default for TreeRegistry<B> {
+    fn default() -> Self {
+        TreeRegistry {
+            trees: BTreeMap::new(),
+        }
+    }
+}
+
+impl<B> std::fmt::Debug for TreeRegistry<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.trees.keys().map(|name| String::from_utf8_lossy(name)))
+            .finish()
+    }
+}
+
+/// Cloning a registry deep copies every tree, so the clone does not share
+/// any state with the original.
+impl<B: Clone> Clone for TreeRegistry<B> {
+    fn clone(&self) -> Self {
+        let trees = self
+            .trees
+            .iter()
+            .map(|(name, tree)| {
+                let contents = tree.read().unwrap_or_else(|e| e.into_inner()).clone();
+                (name.clone(), Arc::new(RwLock::new(contents)))
+            })
+            .collect();
+
+        TreeRegistry { trees }
+    }
+}
+
+impl<B: PartialEq> PartialEq for TreeRegistry<B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.trees.len() == other.trees.len()
+            && self
+                .trees
+                .iter()
+                .zip(other.trees.iter())
+                .all(|((l_name, l), (r_name, r))| {
+                    l_name == r_name
+                        && (Arc::ptr_eq(l, r)
+                            || *l.read().unwrap_or_else(|e| e.into_inner())
+                                == *r.read().unwrap_or_else(|e| e.into_inner()))
+                })
+    }
+}
+
+impl<B: Eq> Eq for TreeRegistry<B> {}
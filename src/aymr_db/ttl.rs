@@ -0,0 +1,349 @@
+//! `Aymr expiry`
+//!
+//! Keys inserted with `AymrDatabase::insert_with_ttl` expire once their time
+//! to live has passed. Expired keys are invisible to every read right away,
+//! and get removed for good by the next write or by `purge_expired`.
+//!
+//! A `Sweeper` can call `purge_expired` periodically from a background thread,
+//! so memory held by expired keys is released even if nothing writes to the
+//! database.
+//!
+//! In-memory backends keep deadlines in an `ExpiryIndex` next to their data.
+//! On-disk backends keep them in a reserved tree of their own as `Deadline`s,
+//! which carry over restarts.
+
+#[cfg(any(feature = "btreemap", feature = "hashmap"))]
+use std::ops::Bound;
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+use std::{
+    sync::{
+        mpsc::{
+            self,
+            RecvTimeoutError,
+            Sender,
+        },
+        Arc,
+        Mutex,
+    },
+    thread::{
+        self,
+        JoinHandle,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+#[cfg(any(feature = "btreemap", feature = "hashmap"))]
+use imbl::{
+    OrdMap,
+    OrdSet,
+};
+
+use crate::aymr_db::traits::AymrDatabase;
+#[cfg(any(feature = "btreemap", feature = "hashmap"))]
+use crate::aymr_db::traits::InlineArray;
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+use crate::aymr_db::{
+    error::Error,
+    iter::Entry,
+};
+
+/// Deadlines of every key that has a time to live.
+///
+/// Both maps are persistent, so cloning the index is cheap and a clone only
+/// copies the nodes it changes afterwards.
+#[cfg(any(feature = "btreemap", feature = "hashmap"))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ExpiryIndex {
+    deadlines: OrdMap<InlineArray, Instant>,
+    /// The same deadlines ordered by time.
+    queue: OrdSet<(Instant, InlineArray)>,
+}
+
+#[cfg(any(feature = "btreemap", feature = "hashmap"))]
+impl ExpiryIndex {
+    /// Returns true if no key has a deadline.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+
+    /// Sets the deadline of `key`, replacing any previous one.
+    pub(crate) fn set(&mut self, key: InlineArray, deadline: Instant) {
+        self.unset(&key);
+        self.deadlines.insert(key.clone(), deadline);
+        self.queue.insert((deadline, key));
+    }
+
+    /// Removes the deadline of `key`, if it has one.
+    pub(crate) fn unset(&mut self, key: &[u8]) {
+        if let Some((key, deadline)) = self.deadlines.remove_with_key(key) {
+            self.queue.remove(&(deadline, key));
+        }
+    }
+
+    /// Removes the deadline of every key starting with `prefix`.
+    pub(crate) fn unset_prefix(&mut self, prefix: &[u8]) {
+        let keys: Vec<_> = self
+            .deadlines
+            .range::<_, [u8]>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.unset(&key);
+        }
+    }
+
+    /// Returns the time `key` has left to live, or `None` if it has no
+    /// deadline or already expired.
+    pub(crate) fn ttl(&self, key: &[u8], now: Instant) -> Option<Duration> {
+        self.deadlines
+            .get(key)
+            .filter(|deadline| **deadline > now)
+            .map(|deadline| *deadline - now)
+    }
+
+    /// Returns true if `key` had a deadline that has passed by `now`.
+    pub(crate) fn is_expired(&self, key: &[u8], now: Instant) -> bool {
+        !self.is_empty()
+            && self
+                .deadlines
+                .get(key)
+                .is_some_and(|deadline| *deadline <= now)
+    }
+
+    /// Returns how many keys have expired by `now`.
+    pub(crate) fn expired_count(&self, now: Instant) -> usize {
+        self.queue
+            .iter()
+            .take_while(|(deadline, _)| *deadline <= now)
+            .count()
+    }
+
+    /// Returns true if at least one key has expired by `now`.
+    pub(crate) fn has_expired(&self, now: Instant) -> bool {
+        self.queue
+            .get_min()
+            .is_some_and(|(deadline, _)| *deadline <= now)
+    }
+
+    /// Removes and returns every key that has expired by `now`.
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Vec<InlineArray> {
+        let mut expired = Vec::new();
+        while self.has_expired(now) {
+            if let Some((_, key)) = self.queue.remove_min() {
+                self.deadlines.remove(&key);
+                expired.push(key);
+            }
+        }
+
+        expired
+    }
+}
+
+/// Turns a time to live into a deadline, saturating far in the future.
+#[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+pub(crate) fn deadline_after(ttl: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(ttl)
+        .unwrap_or_else(|| now + Duration::from_secs(u32::MAX as u64))
+}
+
+/// Deadline of a key kept on disk, in milliseconds since the Unix epoch.
+///
+/// Every tree keeps the deadlines of its keys in the reserved tree of its
+/// database, each under the tree's `deadline_prefix` followed by the key.
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Deadline(u64);
+
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+impl Deadline {
+    pub(crate) fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Deadline(millis(since_epoch))
+    }
+
+    /// Turns a time to live into a deadline, saturating far in the future.
+    pub(crate) fn after(ttl: Duration) -> Self {
+        Deadline(Self::now().0.saturating_add(millis(ttl)))
+    }
+
+    /// Reads a deadline written by `to_bytes`.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = bytes
+            .try_into()
+            .map_err(|_| Error::corruption(Some("key deadline".to_string())))?;
+        Ok(Deadline(u64::from_be_bytes(bytes)))
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
+    /// Returns true if the deadline is `now` or earlier.
+    pub(crate) fn has_passed(self, now: Deadline) -> bool {
+        self <= now
+    }
+
+    /// Returns the time left until the deadline, or `None` if it has passed.
+    pub(crate) fn left(self, now: Deadline) -> Option<Duration> {
+        (!self.has_passed(now)).then(|| Duration::from_millis(self.0 - now.0))
+    }
+}
+
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Returns the prefix of the keys of the deadlines of the tree called `tree`.
+///
+/// The length of the name comes first, so no tree's prefix starts with
+/// another's.
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+pub(crate) fn deadline_prefix(tree: &[u8]) -> Vec<u8> {
+    let len = u32::try_from(tree.len()).unwrap_or(u32::MAX);
+    let mut prefix = len.to_be_bytes().to_vec();
+    prefix.extend_from_slice(tree);
+    prefix
+}
+
+/// Returns the key of the deadline of `key`, given the `deadline_prefix` of
+/// its tree.
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+pub(crate) fn deadline_key(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    [prefix, key].concat()
+}
+
+/// Skips the entries whose deadline, as found by `deadline`, has passed.
+#[cfg(any(
+    feature = "sled_pre",
+    feature = "sled_alpha",
+    feature = "redb",
+    feature = "fjall",
+    feature = "lmdb"
+))]
+pub(crate) fn unexpired<I, F>(
+    entries: I,
+    deadline: F,
+) -> impl DoubleEndedIterator<Item = Result<Entry, Error>>
+where
+    I: DoubleEndedIterator<Item = Result<Entry, Error>>,
+    F: Fn(&[u8]) -> Result<Option<Deadline>, Error>,
+{
+    let now = Deadline::now();
+    entries.filter_map(move |entry| {
+        let expired = match &entry {
+            Ok((key, _)) => deadline(key).map(|d| d.is_some_and(|d| d.has_passed(now))),
+            Err(_) => Ok(false),
+        };
+        match expired {
+            Ok(true) => None,
+            Ok(false) => Some(entry),
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Background thread purging expired keys of a shared database.
+///
+/// The thread only holds a weak reference to the database, and stops once
+/// the `Sweeper` or the database is dropped.
+#[derive(Debug)]
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    /// Starts calling `purge_expired` on `db` every `interval`.
+    pub fn spawn<K, V, D>(db: &Arc<Mutex<D>>, interval: Duration) -> Self
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        D: AymrDatabase<K, V> + Send + 'static,
+    {
+        let db = Arc::downgrade(db);
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Some(db) = db.upgrade() else {
+                    return;
+                };
+                let Ok(mut db) = db.lock() else {
+                    return;
+                };
+                if db.purge_expired().is_err() {
+                    return;
+                }
+            }
+        });
+
+        Sweeper {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up right away.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
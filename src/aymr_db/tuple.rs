@@ -0,0 +1,503 @@
+//! `Aymr tuple keys`
+//!
+//! Encodes tuples of values into bytes that sort the same way the tuples do,
+//! modelled on FoundationDB's tuple layer. Storing composite keys like
+//! `(user_id, timestamp)` this way makes ranges and prefix scans over them
+//! work on every ordered backend.
+//!
+//! Every element starts with a type code, so values of different types sort
+//! by type first:
+//!
+//! - Byte strings (`0x01`) and strings (`0x02`) are stored with `0x00`
+//!   escaped as `0x00 0xFF` and end with `0x00`.
+//! - Nested tuples start with `0x05` and end with `0x00`.
+//! - Integers (`0x0C..=0x1C`) are stored big-endian in as few bytes as
+//!   possible, with the length in the type code, and negative ones in ones'
+//!   complement. Signed and unsigned integers share this encoding, so they
+//!   compare with each other.
+//! - Floats (`0x20`) and doubles (`0x21`) are stored big-endian with the sign
+//!   bit flipped, or every bit flipped if they are negative.
+//! - `false` is `0x26` and `true` is `0x27`.
+//! - UUIDs (`0x30`), with the `uuid` feature, are stored as their 16 bytes.
+//!
+//! No encoding is a prefix of another, so `pack(&(a,))` is a prefix of
+//! `pack(&(a, b))` for any `b`, and can be passed to `scan_prefix`.
+
+#[cfg(feature = "uuid")]
+use uuid::Uuid;
+
+use crate::aymr_db::error::Error;
+
+const BYTES: u8 = 0x01;
+const STRING: u8 = 0x02;
+const NESTED: u8 = 0x05;
+const INT_ZERO: u8 = 0x14;
+const FLOAT: u8 = 0x20;
+const DOUBLE: u8 = 0x21;
+const FALSE: u8 = 0x26;
+const TRUE: u8 = 0x27;
+#[cfg(feature = "uuid")]
+const UUID: u8 = 0x30;
+
+/// Terminates byte strings, strings and nested tuples.
+const END: u8 = 0x00;
+/// Follows an escaped `0x00` inside byte strings and strings.
+const ESCAPE: u8 = 0xFF;
+
+/// A value that can be an element of a tuple.
+pub trait Element: Sized {
+    /// Appends the encoding of `self`, type code included, to `out`.
+    fn pack_into(&self, out: &mut Vec<u8>);
+
+    /// Decodes one element from the front of `input`, and advances past it.
+    fn unpack_from(input: &mut &[u8]) -> Result<Self, Error>;
+}
+
+/// A tuple that can be packed into a key.
+///
+/// This is implemented for Rust tuples of up to eight `Element`s, and for
+/// single elements, which pack like a tuple of one.
+pub trait Pack: Sized {
+    /// Appends the encodings of the elements of `self` to `out`.
+    fn pack_elements(&self, out: &mut Vec<u8>);
+
+    /// Decodes the elements of a tuple from the front of `input`, and
+    /// advances past them.
+    fn unpack_elements(input: &mut &[u8]) -> Result<Self, Error>;
+}
+
+/// Encodes `tuple` into an order-preserving key.
+pub fn pack<T: Pack>(tuple: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    tuple.pack_elements(&mut out);
+    out
+}
+
+/// Decodes a key made by `pack`, failing with `Error::Decode` if it holds
+/// anything but a `T`.
+pub fn unpack<T: Pack>(mut bytes: &[u8]) -> Result<T, Error> {
+    let tuple = T::unpack_elements(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(Error::Decode(format!(
+            "{} trailing bytes after tuple",
+            bytes.len()
+        )));
+    }
+
+    Ok(tuple)
+}
+
+fn take_byte(input: &mut &[u8]) -> Result<u8, Error> {
+    let (&byte, rest) = input
+        .split_first()
+        .ok_or_else(|| Error::Decode("unexpected end of tuple".to_string()))?;
+    *input = rest;
+    Ok(byte)
+}
+
+fn take_bytes<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if input.len() < len {
+        return Err(Error::Decode("unexpected end of tuple".to_string()));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+fn expect_code(input: &mut &[u8], expected: u8, what: &str) -> Result<(), Error> {
+    let code = take_byte(input)?;
+    if code != expected {
+        return Err(unexpected_code(code, what));
+    }
+    Ok(())
+}
+
+fn unexpected_code(code: u8, what: &str) -> Error {
+    Error::Decode(format!("expected {}, found type code {:#04x}", what, code))
+}
+
+fn pack_escaped(code: u8, bytes: &[u8], out: &mut Vec<u8>) {
+    out.push(code);
+    for &byte in bytes {
+        out.push(byte);
+        if byte == END {
+            out.push(ESCAPE);
+        }
+    }
+    out.push(END);
+}
+
+fn unpack_escaped(input: &mut &[u8], code: u8, what: &str) -> Result<Vec<u8>, Error> {
+    expect_code(input, code, what)?;
+
+    let mut bytes = Vec::new();
+    loop {
+        match take_byte(input)? {
+            END if input.first() == Some(&ESCAPE) => {
+                *input = &input[1..];
+                bytes.push(END);
+            }
+            END => return Ok(bytes),
+            byte => bytes.push(byte),
+        }
+    }
+}
+
+/// Packs an integer given by its sign and magnitude.
+fn pack_int(negative: bool, magnitude: u64, out: &mut Vec<u8>) {
+    let len = 8 - magnitude.leading_zeros() as usize / 8;
+    let bytes = if negative { !magnitude } else { magnitude }.to_be_bytes();
+
+    out.push(if negative {
+        INT_ZERO - len as u8
+    } else {
+        INT_ZERO + len as u8
+    });
+    out.extend_from_slice(&bytes[8 - len..]);
+}
+
+/// Unpacks an integer into its sign and magnitude.
+fn unpack_int(input: &mut &[u8]) -> Result<(bool, u64), Error> {
+    let code = take_byte(input)?;
+    if !(INT_ZERO - 8..=INT_ZERO + 8).contains(&code) {
+        return Err(unexpected_code(code, "an integer"));
+    }
+
+    let negative = code < INT_ZERO;
+    let len = code.abs_diff(INT_ZERO) as usize;
+    let mut bytes = [if negative { 0xFF } else { 0 }; 8];
+    bytes[8 - len..].copy_from_slice(take_bytes(input, len)?);
+
+    let value = u64::from_be_bytes(bytes);
+    Ok((negative, if negative { !value } else { value }))
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {$(
+        impl Element for $t {
+            fn pack_into(&self, out: &mut Vec<u8>) {
+                pack_int(false, *self as u64, out);
+            }
+
+            fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+                match unpack_int(input)? {
+                    (false, magnitude) => <$t>::try_from(magnitude).ok(),
+                    (true, _) => None,
+                }
+                .ok_or_else(|| {
+                    Error::Decode(format!("integer out of range for {}", stringify!($t)))
+                })
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),*) => {$(
+        impl Element for $t {
+            fn pack_into(&self, out: &mut Vec<u8>) {
+                pack_int(*self < 0, self.unsigned_abs() as u64, out);
+            }
+
+            fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+                let (negative, magnitude) = unpack_int(input)?;
+                let value = if negative {
+                    -(magnitude as i128)
+                } else {
+                    magnitude as i128
+                };
+
+                <$t>::try_from(value).map_err(|_| {
+                    Error::Decode(format!("integer out of range for {}", stringify!($t)))
+                })
+            }
+        }
+    )*};
+}
+
+impl_unsigned!(u8, u16, u32, u64, usize);
+impl_signed!(i8, i16, i32, i64, isize);
+
+macro_rules! impl_float {
+    ($($t:ty, $bits:ty, $code:expr, $what:expr);*) => {$(
+        impl Element for $t {
+            fn pack_into(&self, out: &mut Vec<u8>) {
+                let bits = self.to_bits();
+                let sign = 1 << (<$bits>::BITS - 1);
+                let bits = if bits & sign != 0 { !bits } else { bits ^ sign };
+
+                out.push($code);
+                out.extend_from_slice(&bits.to_be_bytes());
+            }
+
+            fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+                expect_code(input, $code, $what)?;
+
+                const LEN: usize = std::mem::size_of::<$bits>();
+                let mut bytes = [0; LEN];
+                bytes.copy_from_slice(take_bytes(input, LEN)?);
+
+                let bits = <$bits>::from_be_bytes(bytes);
+                let sign = 1 << (<$bits>::BITS - 1);
+                let bits = if bits & sign != 0 { bits ^ sign } else { !bits };
+                Ok(<$t>::from_bits(bits))
+            }
+        }
+    )*};
+}
+
+impl_float!(f32, u32, FLOAT, "a float"; f64, u64, DOUBLE, "a double");
+
+impl Element for bool {
+    fn pack_into(&self, out: &mut Vec<u8>) {
+        out.push(if *self { TRUE } else { FALSE });
+    }
+
+    fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+        match take_byte(input)? {
+            FALSE => Ok(false),
+            TRUE => Ok(true),
+            code => Err(unexpected_code(code, "a boolean")),
+        }
+    }
+}
+
+impl Element for String {
+    fn pack_into(&self, out: &mut Vec<u8>) {
+        pack_escaped(STRING, self.as_bytes(), out);
+    }
+
+    fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+        String::from_utf8(unpack_escaped(input, STRING, "a string")?)
+            .map_err(|e| Error::Decode(e.to_string()))
+    }
+}
+
+impl Element for Vec<u8> {
+    fn pack_into(&self, out: &mut Vec<u8>) {
+        pack_escaped(BYTES, self, out);
+    }
+
+    fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+        unpack_escaped(input, BYTES, "a byte string")
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl Element for Uuid {
+    fn pack_into(&self, out: &mut Vec<u8>) {
+        out.push(UUID);
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+        expect_code(input, UUID, "a UUID")?;
+        Ok(Uuid::from_slice(take_bytes(input, 16)?).expect("16 bytes make a UUID"))
+    }
+}
+
+/// Single elements pack like a tuple of one, so they can be keys on their own.
+macro_rules! impl_pack_element {
+    ($($t:ty),*) => {$(
+        impl Pack for $t {
+            fn pack_elements(&self, out: &mut Vec<u8>) {
+                self.pack_into(out);
+            }
+
+            fn unpack_elements(input: &mut &[u8]) -> Result<Self, Error> {
+                Self::unpack_from(input)
+            }
+        }
+    )*};
+}
+
+impl_pack_element!(
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    isize,
+    f32,
+    f64,
+    bool,
+    String,
+    Vec<u8>
+);
+#[cfg(feature = "uuid")]
+impl_pack_element!(Uuid);
+
+/// Tuples pack their elements one after the other, and nest between `0x05`
+/// and `0x00` when they are an element themselves.
+macro_rules! impl_tuple {
+    ($($name:ident: $t:ident),+) => {
+        impl<$($t: Element),+> Pack for ($($t,)+) {
+            fn pack_elements(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.pack_into(out);)+
+            }
+
+            fn unpack_elements(input: &mut &[u8]) -> Result<Self, Error> {
+                Ok(($($t::unpack_from(input)?,)+))
+            }
+        }
+
+        impl<$($t: Element),+> Element for ($($t,)+) {
+            fn pack_into(&self, out: &mut Vec<u8>) {
+                out.push(NESTED);
+                self.pack_elements(out);
+                out.push(END);
+            }
+
+            fn unpack_from(input: &mut &[u8]) -> Result<Self, Error> {
+                expect_code(input, NESTED, "a nested tuple")?;
+                let tuple = Self::unpack_elements(input)?;
+                expect_code(input, END, "the end of a nested tuple")?;
+                Ok(tuple)
+            }
+        }
+    };
+}
+
+impl_tuple!(a: A);
+impl_tuple!(a: A, b: B);
+impl_tuple!(a: A, b: B, c: C);
+impl_tuple!(a: A, b: B, c: C, d: D);
+impl_tuple!(a: A, b: B, c: C, d: D, e: E);
+impl_tuple!(a: A, b: B, c: C, d: D, e: E, f: F);
+impl_tuple!(a: A, b: B, c: C, d: D, e: E, f: F, g: G);
+impl_tuple!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aymr_db::db::tests::{
+        test_config,
+        test_kind,
+    };
+
+    #[test]
+    fn test_tuple_order() {
+        // Packs `values`, which must be sorted, and checks the keys sort the
+        // same way and unpack to the same values.
+        fn assert_sorted<T: Pack + PartialEq + std::fmt::Debug>(values: Vec<T>) {
+            let packed: Vec<_> = values.iter().map(pack).collect();
+            for pair in packed.windows(2) {
+                assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+            }
+            for (value, bytes) in values.iter().zip(&packed) {
+                assert_eq!(&unpack::<T>(bytes).unwrap(), value);
+            }
+        }
+
+        assert_sorted(vec![
+            i64::MIN,
+            -65536,
+            -256,
+            -255,
+            -1,
+            0,
+            1,
+            255,
+            256,
+            i64::MAX,
+        ]);
+        assert_sorted(vec![0u64, 1, 255, 256, u32::MAX as u64, u64::MAX]);
+        assert_sorted(vec![
+            f64::NEG_INFINITY,
+            -1.5,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            2.5,
+            f64::INFINITY,
+        ]);
+        assert_sorted(vec![-1.5f32, 0.0, 2.5]);
+        assert_sorted(vec![false, true]);
+        assert_sorted(vec![
+            String::new(),
+            "a".to_string(),
+            "a\0".to_string(),
+            "ab".to_string(),
+            "b".to_string(),
+        ]);
+        assert_sorted(vec![vec![], vec![0], vec![0, 0], vec![0, 1], vec![1]]);
+        #[cfg(feature = "uuid")]
+        assert_sorted(vec![Uuid::nil(), Uuid::from_u128(1), Uuid::max()]);
+        assert_sorted(vec![
+            (1u8, (String::from("a"), 2i32)),
+            (1, (String::from("a"), 10)),
+            (1, (String::from("b"), -5)),
+            (2, (String::new(), 0)),
+        ]);
+
+        // Signed and unsigned integers share their encoding.
+        assert!(pack(&-1i8) < pack(&0u8));
+        assert_eq!(unpack::<u16>(&pack(&7i64)).unwrap(), 7);
+        assert!(matches!(
+            unpack::<u8>(&pack(&256u16)),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(
+            unpack::<u32>(&pack(&-1i32)),
+            Err(Error::Decode(_))
+        ));
+        assert!(matches!(unpack::<bool>(&pack(&1u8)), Err(Error::Decode(_))));
+        assert!(matches!(
+            unpack::<(u8,)>(&pack(&(1u8, 2u8))),
+            Err(Error::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_tuple_ranges() {
+        use crate::aymr_db::{
+            codec::{
+                Str,
+                Tuple,
+            },
+            typed::{
+                TypedDb,
+                TypedIter,
+            },
+        };
+
+        let mut db =
+            TypedDb::<(u32, i64), String, Tuple, Str>::open(test_config(test_kind())).unwrap();
+        for user in [1, 2, 10] {
+            for time in [-20, -3, 0, 7, 300] {
+                db.insert(&(user, time), &format!("{}@{}", user, time))
+                    .unwrap();
+            }
+        }
+
+        let keys = |iter: TypedIter<'_, (u32, i64), String, Tuple, Str>| {
+            iter.map(|entry| entry.unwrap().0).collect::<Vec<_>>()
+        };
+
+        assert_eq!(db.len(), 15);
+
+        assert_eq!(
+            keys(db.range((2, -3)..(2, 300)).unwrap()),
+            vec![(2, -3), (2, 0), (2, 7)]
+        );
+        assert_eq!(
+            keys(db.range((1, i64::MAX)..=(10, -20)).unwrap()),
+            vec![(2, -20), (2, -3), (2, 0), (2, 7), (2, 300), (10, -20)]
+        );
+        assert_eq!(
+            keys(db.scan_prefix(pack(&(10u32,))).unwrap()),
+            vec![(10, -20), (10, -3), (10, 0), (10, 7), (10, 300)]
+        );
+        assert_eq!(
+            keys(db.scan_prefix(pack(&(1u32,))).unwrap()).len(),
+            5,
+            "the prefix of 1 doesn't match 10"
+        );
+        assert_eq!(db.scan_prefix(pack(&(3u32,))).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,377 @@
+//! `Aymr typed databases`
+//!
+//! `TypedDb` wraps a database storing bytes, and encodes keys and values on
+//! the way in and decodes them on the way out through a pair of codecs, see
+//! `codec`. Reads return the decoded values directly, and bytes the codecs
+//! can't make sense of come back as `Error::Decode`.
+//!
+//! Everything not covered by `TypedDb` is available on the wrapped database
+//! through `inner`.
+
+use std::{
+    marker::PhantomData,
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+};
+
+use crate::aymr_db::{
+    backend::AymrBackend,
+    codec::Codec,
+    config::BackendConfig,
+    error::Error,
+    iter::Iter,
+    traits::{
+        AymrDatabase,
+        AymrOpenable,
+        InlineArray,
+    },
+};
+
+/// Marks the key and value types a `TypedDb` decodes to, and their codecs.
+type Types<K, V, KC, VC> = PhantomData<fn() -> (K, V, KC, VC)>;
+
+/// Database of `K` keys and `V` values, stored in `D` through the codecs `KC`
+/// and `VC`.
+///
+/// `D` defaults to picking a backend at runtime, like `AymrDb`.
+#[derive(Debug, Clone)]
+pub struct TypedDb<K, V, KC, VC, D = AymrBackend> {
+    db: D,
+    _types: Types<K, V, KC, VC>,
+}
+
+impl<K, V, KC, VC, D> TypedDb<K, V, KC, VC, D> {
+    /// Wraps an already opened database.
+    pub fn new(db: D) -> Self {
+        TypedDb {
+            db,
+            _types: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped database.
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    /// Returns the wrapped database for writing.
+    pub fn inner_mut(&mut self) -> &mut D {
+        &mut self.db
+    }
+
+    /// Unwraps the wrapped database.
+    pub fn into_inner(self) -> D {
+        self.db
+    }
+}
+
+/// Opens a concrete backend, e.g.
+/// `<TypedDb<K, V, KC, VC, AymrBtreeMap>>::open(BtreeMapConfig {})`.
+impl<K, V, KC, VC, D: AymrOpenable> AymrOpenable for TypedDb<K, V, KC, VC, D> {
+    type Config = D::Config;
+
+    fn open(config: D::Config) -> Result<Self, Error> {
+        Ok(TypedDb::new(D::open(config)?))
+    }
+
+    fn create_new(config: D::Config) -> Result<Self, Error> {
+        Ok(TypedDb::new(D::create_new(config)?))
+    }
+
+    fn open_existing(config: D::Config) -> Result<Self, Error> {
+        Ok(TypedDb::new(D::open_existing(config)?))
+    }
+
+    fn temporary() -> Result<Self, Error> {
+        Ok(TypedDb::new(D::temporary()?))
+    }
+
+    fn close(self) -> Result<(), Error> {
+        self.db.close()
+    }
+}
+
+impl<K, V, KC, VC> TypedDb<K, V, KC, VC> {
+    /// Opens a backend from its config, see `AymrDb::open`.
+    pub fn open(config: impl Into<BackendConfig>) -> Result<Self, Error> {
+        Ok(TypedDb::new(AymrBackend::open(config.into())?))
+    }
+}
+
+// `is_empty` returns a `Result`, like `AymrDatabase::is_empty`.
+#[allow(clippy::len_without_is_empty)]
+impl<K, V, KC, VC, D> TypedDb<K, V, KC, VC, D>
+where
+    KC: Codec<K>,
+    VC: Codec<V>,
+    D: AymrDatabase<InlineArray, InlineArray>,
+{
+    /// Removes every entry.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.db.clear()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.db.len()
+    }
+
+    /// Returns true if there are no entries.
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        self.db.is_empty()
+    }
+
+    /// Retrieves the value of `key`, if it has one.
+    pub fn get(&self, key: &K) -> Result<Option<V>, Error> {
+        decode_value::<V, VC>(self.db.get(&*KC::encode(key)?)?)
+    }
+
+    /// Inserts a key-value pair, returning the old value if it was set.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<Option<V>, Error> {
+        decode_value::<V, VC>(self.db.insert(KC::encode(key)?, VC::encode(value)?)?)
+    }
+
+    /// Removes a key-value pair, returning the old value if it existed.
+    pub fn remove(&mut self, key: &K) -> Result<Option<V>, Error> {
+        decode_value::<V, VC>(self.db.remove(&*KC::encode(key)?)?)
+    }
+
+    /// Returns true if `key` has a value.
+    pub fn contains_key(&self, key: &K) -> Result<bool, Error> {
+        self.db.contains_key(&*KC::encode(key)?)
+    }
+
+    /// Returns an iterator over every entry, ordered by encoded key.
+    pub fn iter(&self) -> Result<TypedIter<'_, K, V, KC, VC>, Error> {
+        Ok(TypedIter::new(self.db.iter()?))
+    }
+
+    /// Returns an iterator over the entries whose encoded key falls within
+    /// the encoded `range`.
+    ///
+    /// This is only meaningful if `KC` encodes keys in the order they sort in.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<TypedIter<'_, K, V, KC, VC>, Error> {
+        let start = encode_bound::<K, KC>(range.start_bound())?;
+        let end = encode_bound::<K, KC>(range.end_bound())?;
+
+        Ok(TypedIter::new(
+            self.db.range::<InlineArray, _>((start, end))?,
+        ))
+    }
+
+    /// Returns an iterator over the entries whose encoded key starts with
+    /// `prefix`.
+    ///
+    /// With the `Tuple` codec, `tuple::pack(&(a,))` selects every key whose
+    /// first element is `a`.
+    pub fn scan_prefix(
+        &self,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<TypedIter<'_, K, V, KC, VC>, Error> {
+        Ok(TypedIter::new(self.db.scan_prefix(prefix.as_ref())?))
+    }
+
+    /// Returns the entry with the smallest encoded key.
+    pub fn first(&self) -> Result<Option<(K, V)>, Error> {
+        self.db
+            .first()?
+            .map(decode_entry::<K, V, KC, VC>)
+            .transpose()
+    }
+
+    /// Returns the entry with the largest encoded key.
+    pub fn last(&self) -> Result<Option<(K, V)>, Error> {
+        self.db
+            .last()?
+            .map(decode_entry::<K, V, KC, VC>)
+            .transpose()
+    }
+}
+
+/// Iterator over the decoded entries of a `TypedDb`.
+///
+/// Every item is decoded as it is yielded, so a malformed entry only fails
+/// its own item.
+pub struct TypedIter<'a, K, V, KC, VC> {
+    inner: Iter<'a>,
+    _types: Types<K, V, KC, VC>,
+}
+
+impl<'a, K, V, KC, VC> TypedIter<'a, K, V, KC, VC> {
+    fn new(inner: Iter<'a>) -> Self {
+        TypedIter {
+            inner,
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<K, V, KC, VC> Iterator for TypedIter<'_, K, V, KC, VC>
+where
+    KC: Codec<K>,
+    VC: Codec<V>,
+{
+    type Item = Result<(K, V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|entry| entry.and_then(decode_entry::<K, V, KC, VC>))
+    }
+}
+
+impl<K, V, KC, VC> DoubleEndedIterator for TypedIter<'_, K, V, KC, VC>
+where
+    KC: Codec<K>,
+    VC: Codec<V>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back()
+            .map(|entry| entry.and_then(decode_entry::<K, V, KC, VC>))
+    }
+}
+
+impl<K, V, KC, VC> std::fmt::Debug for TypedIter<'_, K, V, KC, VC> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedIter").finish_non_exhaustive()
+    }
+}
+
+fn decode_value<V, VC: Codec<V>>(value: Option<InlineArray>) -> Result<Option<V>, Error> {
+    value.map(VC::decode).transpose()
+}
+
+fn decode_entry<K, V, KC, VC>((key, value): (InlineArray, InlineArray)) -> Result<(K, V), Error>
+where
+    KC: Codec<K>,
+    VC: Codec<V>,
+{
+    Ok((KC::decode(key)?, VC::decode(value)?))
+}
+
+fn encode_bound<K, KC: Codec<K>>(bound: Bound<&K>) -> Result<Bound<InlineArray>, Error> {
+    Ok(match bound {
+        Bound::Included(key) => Bound::Included(KC::encode(key)?),
+        Bound::Excluded(key) => Bound::Excluded(KC::encode(key)?),
+        Bound::Unbounded => Bound::Unbounded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aymr_db::db::tests::{
+        test_config,
+        test_kind,
+    };
+
+    #[test]
+    fn test_typed_db() {
+        use crate::aymr_db::codec::{
+            Str,
+            Zerocopy,
+        };
+
+        let mut db = TypedDb::<u32, String, Zerocopy, Str>::open(test_config(test_kind())).unwrap();
+        assert_eq!(db.insert(&1, &"one".to_string()).unwrap(), None);
+        assert_eq!(
+            db.insert(&1, &"uno".to_string()).unwrap(),
+            Some("one".to_string())
+        );
+        db.insert(&2, &"two".to_string()).unwrap();
+
+        assert_eq!(db.get(&1).unwrap(), Some("uno".to_string()));
+        assert!(db.contains_key(&2).unwrap());
+        assert_eq!(db.len(), 2);
+
+        let mut entries: Vec<_> = db.iter().unwrap().map(Result::unwrap).collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![(1, "uno".to_string()), (2, "two".to_string())]
+        );
+
+        assert_eq!(db.remove(&1).unwrap(), Some("uno".to_string()));
+        assert_eq!(db.get(&1).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(any(feature = "bincode", feature = "json"))]
+    fn test_typed_db_serde() {
+        use serde::{
+            Deserialize,
+            Serialize,
+        };
+
+        use crate::aymr_db::codec::Str;
+
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        struct User {
+            name: String,
+            age: u8,
+        }
+
+        let user = User {
+            name: "alice".to_string(),
+            age: 30,
+        };
+        let key = "alice".to_string();
+
+        #[cfg(feature = "bincode")]
+        {
+            use crate::aymr_db::codec::Bincode;
+
+            let mut db =
+                TypedDb::<String, User, Str, Bincode>::open(test_config(test_kind())).unwrap();
+            db.insert(&key, &user).unwrap();
+            assert_eq!(db.get(&key).unwrap(), Some(user.clone()));
+        }
+
+        #[cfg(feature = "json")]
+        {
+            use crate::aymr_db::{
+                codec::Json,
+                dyn_db::DynDatabase,
+            };
+
+            let mut db =
+                TypedDb::<String, User, Str, Json>::open(test_config(test_kind())).unwrap();
+            db.insert(&key, &user).unwrap();
+            assert_eq!(db.get(&key).unwrap(), Some(user.clone()));
+            assert_eq!(
+                DynDatabase::get(db.inner(), b"alice").unwrap(),
+                Some(br#"{"name":"alice","age":30}"#.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_typed_db_decode_error() {
+        use crate::aymr_db::{
+            codec::{
+                Str,
+                Zerocopy,
+            },
+            dyn_db::DynDatabase,
+        };
+
+        let mut db =
+            TypedDb::<u32, u64, Zerocopy, Zerocopy>::open(test_config(test_kind())).unwrap();
+        DynDatabase::insert(db.inner_mut(), &1u32.to_ne_bytes(), &[1, 2, 3]).unwrap();
+        assert_eq!(
+            db.get(&1),
+            Err(Error::Decode("expected 8 bytes, found 3".to_string()))
+        );
+
+        let mut db = TypedDb::<String, String, Str, Str>::open(test_config(test_kind())).unwrap();
+        DynDatabase::insert(db.inner_mut(), b"key", &[0xff]).unwrap();
+        assert!(matches!(db.get(&"key".to_string()), Err(Error::Decode(_))));
+        assert!(matches!(
+            db.iter().unwrap().next(),
+            Some(Err(Error::Decode(_)))
+        ));
+    }
+}
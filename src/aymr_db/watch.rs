@@ -0,0 +1,305 @@
+//! `Aymr watch subscriptions`
+//!
+//! `AymrDatabase::watch_prefix` returns a `Subscriber`, which receives an
+//! `Event` for every insert or removal of a key starting with the watched
+//! prefix. Events are delivered in the order the writes were applied, and a
+//! batch only publishes its events once the whole batch went through.
+//!
+//! A `Subscriber` is a blocking iterator. It ends once the database it is
+//! watching has been dropped.
+//!
+//! Backends without subscriptions of their own keep their subscribers in a
+//! `Subscribers`, and publish every write through it.
+
+use std::{
+    sync::{
+        mpsc::{
+            self,
+            Receiver,
+            RecvTimeoutError,
+            Sender,
+        },
+        Mutex,
+        MutexGuard,
+    },
+    time::Duration,
+};
+
+use crate::aymr_db::traits::InlineArray;
+
+/// A change to a watched key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// `key` was set to `value`.
+    Insert {
+        key: InlineArray,
+        value: InlineArray,
+    },
+    /// `key` was removed.
+    Remove { key: InlineArray },
+}
+
+impl Event {
+    /// Returns the key this event is about.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Event::Insert { key, .. } | Event::Remove { key } => key,
+        }
+    }
+
+    pub(crate) fn insert(key: impl Into<InlineArray>, value: impl Into<InlineArray>) -> Self {
+        Event::Insert {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    pub(crate) fn remove(key: impl Into<InlineArray>) -> Self {
+        Event::Remove { key: key.into() }
+    }
+}
+
+/// Where a `Subscriber` gets its events from, either a `Subscribers` or the
+/// subscription of a backend that has its own.
+pub(crate) trait EventSource: Send {
+    /// Blocks until the next event arrives, or returns `None` once the
+    /// watched database has been dropped.
+    fn next(&mut self) -> Option<Event>;
+
+    /// Waits up to `timeout` for the next event.
+    fn next_timeout(&mut self, timeout: Duration) -> Result<Event, RecvTimeoutError>;
+
+    /// Returns the next event if one is already waiting, without blocking.
+    fn try_next(&mut self) -> Option<Event> {
+        self.next_timeout(Duration::ZERO).ok()
+    }
+}
+
+impl EventSource for Receiver<Event> {
+    fn next(&mut self) -> Option<Event> {
+        self.recv().ok()
+    }
+
+    fn next_timeout(&mut self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        self.recv_timeout(timeout)
+    }
+
+    fn try_next(&mut self) -> Option<Event> {
+        self.try_recv().ok()
+    }
+}
+
+/// Receives the events of a single `watch_prefix` call.
+pub struct Subscriber {
+    source: Box<dyn EventSource>,
+}
+
+impl Subscriber {
+    pub(crate) fn new(source: impl EventSource + 'static) -> Self {
+        Subscriber {
+            source: Box::new(source),
+        }
+    }
+
+    /// Waits up to `timeout` for the next event.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        self.source.next_timeout(timeout)
+    }
+
+    /// Returns the next event if one is already waiting, without blocking.
+    pub fn try_next(&mut self) -> Option<Event> {
+        self.source.try_next()
+    }
+}
+
+impl std::fmt::Debug for Subscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscriber").finish_non_exhaustive()
+    }
+}
+
+impl Iterator for Subscriber {
+    type Item = Event;
+
+    /// Blocks until the next event arrives, or returns `None` once the
+    /// watched database has been dropped.
+    fn next(&mut self) -> Option<Event> {
+        self.source.next()
+    }
+}
+
+/// Events recorded by a write before it is applied, if anyone is listening.
+#[cfg_attr(
+    not(any(
+        feature = "sled_alpha",
+        feature = "redb",
+        feature = "fjall",
+        feature = "lmdb"
+    )),
+    allow(dead_code)
+)]
+pub(crate) struct Events(Option<Vec<Event>>);
+
+#[cfg_attr(
+    not(any(
+        feature = "sled_alpha",
+        feature = "redb",
+        feature = "fjall",
+        feature = "lmdb"
+    )),
+    allow(dead_code)
+)]
+impl Events {
+    /// Returns true if the events get published, so the write has to find
+    /// out what it changes.
+    #[cfg_attr(
+        not(any(feature = "sled_alpha", feature = "fjall", feature = "lmdb")),
+        allow(dead_code)
+    )]
+    pub(crate) fn watched(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u8], value: &[u8]) {
+        if let Some(events) = &mut self.0 {
+            events.push(Event::insert(key, value));
+        }
+    }
+
+    /// Records the removal of `key`, which has to be there.
+    pub(crate) fn remove(&mut self, key: &[u8]) {
+        if let Some(events) = &mut self.0 {
+            events.push(Event::remove(key));
+        }
+    }
+}
+
+/// Every live subscription of a backend that publishes its own events.
+///
+/// Subscriptions are tied to a single database instance, so clones of a
+/// backend start out without any.
+#[cfg_attr(
+    not(any(
+        feature = "btreemap",
+        feature = "hashmap",
+        feature = "sled_alpha",
+        feature = "redb",
+        feature = "fjall",
+        feature = "lmdb"
+    )),
+    allow(dead_code)
+)]
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    watchers: Mutex<Watchers>,
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "btreemap",
+        feature = "hashmap",
+        feature = "sled_alpha",
+        feature = "redb",
+        feature = "fjall",
+        feature = "lmdb"
+    )),
+    allow(dead_code)
+)]
+impl Subscribers {
+    /// Registers a new subscription for keys starting with `prefix`.
+    pub(crate) fn subscribe(&self, prefix: &[u8]) -> Subscriber {
+        let (tx, rx) = mpsc::channel();
+        self.lock().push((prefix.into(), tx));
+        Subscriber::new(rx)
+    }
+
+    /// Returns true if nobody is listening.
+    ///
+    /// Lets backends skip building events nobody receives.
+    #[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Sends every event to the subscriptions watching its key, dropping the
+    /// ones whose `Subscriber` went away.
+    #[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+    pub(crate) fn publish(&self, events: impl IntoIterator<Item = Event>) {
+        send(&mut self.lock(), events);
+    }
+
+    /// Publishes the event built by `f`, without building it if nobody is listening.
+    #[cfg_attr(not(any(feature = "btreemap", feature = "hashmap")), allow(dead_code))]
+    pub(crate) fn publish_with(&self, f: impl FnOnce() -> Event) {
+        if !self.is_empty() {
+            self.publish([f()]);
+        }
+    }
+
+    /// Runs `write`, then publishes the events it recorded if it succeeded.
+    ///
+    /// While anyone is listening, writes hold the subscriptions locked until
+    /// their events are out, so they take turns and publish in the order
+    /// they were applied. `write` must not subscribe or publish itself.
+    #[cfg_attr(
+        not(any(
+            feature = "sled_alpha",
+            feature = "redb",
+            feature = "fjall",
+            feature = "lmdb"
+        )),
+        allow(dead_code)
+    )]
+    pub(crate) fn write<T, E>(
+        &self,
+        write: impl FnOnce(&mut Events) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let mut watchers = self.lock();
+        if watchers.is_empty() {
+            drop(watchers);
+            return write(&mut Events(None));
+        }
+
+        let mut events = Events(Some(Vec::new()));
+        let out = write(&mut events)?;
+        send(&mut watchers, events.0.unwrap_or_default());
+
+        Ok(out)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Watchers> {
+        self.watchers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+type Watchers = Vec<(InlineArray, Sender<Event>)>;
+
+/// Publishes `events` to `watchers`, which are already locked.
+fn send(watchers: &mut Watchers, events: impl IntoIterator<Item = Event>) {
+    for event in events {
+        watchers.retain(|(prefix, tx)| {
+            !event.key().starts_with(prefix) || tx.send(event.clone()).is_ok()
+        });
+    }
+}
+
+impl Clone for Subscribers {
+    fn clone(&self) -> Self {
+        Subscribers::default()
+    }
+}
+
+impl std::fmt::Debug for Subscribers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Subscribers({})", self.lock().len())
+    }
+}
+
+/// Subscriptions don't take part in comparing databases.
+impl PartialEq for Subscribers {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Subscribers {}
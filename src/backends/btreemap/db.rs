@@ -1,6 +1,6 @@
 //! `btreemap`
 //!
-//! This is a basic impl of the aymr DB traits for `std::collections::BTreeMap`.
+//! This is a basic impl of the aymr DB traits for a B-tree map, `imbl::OrdMap`.
 //!
 //! Using a BTreeMap is optimal when:
 //!
@@ -14,68 +14,455 @@
 //!
 //! Aymr btreemap does not flush to disk. Every change you have is exclusively going to be in-memory.
 
-use std::collections::BTreeMap;
+use std::{
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use imbl::OrdMap;
 
-use crate::aymr_db::{
-    error::Error,
-    traits::{
-        AymrDatabase,
-        AymrOpenable,
-        Batch,
-        InlineArray,
+use crate::{
+    aymr_db::{
+        backend::{
+            self,
+            BackendKind,
+        },
+        config::BtreeMapConfig,
+        error::{
+            CompareAndSwapResult,
+            Error,
+        },
+        iter::{
+            self,
+            ByteBounds,
+            Entry,
+            Iter,
+        },
+        merge::MergeOperator,
+        traits::{
+            AymrBatch,
+            AymrDatabase,
+            AymrOpenable,
+            AymrSnapshot,
+            AymrTransactional,
+            AymrTrees,
+            InlineArray,
+        },
+        transaction::{
+            self,
+            ConflictableTransactionResult,
+            Transaction,
+            TransactionResult,
+        },
+        tree::{
+            AymrTree,
+            RegistryBackend,
+            TreeRegistry,
+        },
+        ttl::ExpiryIndex,
+        watch::Subscriber,
+    },
+    backends::memory::{
+        MemoryDb,
+        MemoryMap,
+        MemorySnapshot,
+        OwnedBounds,
     },
 };
 
-/// Struct implementing `AymrDatabase` and `AymrOpenable` over `BTreeMap`.
+/// Struct implementing `AymrDatabase` and `AymrOpenable` over `OrdMap`.
+///
+/// Keys and values are stored as bytes, so the same map can be accessed with
+/// any key and value types. The map is a persistent B-tree sharing its nodes
+/// with any snapshots taken of it, so a write while a snapshot is alive only
+/// copies the nodes on the way to its key. Deadlines of keys with a time to
+/// live are kept in a side index, shared the same way.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct AymrBtreeMap<K, V> {
-    db: BTreeMap<K, V>,
+pub struct AymrBtreeMap {
+    core: MemoryDb<OrdMap<InlineArray, InlineArray>>,
+    trees: TreeRegistry<AymrBtreeMap>,
 }
 
-impl<K, V> AymrDatabase<K, V> for AymrBtreeMap<K, V>
+impl MemoryMap for OrdMap<InlineArray, InlineArray> {
+    fn get(&self, key: &[u8]) -> Option<&InlineArray> {
+        OrdMap::get(self, key)
+    }
+
+    fn insert(&mut self, key: InlineArray, value: InlineArray) -> Option<InlineArray> {
+        OrdMap::insert(self, key, value)
+    }
+
+    fn remove_entry(&mut self, key: &[u8]) -> Option<(InlineArray, InlineArray)> {
+        OrdMap::remove_with_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        OrdMap::len(self)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &InlineArray> {
+        OrdMap::keys(self)
+    }
+
+    fn remove_prefix(&mut self, prefix: &[u8]) -> Vec<InlineArray> {
+        let end = iter::prefix_upper_bound(prefix);
+        let removed: Vec<_> = OrdMap::range::<_, [u8]>(
+            self,
+            (Bound::Included(prefix), end.as_ref().map(Vec::as_slice)),
+        )
+        .map(|(k, _)| k.clone())
+        .collect();
+        for key in &removed {
+            OrdMap::remove(self, key);
+        }
+
+        removed
+    }
+
+    fn range<'a>(&'a self, expiry: &'a ExpiryIndex, bounds: ByteBounds<'_>) -> Iter<'a> {
+        let now = Instant::now();
+        Iter::new(
+            OrdMap::range::<_, [u8]>(self, bounds)
+                .filter(move |(k, _)| !expiry.is_expired(k, now))
+                .map(|(k, v)| (k.clone(), v.clone())),
+        )
+    }
+
+    fn range_owned(map: Self, expiry: ExpiryIndex, bounds: OwnedBounds) -> Iter<'static> {
+        Iter::new(Cursor {
+            map,
+            expiry,
+            front: bounds.0,
+            back: bounds.1,
+            now: Instant::now(),
+        })
+    }
+}
+
+/// Walks the live entries of a shared map within a range.
+///
+/// The next entry from either end is looked up afresh on every step, so the
+/// cursor owns its map rather than borrowing it.
+struct Cursor {
+    map: OrdMap<InlineArray, InlineArray>,
+    expiry: ExpiryIndex,
+    front: Bound<InlineArray>,
+    back: Bound<InlineArray>,
+    now: Instant,
+}
+
+impl Cursor {
+    /// Returns the next live entry from the back if `back` is set, or else
+    /// from the front, and moves that end past it.
+    fn step(&mut self, back: bool) -> Option<Entry> {
+        loop {
+            let bounds = (
+                self.front.as_ref().map(|k| &k[..]),
+                self.back.as_ref().map(|k| &k[..]),
+            );
+            if iter::is_empty_range(&bounds) {
+                return None;
+            }
+            let mut range = self.map.range::<_, [u8]>(bounds);
+            let (key, value) = if back {
+                range.next_back()
+            } else {
+                range.next()
+            }?;
+            let (key, value) = (key.clone(), value.clone());
+
+            if back {
+                self.back = Bound::Excluded(key.clone());
+            } else {
+                self.front = Bound::Excluded(key.clone());
+            }
+            if !self.expiry.is_expired(&key, self.now) {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        self.step(false)
+    }
+}
+
+impl DoubleEndedIterator for Cursor {
+    fn next_back(&mut self) -> Option<Entry> {
+        self.step(true)
+    }
+}
+
+impl AymrBtreeMap {
+    /// Removes the first live entry, or the last one if `last` is set.
+    fn pop(&mut self, last: bool) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.core.remove_expired();
+        let entry = {
+            let mut entries = self.core.range((Bound::Unbounded, Bound::Unbounded));
+            if last {
+                entries.next_back()
+            } else {
+                entries.next()
+            }
+        };
+        let Some((key, _)) = entry.transpose()? else {
+            return Ok(None);
+        };
+
+        Ok(self.core.remove_bytes(&key).map(|value| (key, value)))
+    }
+}
+
+impl<K, V> AymrDatabase<K, V> for AymrBtreeMap
 where
-    K: AsRef<[u8]> + Ord,
+    K: AsRef<[u8]>,
     V: AsRef<[u8]>,
 {
+    type Snapshot = AymrBtreeMapSnapshot;
+
     fn clear(&mut self) -> Result<(), Error> {
-        self.db.clear();
+        self.core.clear();
         Ok(())
     }
 
+    /// Expired keys that have not been purged yet are not counted.
     fn len(&self) -> usize {
-        self.db.len()
+        self.core.len()
     }
 
     fn is_empty(&self) -> Result<bool, Error> {
-        Ok(self.db.is_empty())
+        Ok(self.core.len() == 0)
     }
 
-    fn get(&self, key: &K) -> Result<Option<InlineArray>, Error> {
-        let rax = self.db.get(key).map(|v| v.as_ref().to_vec());
-        Ok(rax)
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        Ok(self.core.get(key.as_ref()))
     }
 
     fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
-        Ok(self.db.insert(key, value).map(|v| v.as_ref().to_vec()))
+        Ok(self.core.insert(key.as_ref(), value.as_ref()))
+    }
+
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        Ok(self.core.remove(key.as_ref()))
+    }
+
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        Ok(self.core.insert_with_ttl(key.as_ref(), value.as_ref(), ttl))
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        Ok(self.core.ttl(key.as_ref()))
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        Ok(self.core.remove_expired())
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.core.compare_and_swap(key.as_ref(), old, new)
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.core.set_merge_operator(merge_operator);
+    }
+
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        self.core.merge(key.as_ref(), operand.as_ref())
+    }
+
+    /// Applying an operation to an in-memory map can't fail, so the batch
+    /// always lands in full.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        self.core.apply_batch(batch);
+        Ok(())
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(self.core.get(key.as_ref()).is_some())
     }
 
-    fn remove(&mut self, key: K) -> Result<Option<InlineArray>, Error> {
-        Ok(self.db.remove(&key).map(|v| v.as_ref().to_vec()))
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        Ok(self.core.range((Bound::Unbounded, Bound::Unbounded)))
     }
 
-    fn apply_batch<B: Batch>(&self, _batch: B) -> Result<(), Error> {
-        // Implement apply_batch logic here
-        todo!()
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Ok(self.core.range(iter::byte_bounds(&range)))
     }
 
-    fn contains_key(&self, key: &K) -> Result<bool, Error> {
-        Ok(self.db.contains_key(key))
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        Ok(self.core.scan_prefix(prefix.as_ref()))
+    }
+
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        Ok(self.core.remove_prefix(prefix.as_ref()))
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.core
+            .range((Bound::Unbounded, Bound::Excluded(key.as_ref())))
+            .next_back()
+            .transpose()
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.core
+            .range((Bound::Excluded(key.as_ref()), Bound::Unbounded))
+            .next()
+            .transpose()
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::<K, V>::iter(self)?.next().transpose()
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        AymrDatabase::<K, V>::iter(self)?.next_back().transpose()
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.pop(false)
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.pop(true)
+    }
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        Ok(AymrBtreeMapSnapshot(self.core.snapshot()))
+    }
+
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        Ok(self.core.watch_prefix(prefix.as_ref()))
+    }
+}
+
+impl RegistryBackend for AymrBtreeMap {
+    fn scan(&self, bounds: ByteBounds<'_>) -> Iter<'static> {
+        self.core.range_owned(bounds)
     }
 }
 
-impl<K, V> AymrOpenable for AymrBtreeMap<K, V> {
-    fn open() -> Self {
-        let db = BTreeMap::new();
-        AymrBtreeMap { db }
+/// Point-in-time snapshot of an `AymrBtreeMap`.
+///
+/// Taking a snapshot only bumps the reference count of the map's root, the
+/// rest of the tree is shared. Keys still expire while the snapshot is held.
+#[derive(Debug, Clone)]
+pub struct AymrBtreeMapSnapshot(MemorySnapshot<OrdMap<InlineArray, InlineArray>>);
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrBtreeMapSnapshot {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.0.len() == 0)
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        Ok(self.0.get(key.as_ref()))
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(self.0.get(key.as_ref()).is_some())
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        Ok(self.0.range((Bound::Unbounded, Bound::Unbounded)))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Ok(self.0.range(iter::byte_bounds(&range)))
+    }
+}
+
+impl<K, V> AymrTransactional<K, V> for AymrBtreeMap
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |db, pending| pending.apply_to(db))
+    }
+}
+
+impl<K, V> AymrTrees<K, V> for AymrBtreeMap
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Tree = AymrTree<AymrBtreeMap>;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        self.trees.open(name.as_ref())
+    }
+
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        self.trees.drop_tree(name.as_ref())
+    }
+
+    fn tree_names(&self) -> Vec<InlineArray> {
+        self.trees.names()
+    }
+}
+
+/// The map only lives in memory, so it never exists before being opened and
+/// is always temporary.
+impl AymrOpenable for AymrBtreeMap {
+    type Config = BtreeMapConfig;
+
+    fn open(_config: BtreeMapConfig) -> Result<Self, Error> {
+        Ok(AymrBtreeMap {
+            core: MemoryDb::new(OrdMap::new()),
+            trees: TreeRegistry::default(),
+        })
+    }
+
+    fn create_new(config: BtreeMapConfig) -> Result<Self, Error> {
+        Self::open(config)
+    }
+
+    fn open_existing(_config: BtreeMapConfig) -> Result<Self, Error> {
+        Err(backend::not_persisted(BackendKind::BtreeMap))
+    }
+
+    fn temporary() -> Result<Self, Error> {
+        Self::open(BtreeMapConfig::default())
     }
 }
@@ -0,0 +1,1056 @@
+//! `fjall`
+//!
+//! This is an impl of the aymr DB traits for `fjall`, an LSM-tree based
+//! embedded database.
+//!
+//! Using fjall is optimal when:
+//!
+//! - You want your data on disk, surviving restarts.
+//! - You write a lot more than you read, like when ingesting data.
+//! - You want ordered keys, ranges and prefix scans.
+//! - You want consistent snapshots while others keep writing.
+//!
+//! Trees map onto partitions of a single keyspace, and every write and batch
+//! is an atomic fjall write batch. Compare and swap, merges, pops and
+//! transactions take turns, so they see each other, while plain writes go
+//! straight to fjall without waiting. fjall has no
+//! expiry, so the deadlines of expiring keys live in a partition of their own,
+//! and watching a prefix sees every write made through Aymr once it is
+//! committed.
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt,
+    io,
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use fjall::{
+    Keyspace,
+    PartitionCreateOptions,
+    PartitionHandle,
+    PersistMode,
+};
+
+use crate::aymr_db::{
+    config::{
+        Durability,
+        FjallConfig,
+    },
+    error::{
+        CompareAndSwapError,
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::{
+        self,
+        ByteBounds,
+        Iter,
+    },
+    merge::{
+        MergeOperator,
+        MergeOperatorSlot,
+    },
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrFlush,
+        AymrOpenable,
+        AymrSnapshot,
+        AymrTransactional,
+        AymrTrees,
+        BatchOp,
+        InlineArray,
+    },
+    transaction::{
+        self,
+        ConflictableTransactionResult,
+        PendingCommit,
+        Transaction,
+        TransactionResult,
+    },
+    ttl::{
+        self,
+        Deadline,
+    },
+    watch::{
+        Events,
+        Subscriber,
+        Subscribers,
+    },
+};
+
+/// Partition holding the default tree. Named trees live in partitions
+/// prefixed with `TREE_PREFIX`, so they can't clash with it.
+const DEFAULT_PARTITION: &str = "aymr";
+const TREE_PREFIX: &str = "aymr#";
+/// Partition holding the deadlines of the expiring keys of every tree.
+const DEADLINES_PARTITION: &str = "aymr$deadlines";
+
+/// Struct implementing `AymrDatabase` and `AymrOpenable` over a partition of
+/// a `fjall::Keyspace`.
+///
+/// The database itself opens on its default partition, and `open_tree` hands
+/// out handles to named ones. Handles are cheap to clone, and every clone
+/// refers to the same partition.
+#[derive(Clone)]
+pub struct AymrFjall {
+    keyspace: Keyspace,
+    partition: PartitionHandle,
+    deadlines: PartitionHandle,
+    /// The `ttl::deadline_prefix` of the partition.
+    deadline_prefix: InlineArray,
+    /// Name of the tree, or `None` for the default one.
+    tree: Option<String>,
+    /// State of the tree of this handle.
+    state: Arc<TreeState>,
+    shared: Arc<Shared>,
+    durability: Durability,
+    merge_operator: MergeOperatorSlot,
+}
+
+/// State shared by every handle to an open keyspace.
+#[derive(Default)]
+struct Shared {
+    /// Taken by the writes that read before they write, so one of them can't
+    /// interleave with another. Plain writes don't wait for it.
+    writes: Mutex<()>,
+    /// State of the trees opened so far, by partition name. fjall tells
+    /// partitions apart by name only, so a handle can't tell on its own
+    /// whether its tree has been dropped and opened again since.
+    trees: Mutex<HashMap<String, Arc<TreeState>>>,
+}
+
+/// What every handle to a tree shares.
+struct TreeState {
+    /// Cleared once the tree is dropped.
+    alive: AtomicBool,
+    subscribers: Subscribers,
+}
+
+impl Default for TreeState {
+    fn default() -> Self {
+        TreeState {
+            alive: AtomicBool::new(true),
+            subscribers: Subscribers::default(),
+        }
+    }
+}
+
+impl AymrFjall {
+    fn from_keyspace(keyspace: Keyspace, durability: Durability) -> Result<Self, Error> {
+        let partition =
+            keyspace.open_partition(DEFAULT_PARTITION, PartitionCreateOptions::default())?;
+        let deadlines =
+            keyspace.open_partition(DEADLINES_PARTITION, PartitionCreateOptions::default())?;
+
+        Ok(AymrFjall {
+            keyspace,
+            partition,
+            deadlines,
+            deadline_prefix: ttl::deadline_prefix(DEFAULT_PARTITION.as_bytes()).into(),
+            tree: None,
+            state: Arc::default(),
+            shared: Arc::default(),
+            durability,
+            merge_operator: MergeOperatorSlot::default(),
+        })
+    }
+
+    /// Returns the partition of this handle, failing if its tree has been
+    /// dropped.
+    fn partition(&self) -> Result<&PartitionHandle, Error> {
+        if self.state.alive.load(Ordering::Acquire) {
+            Ok(&self.partition)
+        } else {
+            Err(Error::CollectionNotFound(
+                self.tree.clone().unwrap_or_default(),
+            ))
+        }
+    }
+
+    /// Takes a snapshot of the partition and of the deadlines of its keys, as
+    /// of the same instant.
+    fn read(&self) -> Result<AymrFjallSnapshot, Error> {
+        let instant = self.keyspace.instant();
+        Ok(AymrFjallSnapshot {
+            snapshot: Arc::new(self.partition()?.snapshot_at(instant)),
+            deadlines: Arc::new(self.deadlines.snapshot_at(instant)),
+            deadline_prefix: self.deadline_prefix.clone(),
+        })
+    }
+
+    /// Runs `f`, then commits the batch it filled in atomically and publishes
+    /// the events `f` recorded.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&Partitions<'_>, &mut fjall::Batch, &mut Events) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.state.subscribers.write(|events| {
+            let partition = Partitions {
+                data: self.partition()?,
+                deadlines: &self.deadlines,
+                deadline_prefix: &self.deadline_prefix,
+                now: Deadline::now(),
+            };
+
+            // Batches reach the OS buffers by default, which is all
+            // `Durability::Buffered` asks for.
+            let mut batch = self.keyspace.batch();
+            if self.durability == Durability::Immediate {
+                batch = batch.durability(Some(PersistMode::SyncAll));
+            }
+            let out = f(&partition, &mut batch, events)?;
+            if !batch.is_empty() {
+                batch.commit()?;
+            }
+
+            Ok(out)
+        })
+    }
+
+    /// Runs `write` holding the write lock, for writes that depend on what
+    /// they read.
+    fn read_modify_write<T>(
+        &self,
+        f: impl FnOnce(&Partitions<'_>, &mut fjall::Batch, &mut Events) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let _write = lock(&self.shared.writes)?;
+        self.write(f)
+    }
+
+    /// Validates the read set and applies the writes of a transaction in a
+    /// single batch, returning false if it conflicted.
+    ///
+    /// Plain writes made while it commits aren't caught.
+    pub(crate) fn commit<K, V>(&self, pending: PendingCommit<K, V>) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.read_modify_write(|partition, batch, events| {
+            for (key, seen) in pending.reads() {
+                if partition.get(key.as_ref())?.as_deref() != seen.as_deref() {
+                    return Ok(false);
+                }
+            }
+            for (key, value) in pending.writes() {
+                match value {
+                    Some(value) => partition.put(batch, events, key.as_ref(), value.as_ref(), None),
+                    None => partition.delete(batch, events, key.as_ref())?,
+                }
+            }
+            Ok(true)
+        })
+    }
+}
+
+impl fmt::Debug for AymrFjall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrFjall")
+            .field("partition", &&*self.partition.name)
+            .field("durability", &self.durability)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two handles are equal if they refer to the same partition of the same
+/// open keyspace.
+impl PartialEq for AymrFjall {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared) && self.partition == other.partition
+    }
+}
+
+impl Eq for AymrFjall {}
+
+impl<K, V> AymrDatabase<K, V> for AymrFjall
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Snapshot = AymrFjallSnapshot;
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.write(|partition, batch, events| {
+            for key in partition.data.keys() {
+                partition.remove(batch, events, &key?);
+            }
+            Ok(())
+        })
+    }
+
+    /// Counts every key, so it takes a full scan.
+    fn len(&self) -> usize {
+        self.read()
+            .map(|snapshot| AymrSnapshot::<K>::len(&snapshot))
+            .unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        AymrSnapshot::<K>::is_empty(&self.read()?)
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        AymrSnapshot::<K>::get(&self.read()?, key)
+    }
+
+    /// The write doesn't wait for any other, so the old value is the one
+    /// read just before it.
+    fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+        self.write(|partition, batch, events| {
+            let old = partition.get(key.as_ref())?;
+            partition.put(batch, events, key.as_ref(), value.as_ref(), None);
+            Ok(old)
+        })
+    }
+
+    /// The write doesn't wait for any other, so the old value is the one
+    /// read just before it.
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.write(|partition, batch, events| {
+            let old = partition.get(key.as_ref())?;
+            partition.delete(batch, events, key.as_ref())?;
+            Ok(old)
+        })
+    }
+
+    /// The deadline is committed in the same batch as the key.
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        let deadline = Deadline::after(ttl);
+        self.write(|partition, batch, events| {
+            let old = partition.get(key.as_ref())?;
+            partition.put(batch, events, key.as_ref(), value.as_ref(), Some(deadline));
+            Ok(old)
+        })
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        let deadline = self.read()?.deadline(key.as_ref())?;
+        Ok(deadline.and_then(|deadline| deadline.left(Deadline::now())))
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        self.write(|partition, batch, events| {
+            let mut purged = 0;
+            for deadline in partition.deadlines.prefix(partition.deadline_prefix) {
+                let (key, deadline) = deadline?;
+                if Deadline::from_bytes(&deadline)?.has_passed(partition.now) {
+                    partition.remove(batch, events, &key[partition.deadline_prefix.len()..]);
+                    purged += 1;
+                }
+            }
+            Ok(purged)
+        })
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.read_modify_write(|partition, batch, events| {
+            let current = partition.get(key.as_ref())?;
+            if current.as_deref() != old.as_ref().map(AsRef::as_ref) {
+                return Ok(Err(CompareAndSwapError {
+                    current,
+                    proposed: new.as_ref().map(|v| v.as_ref().into()),
+                }));
+            }
+            match &new {
+                Some(value) => partition.put(batch, events, key.as_ref(), value.as_ref(), None),
+                None => partition.delete(batch, events, key.as_ref())?,
+            }
+            Ok(Ok(()))
+        })
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.merge_operator.set(merge_operator);
+    }
+
+    /// Reads, merges and writes back while holding the write lock.
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        self.read_modify_write(|partition, batch, events| {
+            let old = partition.get(key.as_ref())?;
+            let new = self.merge_operator.get()?(key.as_ref(), old.as_deref(), operand.as_ref());
+            match new {
+                Some(value) => partition.put(batch, events, key.as_ref(), &value, None),
+                None => partition.delete(batch, events, key.as_ref())?,
+            }
+            Ok(old)
+        })
+    }
+
+    /// Committed as a single fjall write batch, so either every operation of
+    /// the batch lands or none does.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        self.write(|partition, native, events| {
+            // fjall gives every write of a batch the same sequence number, so
+            // only the last write to each key may go in.
+            let mut writes = BTreeMap::new();
+            for op in batch {
+                match op {
+                    BatchOp::Clear => {
+                        writes.clear();
+                        for key in partition.data.keys() {
+                            writes.insert(key?.to_vec(), None);
+                        }
+                    }
+                    BatchOp::Insert(key, value) => {
+                        writes.insert(key.as_ref().to_vec(), Some(value));
+                    }
+                    BatchOp::Remove(key) => {
+                        writes.insert(key.as_ref().to_vec(), None);
+                    }
+                }
+            }
+            for (key, value) in writes {
+                match value {
+                    Some(value) => partition.put(native, events, &key, value.as_ref(), None),
+                    None => partition.delete(native, events, &key)?,
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        AymrSnapshot::<K>::contains_key(&self.read()?, key)
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.read()?.within((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.read()?.within(iter::byte_bounds(&range))
+    }
+
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        let snapshot = self.read()?;
+        snapshot.entries(snapshot.snapshot.prefix(prefix.as_ref()))
+    }
+
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        self.write(|partition, batch, events| {
+            let mut removed = 0;
+            for key in partition.data.prefix(prefix.as_ref()) {
+                let (key, _) = key?;
+                if !partition.is_expired(&key)? {
+                    removed += 1;
+                }
+                partition.remove(batch, events, &key);
+            }
+            Ok(removed)
+        })
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .within((Bound::Unbounded, Bound::Excluded(key.as_ref())))?
+            .next_back()
+            .transpose()
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .within((Bound::Excluded(key.as_ref()), Bound::Unbounded))?
+            .next()
+            .transpose()
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .within((Bound::Unbounded, Bound::Unbounded))?
+            .next()
+            .transpose()
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .within((Bound::Unbounded, Bound::Unbounded))?
+            .next_back()
+            .transpose()
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read_modify_write(|partition, batch, events| {
+            partition.pop(batch, events, partition.data.iter())
+        })
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read_modify_write(|partition, batch, events| {
+            partition.pop(batch, events, partition.data.iter().rev())
+        })
+    }
+
+    /// Takes a fjall snapshot, which keeps seeing the partition as it was
+    /// until it is dropped.
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        self.read()
+    }
+
+    /// Only sees the writes made through Aymr, by any handle to the tree.
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        Ok(self.state.subscribers.subscribe(prefix.as_ref()))
+    }
+}
+
+/// Snapshot of an `AymrFjall`, holding fjall snapshots of its partition and
+/// of the deadlines of its keys. Keys still expire while it is held.
+#[derive(Clone)]
+pub struct AymrFjallSnapshot {
+    snapshot: Arc<fjall::Snapshot>,
+    deadlines: Arc<fjall::Snapshot>,
+    deadline_prefix: InlineArray,
+}
+
+impl AymrFjallSnapshot {
+    /// Wraps a fjall iterator over the snapshot, skipping the keys that
+    /// expired and passing on the errors of entries that can't be read from
+    /// disk.
+    fn entries<E: Into<Error> + 'static>(
+        &self,
+        iter: impl DoubleEndedIterator<Item = Result<fjall::KvPair, E>> + 'static,
+    ) -> Result<Iter<'static>, Error> {
+        let entries = iter.map(|res| res.map(entry).map_err(Into::into));
+        if self
+            .deadlines
+            .prefix(&*self.deadline_prefix)
+            .next()
+            .transpose()?
+            .is_none()
+        {
+            return Ok(Iter::fallible(entries));
+        }
+
+        let snapshot = self.clone();
+        Ok(Iter::fallible(ttl::unexpired(entries, move |key| {
+            snapshot.deadline(key)
+        })))
+    }
+
+    /// Returns the entries within `bounds` that haven't expired.
+    fn within(&self, bounds: ByteBounds<'_>) -> Result<Iter<'static>, Error> {
+        if iter::is_empty_range(&bounds) {
+            return Ok(Iter::empty());
+        }
+
+        self.entries(self.snapshot.range::<&[u8], _>(bounds))
+    }
+
+    /// Returns the deadline of `key`, if it has one.
+    fn deadline(&self, key: &[u8]) -> Result<Option<Deadline>, Error> {
+        self.deadlines
+            .get(ttl::deadline_key(&self.deadline_prefix, key))?
+            .map(|deadline| Deadline::from_bytes(&deadline))
+            .transpose()
+    }
+
+    /// Counts the keys that haven't expired.
+    fn count(&self) -> Result<usize, Error> {
+        let now = Deadline::now();
+        let mut expired = 0;
+        for deadline in self.deadlines.prefix(&*self.deadline_prefix) {
+            if Deadline::from_bytes(&deadline?.1)?.has_passed(now) {
+                expired += 1;
+            }
+        }
+
+        Ok(self.snapshot.len()? - expired)
+    }
+}
+
+impl fmt::Debug for AymrFjallSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrFjallSnapshot").finish_non_exhaustive()
+    }
+}
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrFjallSnapshot {
+    /// Counts every key, so it takes a full scan.
+    fn len(&self) -> usize {
+        self.count().unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        let first = AymrSnapshot::<K>::iter(self)?.next().transpose()?;
+        Ok(first.is_none())
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        let Some(value) = self.snapshot.get(key.as_ref())?.map(bytes) else {
+            return Ok(None);
+        };
+        let expired = self
+            .deadline(key.as_ref())?
+            .is_some_and(|deadline| deadline.has_passed(Deadline::now()));
+
+        Ok((!expired).then_some(value))
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(AymrSnapshot::<K>::get(self, key)?.is_some())
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.entries(self.snapshot.iter())
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.within(iter::byte_bounds(&range))
+    }
+}
+
+/// Commits as a single fjall write batch while holding the write lock, so the
+/// read set is validated against the writes of every handle to the keyspace.
+impl<K, V> AymrTransactional<K, V> for AymrFjall
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |db, pending| db.commit(pending))
+    }
+}
+
+/// Trees are fjall partitions, so their names can only hold ASCII letters,
+/// digits, `_`, `-`, `.`, `#` and `$`. Tree handles share the durability of
+/// the database they were opened from. A dropped tree can only be opened
+/// again once every handle to it is gone, and until then `open_tree` returns
+/// `Error::Unsupported`.
+impl<K, V> AymrTrees<K, V> for AymrFjall
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Tree = AymrFjall;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        let partition_name = partition_name(name.as_ref())?;
+        let mut trees = lock(&self.shared.trees)?;
+        let partition = self
+            .keyspace
+            .open_partition(&partition_name, PartitionCreateOptions::default())?;
+        let deadline_prefix = ttl::deadline_prefix(partition_name.as_bytes()).into();
+        let state = trees.entry(partition_name).or_default();
+
+        Ok(AymrFjall {
+            keyspace: self.keyspace.clone(),
+            partition,
+            deadlines: self.deadlines.clone(),
+            deadline_prefix,
+            tree: Some(String::from_utf8_lossy(name.as_ref()).into_owned()),
+            state: Arc::clone(state),
+            shared: Arc::clone(&self.shared),
+            durability: self.durability,
+            merge_operator: MergeOperatorSlot::default(),
+        })
+    }
+
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        let name = partition_name(name.as_ref())?;
+        let _write = lock(&self.shared.writes)?;
+        let mut trees = lock(&self.shared.trees)?;
+        if !self.keyspace.partition_exists(&name) {
+            return Ok(false);
+        }
+
+        let mut batch = self.keyspace.batch();
+        for deadline in self.deadlines.prefix(ttl::deadline_prefix(name.as_bytes())) {
+            batch.remove(&self.deadlines, deadline?.0);
+        }
+        batch.commit()?;
+
+        let partition = self
+            .keyspace
+            .open_partition(&name, PartitionCreateOptions::default())?;
+        self.keyspace.delete_partition(partition)?;
+        if let Some(state) = trees.remove(&name) {
+            state.alive.store(false, Ordering::Release);
+        }
+
+        Ok(true)
+    }
+
+    fn tree_names(&self) -> Vec<InlineArray> {
+        let mut names: Vec<InlineArray> = self
+            .keyspace
+            .list_partitions()
+            .iter()
+            .filter_map(|name| name.strip_prefix(TREE_PREFIX).map(InlineArray::from))
+            .collect();
+        names.sort();
+
+        names
+    }
+}
+
+/// Persists the journal of the keyspace, which holds the writes of every
+/// partition.
+impl AymrFlush for AymrFjall {
+    fn flush(&self) -> Result<(), Error> {
+        Ok(self.keyspace.persist(PersistMode::SyncAll)?)
+    }
+}
+
+impl AymrOpenable for AymrFjall {
+    type Config = FjallConfig;
+
+    /// A `temporary` database without a `path` gets a fresh directory of its
+    /// own, which is removed once the last handle is dropped.
+    fn open(config: FjallConfig) -> Result<Self, Error> {
+        let path = match (&config.path, config.temporary) {
+            (Some(path), _) => path.clone(),
+            (None, true) => temporary_dir(),
+            (None, false) => {
+                return Err(Error::Config(
+                    "the `fjall` backend needs a `path` unless it is `temporary`".to_string(),
+                ))
+            }
+        };
+
+        let mut native = fjall::Config::new(path).temporary(config.temporary);
+        if let Some(bytes) = config.cache_capacity {
+            native = native.cache_size(bytes);
+        }
+
+        AymrFjall::from_keyspace(native.open()?, config.durability)
+    }
+
+    fn create_new(config: FjallConfig) -> Result<Self, Error> {
+        if config.path.as_ref().is_some_and(|path| exists(path)) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "there already is a `fjall` keyspace at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    fn open_existing(config: FjallConfig) -> Result<Self, Error> {
+        if !config.path.as_ref().is_some_and(|path| exists(path)) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "there is no `fjall` keyspace at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    /// Opens a database in a fresh temporary directory.
+    fn temporary() -> Result<Self, Error> {
+        Self::open(FjallConfig {
+            path: None,
+            cache_capacity: None,
+            durability: Durability::default(),
+            temporary: true,
+        })
+    }
+
+    fn close(self) -> Result<(), Error> {
+        AymrFlush::flush(&self)
+    }
+}
+
+/// Returns the name of the partition holding the tree `name`.
+fn partition_name(name: &[u8]) -> Result<String, Error> {
+    let valid = |c: &u8| c.is_ascii_alphanumeric() || b"_-.#$".contains(c);
+    if name.len() > usize::from(u8::MAX) - TREE_PREFIX.len() || !name.iter().all(valid) {
+        return Err(Error::Unsupported(
+            "`fjall` can only name trees by up to 250 ASCII letters, digits, `_`, `-`, `.`, `#` \
+             and `$`"
+                .to_string(),
+        ));
+    }
+
+    Ok(format!("{}{}", TREE_PREFIX, String::from_utf8_lossy(name)))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, Error> {
+    mutex.lock().map_err(|_| {
+        Error::ReportableBug("fjall handle lock poisoned by a panicking thread".to_string())
+    })
+}
+
+/// Returns true if a fjall keyspace has been created at `path`.
+fn exists(path: &Path) -> bool {
+    path.join("version").is_file()
+}
+
+/// Returns a directory no other temporary database uses.
+fn temporary_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    std::env::temp_dir().join(format!(
+        "aymr-fjall-{}-{}-{}",
+        std::process::id(),
+        nanos,
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// The partition of a tree and the deadlines of its keys, as seen by a write.
+struct Partitions<'a> {
+    data: &'a PartitionHandle,
+    deadlines: &'a PartitionHandle,
+    deadline_prefix: &'a [u8],
+    now: Deadline,
+}
+
+impl Partitions<'_> {
+    /// Returns the value of `key`, unless it expired.
+    fn get(&self, key: &[u8]) -> Result<Option<InlineArray>, Error> {
+        let Some(value) = self.data.get(key)?.map(bytes) else {
+            return Ok(None);
+        };
+
+        Ok((!self.is_expired(key)?).then_some(value))
+    }
+
+    /// Returns true if `key` has a deadline that has passed.
+    fn is_expired(&self, key: &[u8]) -> Result<bool, Error> {
+        match self.deadlines.get(self.deadline_key(key))? {
+            Some(deadline) => Ok(Deadline::from_bytes(&deadline)?.has_passed(self.now)),
+            None => Ok(false),
+        }
+    }
+
+    fn deadline_key(&self, key: &[u8]) -> Vec<u8> {
+        ttl::deadline_key(self.deadline_prefix, key)
+    }
+
+    /// Adds an insert of `key` with the given deadline to `batch`, recording
+    /// the event.
+    fn put(
+        &self,
+        batch: &mut fjall::Batch,
+        events: &mut Events,
+        key: &[u8],
+        value: &[u8],
+        deadline: Option<Deadline>,
+    ) {
+        events.insert(key, value);
+        batch.insert(self.data, key, value);
+        match deadline {
+            Some(deadline) => {
+                batch.insert(self.deadlines, self.deadline_key(key), deadline.to_bytes())
+            }
+            None => batch.remove(self.deadlines, self.deadline_key(key)),
+        }
+    }
+
+    /// Adds a removal of `key` to `batch`. Its event is only recorded if the
+    /// key is there, which takes a lookup while anyone is listening.
+    fn delete(
+        &self,
+        batch: &mut fjall::Batch,
+        events: &mut Events,
+        key: &[u8],
+    ) -> Result<(), Error> {
+        if events.watched() && self.data.contains_key(key)? {
+            events.remove(key);
+        }
+        batch.remove(self.data, key);
+        batch.remove(self.deadlines, self.deadline_key(key));
+
+        Ok(())
+    }
+
+    /// Adds a removal of `key`, which is there, to `batch`, recording the
+    /// event.
+    fn remove(&self, batch: &mut fjall::Batch, events: &mut Events, key: &[u8]) {
+        events.remove(key);
+        batch.remove(self.data, key);
+        batch.remove(self.deadlines, self.deadline_key(key));
+    }
+
+    /// Adds removals of the entries of `entries` to `batch` until one hasn't
+    /// expired, returning it.
+    fn pop(
+        &self,
+        batch: &mut fjall::Batch,
+        events: &mut Events,
+        entries: impl Iterator<Item = fjall::Result<fjall::KvPair>>,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        for popped in entries {
+            let (key, value) = popped?;
+            self.remove(batch, events, &key);
+            if !self.is_expired(&key)? {
+                return Ok(Some(entry((key, value))));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn bytes(value: fjall::Slice) -> InlineArray {
+    InlineArray::from(&*value)
+}
+
+fn entry((key, value): fjall::KvPair) -> (InlineArray, InlineArray) {
+    (bytes(key), bytes(value))
+}
+
+impl From<fjall::Error> for Error {
+    fn from(error: fjall::Error) -> Self {
+        match error {
+            fjall::Error::Storage(e) => e.into(),
+            fjall::Error::Io(e) => Error::Io(e),
+            fjall::Error::Decode(e) => Error::corruption(Some(e.to_string())),
+            fjall::Error::JournalRecovery(e) => Error::corruption(Some(e.to_string())),
+            fjall::Error::InvalidVersion(version) => {
+                Error::Unsupported(format!(
+                    "the `fjall` keyspace format {:?} can't be opened",
+                    version
+                ))
+            }
+            fjall::Error::PartitionDeleted => {
+                Error::Unsupported(
+                    "`fjall` can't open a dropped tree again while handles to it are around"
+                        .to_string(),
+                )
+            }
+            fjall::Error::Poisoned => {
+                Error::Io(io::Error::other(
+                    "a `fjall` write failed to persist, so the keyspace takes no more writes",
+                ))
+            }
+            other => Error::ReportableBug(other.to_string()),
+        }
+    }
+}
+
+impl From<fjall::LsmError> for Error {
+    fn from(error: fjall::LsmError) -> Self {
+        match error {
+            fjall::LsmError::Io(e) => Error::Io(e),
+            fjall::LsmError::Decode(e) => Error::corruption(Some(e.to_string())),
+            fjall::LsmError::Decompress(_)
+            | fjall::LsmError::InvalidChecksum(_)
+            | fjall::LsmError::Unrecoverable => Error::corruption(Some(error.to_string())),
+            other => Error::ReportableBug(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aymr_db::{
+        backend::BackendKind,
+        config::{
+            AymrDbBuilder,
+            FjallConfig,
+        },
+        db::{
+            tests::test_dir,
+            AymrDb,
+        },
+        error::Error,
+        traits::{
+            AymrDatabase,
+            AymrTrees,
+            InlineArray,
+        },
+    };
+
+    fn open_temporary() -> AymrDb<Vec<u8>, Vec<u8>> {
+        AymrDbBuilder::new()
+            .backend(BackendKind::Fjall)
+            .temporary(true)
+            .cache_capacity(1 << 20)
+            .open()
+            .unwrap()
+    }
+
+    /// Temporary keyspaces are removed once dropped, even at a path.
+    #[test]
+    fn test_temporary_at_path() {
+        let dir = test_dir("fjall-temporary");
+        let mut config = FjallConfig::new(&dir);
+        config.temporary = true;
+
+        let mut db = AymrDb::<Vec<u8>, Vec<u8>>::open(config).unwrap();
+        db.insert(vec![1], vec![1]).unwrap();
+        assert!(dir.is_dir());
+        drop(db);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_tree_names() {
+        let mut db = open_temporary();
+        assert!(matches!(db.open_tree("a/b"), Err(Error::Unsupported(_))));
+        db.open_tree("a-b.c#$_1").unwrap();
+        assert_eq!(db.tree_names(), vec![InlineArray::from("a-b.c#$_1")]);
+    }
+
+    #[test]
+    fn test_dropped_tree_reopens_once_handles_are_gone() {
+        let mut db = open_temporary();
+        let mut tree = db.open_tree(b"tree").unwrap();
+        tree.insert(vec![1], vec![1]).unwrap();
+        assert!(db.drop_tree(b"tree").unwrap());
+
+        assert!(matches!(db.open_tree(b"tree"), Err(Error::Unsupported(_))));
+        drop(tree);
+        let tree = db.open_tree(b"tree").unwrap();
+        assert!(tree.is_empty().unwrap());
+    }
+}
@@ -1,83 +1,403 @@
 //! # `hashmap`
 //!
-//! This is a basic impl of the aymr DB traits for `std::collections::HashMap`.
+//! This is a basic impl of the aymr DB traits for a hash map, `imbl::HashMap`.
 //!
 //! Using a BTreeMap is optimal when:
 //!
 //! - You want to associate arbitrary keys with an arbitrary value.
 //! - You want a cache.
 //! - You want a map, with no extra functionality.
-
+//!
 //! Aymr HashMap does not flush to disk. Every change you have is exclusively going to be in-memory.
 
-use std::collections::HashMap;
+use std::{
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use imbl::HashMap;
 
-use crate::aymr_db::{
-    error::Error,
-    traits::{
-        AymrDatabase,
-        AymrOpenable,
-        Batch,
-        InlineArray,
+use crate::{
+    aymr_db::{
+        backend::{
+            self,
+            BackendKind,
+        },
+        config::HashMapConfig,
+        error::{
+            CompareAndSwapResult,
+            Error,
+        },
+        iter::{
+            self,
+            ByteBounds,
+            Entry,
+            Iter,
+        },
+        merge::MergeOperator,
+        traits::{
+            AymrBatch,
+            AymrDatabase,
+            AymrOpenable,
+            AymrSnapshot,
+            AymrTransactional,
+            AymrTrees,
+            InlineArray,
+        },
+        transaction::{
+            self,
+            ConflictableTransactionResult,
+            Transaction,
+            TransactionResult,
+        },
+        tree::{
+            AymrTree,
+            RegistryBackend,
+            TreeRegistry,
+        },
+        ttl::ExpiryIndex,
+        watch::Subscriber,
+    },
+    backends::memory::{
+        MemoryDb,
+        MemoryMap,
+        MemorySnapshot,
+        OwnedBounds,
     },
 };
 
 /// Struct implementing `AymrDatabase` and `AymrOpenable` over `HashMap`.
+///
+/// Keys and values are stored as bytes, so the same map can be accessed with
+/// any key and value types. The map is a persistent hash trie sharing its
+/// nodes with any snapshots taken of it, so a write while a snapshot is alive
+/// only copies the nodes on the way to its key. Deadlines of keys with a time
+/// to live are kept in a side index, shared the same way.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct AymrHashMap<K, V>
-where
-    K: Eq + std::hash::Hash,
-{
-    db: HashMap<K, V>,
+pub struct AymrHashMap {
+    core: MemoryDb<HashMap<InlineArray, InlineArray>>,
+    trees: TreeRegistry<AymrHashMap>,
+}
+
+impl MemoryMap for HashMap<InlineArray, InlineArray> {
+    fn get(&self, key: &[u8]) -> Option<&InlineArray> {
+        HashMap::get(self, key)
+    }
+
+    fn insert(&mut self, key: InlineArray, value: InlineArray) -> Option<InlineArray> {
+        HashMap::insert(self, key, value)
+    }
+
+    fn remove_entry(&mut self, key: &[u8]) -> Option<(InlineArray, InlineArray)> {
+        HashMap::remove_with_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &InlineArray> {
+        HashMap::keys(self)
+    }
+
+    fn remove_prefix(&mut self, prefix: &[u8]) -> Vec<InlineArray> {
+        let mut removed = Vec::new();
+        self.retain(|k, _| {
+            let matches = k.starts_with(prefix);
+            if matches {
+                removed.push(k.clone());
+            }
+            !matches
+        });
+
+        removed
+    }
+
+    /// `HashMap` has no ordering of its own, so every matching entry is
+    /// collected and sorted on demand.
+    fn range<'a>(&'a self, expiry: &'a ExpiryIndex, bounds: ByteBounds<'_>) -> Iter<'a> {
+        Iter::new(sorted(self, expiry, bounds).into_iter())
+    }
+
+    fn range_owned(map: Self, expiry: ExpiryIndex, bounds: OwnedBounds) -> Iter<'static> {
+        let bounds = (
+            bounds.0.as_ref().map(|k| &k[..]),
+            bounds.1.as_ref().map(|k| &k[..]),
+        );
+        Iter::new(sorted(&map, &expiry, bounds).into_iter())
+    }
 }
 
-impl<K, V> AymrDatabase<K, V> for AymrHashMap<K, V>
+/// Collects the live entries of `map` within `bounds`, sorted by key.
+fn sorted(
+    map: &HashMap<InlineArray, InlineArray>,
+    expiry: &ExpiryIndex,
+    bounds: ByteBounds<'_>,
+) -> Vec<Entry> {
+    let now = Instant::now();
+    let mut entries: Vec<_> = map
+        .iter()
+        .filter(|(k, _)| {
+            RangeBounds::<[u8]>::contains(&bounds, &k[..]) && !expiry.is_expired(k, now)
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    entries
+}
+
+impl<K, V> AymrDatabase<K, V> for AymrHashMap
 where
-    K: AsRef<[u8]> + Ord + std::hash::Hash,
+    K: AsRef<[u8]>,
     V: AsRef<[u8]>,
 {
+    type Snapshot = AymrHashMapSnapshot;
+
     fn clear(&mut self) -> Result<(), Error> {
-        self.db.clear();
+        self.core.clear();
         Ok(())
     }
 
+    /// Expired keys that have not been purged yet are not counted.
     fn len(&self) -> usize {
-        self.db.len()
+        self.core.len()
     }
 
     fn is_empty(&self) -> Result<bool, Error> {
-        Ok(self.db.is_empty())
+        Ok(self.core.len() == 0)
     }
 
-    fn get(&self, key: &K) -> Result<Option<InlineArray>, Error> {
-        let rax = self.db.get(key).map(|v| v.as_ref().to_vec());
-        Ok(rax)
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        Ok(self.core.get(key.as_ref()))
     }
 
     fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
-        Ok(self.db.insert(key, value).map(|v| v.as_ref().to_vec()))
+        Ok(self.core.insert(key.as_ref(), value.as_ref()))
+    }
+
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        Ok(self.core.remove(key.as_ref()))
+    }
+
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        Ok(self.core.insert_with_ttl(key.as_ref(), value.as_ref(), ttl))
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        Ok(self.core.ttl(key.as_ref()))
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        Ok(self.core.remove_expired())
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.core.compare_and_swap(key.as_ref(), old, new)
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.core.set_merge_operator(merge_operator);
+    }
+
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        self.core.merge(key.as_ref(), operand.as_ref())
+    }
+
+    /// Applying an operation to an in-memory map can't fail, so the batch
+    /// always lands in full.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        self.core.apply_batch(batch);
+        Ok(())
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(self.core.get(key.as_ref()).is_some())
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        Ok(self.core.range((Bound::Unbounded, Bound::Unbounded)))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Ok(self.core.range(iter::byte_bounds(&range)))
+    }
+
+    /// Falls back to a full scan, since `HashMap` keys are not ordered.
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        Ok(self.core.scan_prefix(prefix.as_ref()))
+    }
+
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        Ok(self.core.remove_prefix(prefix.as_ref()))
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        _key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        Err(unordered("get_lt"))
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        _key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        Err(unordered("get_gt"))
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        Err(unordered("first"))
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        Err(unordered("last"))
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        Err(unordered("pop_min"))
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        Err(unordered("pop_max"))
+    }
+
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        Ok(AymrHashMapSnapshot(self.core.snapshot()))
+    }
+
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        Ok(self.core.watch_prefix(prefix.as_ref()))
+    }
+}
+
+/// Scans of a tree sort its entries up front, like every other scan.
+impl RegistryBackend for AymrHashMap {
+    fn scan(&self, bounds: ByteBounds<'_>) -> Iter<'static> {
+        self.core.range_owned(bounds)
+    }
+}
+
+/// Point-in-time snapshot of an `AymrHashMap`.
+///
+/// Taking a snapshot only bumps the reference count of the map's root, the
+/// rest of the trie is shared. Keys still expire while the snapshot is held.
+#[derive(Debug, Clone)]
+pub struct AymrHashMapSnapshot(MemorySnapshot<HashMap<InlineArray, InlineArray>>);
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrHashMapSnapshot {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.0.len() == 0)
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        Ok(self.0.get(key.as_ref()))
     }
 
-    fn remove(&mut self, key: K) -> Result<Option<InlineArray>, Error> {
-        Ok(self.db.remove(&key).map(|v| v.as_ref().to_vec()))
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(self.0.get(key.as_ref()).is_some())
     }
 
-    fn apply_batch<B: Batch>(&self, _batch: B) -> Result<(), Error> {
-        // Implement apply_batch logic here
-        todo!()
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        Ok(self.0.range((Bound::Unbounded, Bound::Unbounded)))
     }
 
-    fn contains_key(&self, key: &K) -> Result<bool, Error> {
-        Ok(self.db.contains_key(key))
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        Ok(self.0.range(iter::byte_bounds(&range)))
+    }
+}
+
+/// Error returned by operations that need ordered keys.
+fn unordered(op: &str) -> Error {
+    Error::Unsupported(format!(
+        "`{}` needs ordered keys, which `hashmap` does not have",
+        op
+    ))
+}
+
+impl<K, V> AymrTransactional<K, V> for AymrHashMap
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |db, pending| pending.apply_to(db))
     }
 }
 
-impl<K, V> AymrOpenable for AymrHashMap<K, V>
+impl<K, V> AymrTrees<K, V> for AymrHashMap
 where
-    K: Eq + std::hash::Hash,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
 {
-    fn open() -> Self {
-        let db = HashMap::new();
-        AymrHashMap { db }
+    type Tree = AymrTree<AymrHashMap>;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        self.trees.open(name.as_ref())
+    }
+
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        self.trees.drop_tree(name.as_ref())
+    }
+
+    fn tree_names(&self) -> Vec<InlineArray> {
+        self.trees.names()
+    }
+}
+
+/// The map only lives in memory, so it never exists before being opened and
+/// is always temporary.
+impl AymrOpenable for AymrHashMap {
+    type Config = HashMapConfig;
+
+    /// A hash trie grows a node at a time, so `capacity` has nothing to reserve.
+    fn open(_config: HashMapConfig) -> Result<Self, Error> {
+        Ok(AymrHashMap {
+            core: MemoryDb::new(HashMap::new()),
+            trees: TreeRegistry::default(),
+        })
+    }
+
+    fn create_new(config: HashMapConfig) -> Result<Self, Error> {
+        Self::open(config)
+    }
+
+    fn open_existing(_config: HashMapConfig) -> Result<Self, Error> {
+        Err(backend::not_persisted(BackendKind::HashMap))
+    }
+
+    fn temporary() -> Result<Self, Error> {
+        Self::open(HashMapConfig::default())
     }
 }
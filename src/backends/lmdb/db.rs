@@ -0,0 +1,1379 @@
+//! `lmdb`
+//!
+//! This is an impl of the aymr DB traits for LMDB, the memory mapped B+tree
+//! database, through `heed`.
+//!
+//! Using LMDB is optimal when:
+//!
+//! - You want your data on disk, surviving restarts.
+//! - You read a lot more than you write, straight from the memory map.
+//! - You want ordered keys, ranges and prefix scans.
+//! - You want consistent snapshots while others keep writing.
+//!
+//! Trees map onto named databases, and every write, batch and transaction is
+//! a single LMDB write transaction. Snapshots are read transactions. LMDB's
+//! limits come back as errors: keys longer than 511 bytes fail with
+//! `Error::KeyTooLarge`, and a write that doesn't fit in the map fails with
+//! `Error::MapFull` until the database is reopened with a larger `map_size`.
+//! LMDB has no expiry, so the deadlines of expiring keys live in a database of
+//! their own, and watching a prefix sees every write made through Aymr once it
+//! commits.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs,
+    io,
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use heed::{
+    types::Bytes,
+    EnvFlags,
+    EnvOpenOptions,
+    MdbError,
+    RoTxn,
+    RwTxn,
+    WithoutTls,
+};
+
+use crate::aymr_db::{
+    config::{
+        Durability,
+        LmdbConfig,
+    },
+    error::{
+        CompareAndSwapError,
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::{
+        self,
+        ByteBounds,
+        Iter,
+    },
+    merge::{
+        MergeOperator,
+        MergeOperatorSlot,
+    },
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrFlush,
+        AymrOpenable,
+        AymrSnapshot,
+        AymrTransactional,
+        AymrTrees,
+        BatchOp,
+        InlineArray,
+    },
+    transaction::{
+        self,
+        ConflictableTransactionResult,
+        PendingCommit,
+        Transaction,
+        TransactionResult,
+    },
+    ttl::{
+        self,
+        Deadline,
+    },
+    watch::{
+        Events,
+        Subscriber,
+        Subscribers,
+    },
+};
+
+type Env = heed::Env<WithoutTls>;
+type Database = heed::Database<Bytes, Bytes>;
+type ReadTxn = RoTxn<'static, WithoutTls>;
+
+/// Database holding the default tree. Named trees live in databases prefixed
+/// with `TREE_PREFIX`, `TREES_DATABASE` holds their names and
+/// `DEADLINES_DATABASE` the deadlines of the expiring keys of every tree.
+const DEFAULT_DATABASE: &str = "aymr";
+const TREES_DATABASE: &str = "aymr#trees";
+const DEADLINES_DATABASE: &str = "aymr#deadlines";
+const TREE_PREFIX: &str = "aymr/";
+
+/// Struct implementing `AymrDatabase` and `AymrOpenable` over a named
+/// database of an LMDB environment.
+///
+/// The database itself opens on its default tree, and `open_tree` hands out
+/// handles to named ones. Handles are cheap to clone, and every clone refers
+/// to the same tree.
+#[derive(Clone)]
+pub struct AymrLmdb {
+    env: Arc<Environment>,
+    table: Table,
+    /// Names of the trees, so dropped ones can be told apart from empty ones.
+    trees: Database,
+    /// Name of the tree, or `None` for the default one.
+    tree: Option<String>,
+    /// Subscriptions to the tree, shared by every handle to it.
+    subscribers: Arc<Subscribers>,
+    merge_operator: MergeOperatorSlot,
+}
+
+/// The open environment, removed once the last handle to it goes away if it
+/// is temporary.
+struct Environment {
+    env: Env,
+    temporary: bool,
+    /// Subscriptions to every named tree opened so far, by name.
+    subscribers: Mutex<HashMap<String, Arc<Subscribers>>>,
+}
+
+impl Drop for Environment {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = fs::remove_dir_all(self.env.path());
+        }
+    }
+}
+
+/// A database of the environment and the deadlines of its keys, along with
+/// the longest key it can hold.
+#[derive(Clone)]
+struct Table {
+    db: Database,
+    deadlines: Database,
+    /// The `ttl::deadline_prefix` of the database.
+    deadline_prefix: InlineArray,
+    max_key_size: usize,
+}
+
+impl Table {
+    /// Returns the value of `key`, which is `None` for keys LMDB can't hold
+    /// and keys that expired.
+    fn get(&self, txn: &RoTxn, key: &[u8]) -> Result<Option<InlineArray>, Error> {
+        if !self.holds(key) {
+            return Ok(None);
+        }
+
+        let Some(value) = self.db.get(txn, key)?.map(InlineArray::from) else {
+            return Ok(None);
+        };
+        let expired = self
+            .deadline(txn, key)?
+            .is_some_and(|deadline| deadline.has_passed(Deadline::now()));
+
+        Ok((!expired).then_some(value))
+    }
+
+    /// Returns the deadline of `key`, if it has one.
+    fn deadline(&self, txn: &RoTxn, key: &[u8]) -> Result<Option<Deadline>, Error> {
+        let key = ttl::deadline_key(&self.deadline_prefix, key);
+        if key.len() > self.max_key_size {
+            return Ok(None);
+        }
+
+        self.deadlines
+            .get(txn, &key)?
+            .map(Deadline::from_bytes)
+            .transpose()
+    }
+
+    /// Sets the deadline of `key`, or removes it if `deadline` is `None`,
+    /// returning true if the old one had passed.
+    ///
+    /// A deadline is kept under the key along with the name of its tree, so
+    /// it takes a shorter key than LMDB's longest to have one.
+    fn set_deadline(
+        &self,
+        txn: &mut RwTxn<'_>,
+        key: &[u8],
+        deadline: Option<Deadline>,
+    ) -> Result<bool, Error> {
+        let old = self.deadline(txn, key)?;
+        let key = ttl::deadline_key(&self.deadline_prefix, key);
+        match deadline {
+            Some(_) if key.len() > self.max_key_size => {
+                return Err(Error::KeyTooLarge {
+                    len: key.len() - self.deadline_prefix.len(),
+                    max: self.max_key_size - self.deadline_prefix.len(),
+                });
+            }
+            Some(deadline) => self.deadlines.put(txn, &key, &deadline.to_bytes())?,
+            None if old.is_some() => {
+                self.deadlines.delete(txn, &key)?;
+            }
+            None => {}
+        }
+
+        Ok(old.is_some_and(|old| old.has_passed(Deadline::now())))
+    }
+
+    /// Returns true if any key of the tree has a deadline, as of `txn`.
+    fn expiring(&self, txn: &RoTxn) -> Result<bool, Error> {
+        Ok(self
+            .deadlines
+            .prefix_iter(txn, &self.deadline_prefix)?
+            .next()
+            .transpose()?
+            .is_some())
+    }
+
+    /// Inserts `key` with the given deadline, recording the event.
+    fn put(
+        &self,
+        txn: &mut RwTxn<'_>,
+        events: &mut Events,
+        key: &[u8],
+        value: &[u8],
+        deadline: Option<Deadline>,
+    ) -> Result<(), Error> {
+        if key.is_empty() {
+            return Err(Error::Unsupported(
+                "`lmdb` can't store empty keys".to_string(),
+            ));
+        }
+        if key.len() > self.max_key_size {
+            return Err(Error::KeyTooLarge {
+                len: key.len(),
+                max: self.max_key_size,
+            });
+        }
+
+        self.set_deadline(txn, key, deadline)?;
+        self.db.put(txn, key, value)?;
+        events.insert(key, value);
+
+        Ok(())
+    }
+
+    /// Removes `key` and its deadline, recording the event if it was there.
+    fn delete(&self, txn: &mut RwTxn<'_>, events: &mut Events, key: &[u8]) -> Result<bool, Error> {
+        let deleted = self.holds(key) && self.db.delete(txn, key)?;
+        if deleted {
+            events.remove(key);
+            self.set_deadline(txn, key, None)?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Removes every key within `bounds`, recording their events, and
+    /// returns how many hadn't expired.
+    fn delete_range(
+        &self,
+        txn: &mut RwTxn<'_>,
+        events: &mut Events,
+        bounds: ByteBounds<'_>,
+    ) -> Result<usize, Error> {
+        let Some((start, end)) = self.lookup_bounds(bounds) else {
+            return Ok(0);
+        };
+        let bounds = (
+            start.as_ref().map(Vec::as_slice),
+            end.as_ref().map(Vec::as_slice),
+        );
+        if !self.expiring(txn)? {
+            if events.watched() {
+                for key in self.db.range(txn, &bounds)? {
+                    events.remove(key?.0);
+                }
+            }
+
+            return Ok(self.db.delete_range(txn, &bounds)?);
+        }
+
+        let keys = self
+            .db
+            .range(txn, &bounds)?
+            .map(|entry| entry.map(|(key, _)| InlineArray::from(key)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut removed = 0;
+        for key in keys {
+            self.db.delete(txn, &key)?;
+            events.remove(&key);
+            if !self.set_deadline(txn, &key, None)? {
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes every key and deadline, recording their events.
+    fn clear(&self, txn: &mut RwTxn<'_>, events: &mut Events) -> Result<(), Error> {
+        if events.watched() {
+            for key in self.db.iter(txn)? {
+                events.remove(key?.0);
+            }
+        }
+        self.db.clear(txn)?;
+        clear_deadlines(txn, self.deadlines, &self.deadline_prefix)
+    }
+
+    /// Removes entries off `end` until one hasn't expired, recording their
+    /// removal.
+    fn pop(
+        &self,
+        txn: &mut RwTxn<'_>,
+        events: &mut Events,
+        end: End,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        loop {
+            let popped = match end {
+                End::First => self.db.first(txn)?.map(entry),
+                End::Last => self.db.last(txn)?.map(entry),
+            };
+            let Some((key, value)) = popped else {
+                return Ok(None);
+            };
+            self.db.delete(txn, &key)?;
+            events.remove(&key);
+            if !self.set_deadline(txn, &key, None)? {
+                return Ok(Some((key, value)));
+            }
+        }
+    }
+
+    /// Removes every expired key, recording their events, and returns how
+    /// many there were.
+    fn purge(&self, txn: &mut RwTxn<'_>, events: &mut Events) -> Result<usize, Error> {
+        let now = Deadline::now();
+        let mut expired = Vec::new();
+        for deadline in self.deadlines.prefix_iter(txn, &self.deadline_prefix)? {
+            let (key, deadline) = deadline?;
+            if Deadline::from_bytes(deadline)?.has_passed(now) {
+                expired.push(InlineArray::from(key));
+            }
+        }
+
+        for key in &expired {
+            self.deadlines.delete(txn, key)?;
+            let key = &key[self.deadline_prefix.len()..];
+            self.db.delete(txn, key)?;
+            events.remove(key);
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Counts the keys that haven't expired, as of `txn`.
+    fn count(&self, txn: &RoTxn) -> Result<usize, Error> {
+        let now = Deadline::now();
+        let mut expired = 0;
+        for deadline in self.deadlines.prefix_iter(txn, &self.deadline_prefix)? {
+            if Deadline::from_bytes(deadline?.1)?.has_passed(now) {
+                expired += 1;
+            }
+        }
+
+        Ok(self.db.len(txn)? as usize - expired)
+    }
+
+    fn holds(&self, key: &[u8]) -> bool {
+        !key.is_empty() && key.len() <= self.max_key_size
+    }
+
+    /// Turns `bounds` into ones LMDB can look keys up by, or `None` if no key
+    /// is within them.
+    ///
+    /// LMDB only holds keys that are neither empty nor longer than its
+    /// longest key, so bounds outside of that can be narrowed down to ones
+    /// within it without changing which keys they hold.
+    fn lookup_bounds(&self, (start, end): ByteBounds<'_>) -> Option<OwnedBounds> {
+        if iter::is_empty_range(&(start, end)) {
+            return None;
+        }
+
+        let max = self.max_key_size;
+        let start = match start {
+            Bound::Included([]) | Bound::Excluded([]) | Bound::Unbounded => Bound::Unbounded,
+            Bound::Included(key) | Bound::Excluded(key) if key.len() > max => {
+                Bound::Excluded(key[..max].to_vec())
+            }
+            bound => bound.map(<[u8]>::to_vec),
+        };
+        let end = match end {
+            Bound::Included([]) | Bound::Excluded([]) => return None,
+            Bound::Included(key) | Bound::Excluded(key) if key.len() > max => {
+                Bound::Included(key[..max].to_vec())
+            }
+            bound => bound.map(<[u8]>::to_vec),
+        };
+
+        Some((start, end))
+    }
+
+    /// Returns the entries within `bounds` that haven't expired, as of the
+    /// read transaction `txn`.
+    fn entries(
+        &self,
+        txn: &Arc<Mutex<ReadTxn>>,
+        bounds: ByteBounds<'_>,
+    ) -> Result<Iter<'static>, Error> {
+        let Some((start, end)) = self.lookup_bounds(bounds) else {
+            return Ok(Iter::empty());
+        };
+        let entries = Entries {
+            txn: Arc::clone(txn),
+            db: self.db,
+            start,
+            end,
+            failed: false,
+        };
+        if !self.expiring(&lock_txn(txn))? {
+            return Ok(Iter::fallible(entries));
+        }
+
+        let (txn, table) = (Arc::clone(txn), self.clone());
+        Ok(Iter::fallible(ttl::unexpired(entries, move |key| {
+            table.deadline(&lock_txn(&txn), key)
+        })))
+    }
+}
+
+/// Which end of a database to pop from.
+enum End {
+    First,
+    Last,
+}
+
+type OwnedBounds = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Removes every deadline in `deadlines` kept under `prefix`.
+fn clear_deadlines(txn: &mut RwTxn<'_>, deadlines: Database, prefix: &[u8]) -> Result<(), Error> {
+    let end = iter::prefix_upper_bound(prefix);
+    deadlines.delete_range(
+        txn,
+        &(Bound::Included(prefix), end.as_ref().map(Vec::as_slice)),
+    )?;
+
+    Ok(())
+}
+
+/// Locks a shared read transaction. Reads leave nothing behind when they
+/// panic, so a poisoned lock is as good as any.
+fn lock_txn(txn: &Mutex<ReadTxn>) -> MutexGuard<'_, ReadTxn> {
+    txn.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl AymrLmdb {
+    /// Opens the default tree and the names of the others, creating them if
+    /// needed.
+    fn from_env(env: Env, temporary: bool) -> Result<Self, Error> {
+        let mut txn = env.write_txn()?;
+        let db = env.create_database(&mut txn, Some(DEFAULT_DATABASE))?;
+        let trees = env.create_database(&mut txn, Some(TREES_DATABASE))?;
+        let deadlines = env.create_database(&mut txn, Some(DEADLINES_DATABASE))?;
+        txn.commit()?;
+
+        Ok(AymrLmdb {
+            table: Table {
+                db,
+                deadlines,
+                deadline_prefix: ttl::deadline_prefix(DEFAULT_DATABASE.as_bytes()).into(),
+                max_key_size: env.max_key_size(),
+            },
+            env: Arc::new(Environment {
+                env,
+                temporary,
+                subscribers: Mutex::default(),
+            }),
+            trees,
+            tree: None,
+            subscribers: Arc::default(),
+            merge_operator: MergeOperatorSlot::default(),
+        })
+    }
+
+    /// Fails if the tree of this handle has been dropped as of `txn`.
+    fn check_tree(&self, txn: &RoTxn) -> Result<(), Error> {
+        match &self.tree {
+            Some(tree) if self.trees.get(txn, tree.as_bytes())?.is_none() => {
+                Err(Error::CollectionNotFound(tree.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Begins a read transaction on the tree of this handle.
+    fn read(&self) -> Result<AymrLmdbSnapshot, Error> {
+        let txn = self.env.env.clone().static_read_txn()?;
+        self.check_tree(&txn)?;
+
+        Ok(AymrLmdbSnapshot {
+            txn: Arc::new(Mutex::new(txn)),
+            table: self.table.clone(),
+            _env: Arc::clone(&self.env),
+        })
+    }
+
+    /// Runs `f` in a write transaction on the tree of this handle, which
+    /// commits if `f` succeeds and aborts otherwise. The events `f` records
+    /// are published once it commits.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut RwTxn<'_>, &Table, &mut Events) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.subscribers.write(|events| {
+            let mut txn = self.env.env.write_txn()?;
+            self.check_tree(&txn)?;
+            let out = f(&mut txn, &self.table, events)?;
+            txn.commit()?;
+
+            Ok(out)
+        })
+    }
+
+    /// Validates the read set and applies the writes of a transaction inside
+    /// of a single write transaction, returning false if it conflicted.
+    pub(crate) fn commit<K, V>(&self, pending: PendingCommit<K, V>) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.write(|txn, table, events| {
+            for (key, seen) in pending.reads() {
+                if table.get(txn, key.as_ref())?.as_deref() != seen.as_deref() {
+                    return Ok(false);
+                }
+            }
+            for (key, value) in pending.writes() {
+                match value {
+                    Some(value) => table.put(txn, events, key.as_ref(), value.as_ref(), None)?,
+                    None => {
+                        table.delete(txn, events, key.as_ref())?;
+                    }
+                }
+            }
+            Ok(true)
+        })
+    }
+}
+
+impl fmt::Debug for AymrLmdb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrLmdb")
+            .field("path", &self.env.env.path())
+            .field("tree", &self.tree)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two handles are equal if they refer to the same tree of the same open
+/// environment.
+impl PartialEq for AymrLmdb {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.env, &other.env) && self.tree == other.tree
+    }
+}
+
+impl Eq for AymrLmdb {}
+
+impl<K, V> AymrDatabase<K, V> for AymrLmdb
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Snapshot = AymrLmdbSnapshot;
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.write(|txn, table, events| table.clear(txn, events))
+    }
+
+    fn len(&self) -> usize {
+        self.read()
+            .map(|snapshot| AymrSnapshot::<K>::len(&snapshot))
+            .unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        AymrSnapshot::<K>::is_empty(&self.read()?)
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        AymrSnapshot::<K>::get(&self.read()?, key)
+    }
+
+    /// Fails with `Error::KeyTooLarge` if the key is longer than LMDB's
+    /// longest key, 511 bytes unless LMDB was built otherwise.
+    fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+        self.write(|txn, table, events| {
+            let old = table.get(txn, key.as_ref())?;
+            table.put(txn, events, key.as_ref(), value.as_ref(), None)?;
+            Ok(old)
+        })
+    }
+
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.write(|txn, table, events| {
+            let old = table.get(txn, key.as_ref())?;
+            table.delete(txn, events, key.as_ref())?;
+            Ok(old)
+        })
+    }
+
+    /// The deadline is kept under the key along with the name of its tree,
+    /// so this fails with `Error::KeyTooLarge` for keys a little shorter than
+    /// `insert` takes.
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        let deadline = Deadline::after(ttl);
+        self.write(|txn, table, events| {
+            let old = table.get(txn, key.as_ref())?;
+            table.put(txn, events, key.as_ref(), value.as_ref(), Some(deadline))?;
+            Ok(old)
+        })
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        let snapshot = self.read()?;
+        let deadline = self.table.deadline(&snapshot.txn(), key.as_ref())?;
+        Ok(deadline.and_then(|deadline| deadline.left(Deadline::now())))
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        self.write(|txn, table, events| table.purge(txn, events))
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.write(|txn, table, events| {
+            let current = table.get(txn, key.as_ref())?;
+            if current.as_deref() != old.as_ref().map(AsRef::as_ref) {
+                return Ok(Err(CompareAndSwapError {
+                    current,
+                    proposed: new.as_ref().map(|v| v.as_ref().into()),
+                }));
+            }
+            match &new {
+                Some(value) => table.put(txn, events, key.as_ref(), value.as_ref(), None)?,
+                None => {
+                    table.delete(txn, events, key.as_ref())?;
+                }
+            }
+            Ok(Ok(()))
+        })
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.merge_operator.set(merge_operator);
+    }
+
+    /// Reads, merges and writes back inside of a single write transaction.
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        self.write(|txn, table, events| {
+            let old = table.get(txn, key.as_ref())?;
+            let new = self.merge_operator.get()?(key.as_ref(), old.as_deref(), operand.as_ref());
+            match new {
+                Some(value) => table.put(txn, events, key.as_ref(), &value, None)?,
+                None => {
+                    table.delete(txn, events, key.as_ref())?;
+                }
+            }
+            Ok(old)
+        })
+    }
+
+    /// Applied inside of a single write transaction, so either every
+    /// operation of the batch lands or none does.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        self.write(|txn, table, events| {
+            for op in batch {
+                match op {
+                    BatchOp::Clear => table.clear(txn, events)?,
+                    BatchOp::Insert(key, value) => {
+                        table.put(txn, events, key.as_ref(), value.as_ref(), None)?
+                    }
+                    BatchOp::Remove(key) => {
+                        table.delete(txn, events, key.as_ref())?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        AymrSnapshot::<K>::contains_key(&self.read()?, key)
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.read()?.entries((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.read()?.entries(iter::byte_bounds(&range))
+    }
+
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        let end = iter::prefix_upper_bound(prefix.as_ref());
+        self.read()?.entries((
+            Bound::Included(prefix.as_ref()),
+            end.as_ref().map(Vec::as_slice),
+        ))
+    }
+
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        let end = iter::prefix_upper_bound(prefix.as_ref());
+        self.write(|txn, table, events| {
+            table.delete_range(
+                txn,
+                events,
+                (
+                    Bound::Included(prefix.as_ref()),
+                    end.as_ref().map(Vec::as_slice),
+                ),
+            )
+        })
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Unbounded, Bound::Excluded(key.as_ref())))?
+            .next_back()
+            .transpose()
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Excluded(key.as_ref()), Bound::Unbounded))?
+            .next()
+            .transpose()
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Unbounded, Bound::Unbounded))?
+            .next()
+            .transpose()
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Unbounded, Bound::Unbounded))?
+            .next_back()
+            .transpose()
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.write(|txn, table, events| table.pop(txn, events, End::First))
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.write(|txn, table, events| table.pop(txn, events, End::Last))
+    }
+
+    /// Begins a read transaction, which keeps seeing the tree as it was until
+    /// it is dropped. LMDB can't reuse the pages it sees meanwhile, so
+    /// snapshots shouldn't be held on to for long.
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        self.read()
+    }
+
+    /// Only sees the writes made through Aymr, by any handle to the tree.
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        Ok(self.subscribers.subscribe(prefix.as_ref()))
+    }
+}
+
+/// Snapshot of an `AymrLmdb`, holding an LMDB read transaction.
+///
+/// Iterators over it, and over the database, hold on to the read transaction
+/// too, and look up every next entry by the key before it.
+#[derive(Clone)]
+pub struct AymrLmdbSnapshot {
+    txn: Arc<Mutex<ReadTxn>>,
+    table: Table,
+    _env: Arc<Environment>,
+}
+
+impl AymrLmdbSnapshot {
+    fn txn(&self) -> MutexGuard<'_, ReadTxn> {
+        lock_txn(&self.txn)
+    }
+
+    fn entries(&self, bounds: ByteBounds<'_>) -> Result<Iter<'static>, Error> {
+        self.table.entries(&self.txn, bounds)
+    }
+}
+
+impl fmt::Debug for AymrLmdbSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrLmdbSnapshot").finish_non_exhaustive()
+    }
+}
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrLmdbSnapshot {
+    fn len(&self) -> usize {
+        self.table.count(&self.txn()).unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        let first = self
+            .entries((Bound::Unbounded, Bound::Unbounded))?
+            .next()
+            .transpose()?;
+        Ok(first.is_none())
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.table.get(&self.txn(), key.as_ref())
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(AymrSnapshot::<K>::get(self, key)?.is_some())
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.entries((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.entries(iter::byte_bounds(&range))
+    }
+}
+
+/// Walks the entries within `start` and `end` as of a read transaction.
+///
+/// heed's iterators borrow their transaction, so this looks up every entry
+/// by the key before it instead, moving the bounds inwards as it goes. A
+/// lookup that fails is yielded as an error and ends the walk.
+struct Entries {
+    txn: Arc<Mutex<ReadTxn>>,
+    db: Database,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    failed: bool,
+}
+
+impl Entries {
+    fn txn(&self) -> MutexGuard<'_, ReadTxn> {
+        lock_txn(&self.txn)
+    }
+}
+
+impl Iterator for Entries {
+    type Item = Result<(InlineArray, InlineArray), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let found = {
+            let txn = self.txn();
+            match &self.start {
+                Bound::Unbounded => self.db.first(&txn),
+                Bound::Included(key) => self.db.get_greater_than_or_equal_to(&txn, key),
+                Bound::Excluded(key) => self.db.get_greater_than(&txn, key),
+            }
+            .map(|found| found.map(entry))
+        };
+        match found {
+            Ok(Some(entry)) if below(&entry.0, &self.end) => {
+                self.start = Bound::Excluded(entry.0.to_vec());
+                Some(Ok(entry))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for Entries {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let found = {
+            let txn = self.txn();
+            match &self.end {
+                Bound::Unbounded => self.db.last(&txn),
+                Bound::Included(key) => self.db.get_lower_than_or_equal_to(&txn, key),
+                Bound::Excluded(key) => self.db.get_lower_than(&txn, key),
+            }
+            .map(|found| found.map(entry))
+        };
+        match found {
+            Ok(Some(entry)) if above(&entry.0, &self.start) => {
+                self.end = Bound::Excluded(entry.0.to_vec());
+                Some(Ok(entry))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+/// Returns true if `key` is within the upper bound `end`.
+fn below(key: &[u8], end: &Bound<Vec<u8>>) -> bool {
+    match end {
+        Bound::Unbounded => true,
+        Bound::Included(end) => key <= end.as_slice(),
+        Bound::Excluded(end) => key < end.as_slice(),
+    }
+}
+
+/// Returns true if `key` is within the lower bound `start`.
+fn above(key: &[u8], start: &Bound<Vec<u8>>) -> bool {
+    match start {
+        Bound::Unbounded => true,
+        Bound::Included(start) => key >= start.as_slice(),
+        Bound::Excluded(start) => key > start.as_slice(),
+    }
+}
+
+/// Commits inside of a single LMDB write transaction, which LMDB runs one at
+/// a time, so the read set is validated against the writes of every handle.
+impl<K, V> AymrTransactional<K, V> for AymrLmdb
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |db, pending| db.commit(pending))
+    }
+}
+
+/// Trees are LMDB named databases, so their names have to be UTF-8, and
+/// there can only be as many as the `max_trees` the database was opened
+/// with. A dropped tree is emptied, and LMDB keeps it around for when it is
+/// opened again.
+impl<K, V> AymrTrees<K, V> for AymrLmdb
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Tree = AymrLmdb;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        let (tree, database) = self.database_name(name.as_ref())?;
+        let env = &self.env.env;
+
+        let mut txn = env.write_txn()?;
+        let db = env.create_database(&mut txn, Some(&database))?;
+        self.trees.put(&mut txn, tree.as_bytes(), &[])?;
+        txn.commit()?;
+        let subscribers = Arc::clone(
+            self.env
+                .subscribers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(tree.clone())
+                .or_default(),
+        );
+
+        Ok(AymrLmdb {
+            env: Arc::clone(&self.env),
+            table: Table {
+                db,
+                deadlines: self.table.deadlines,
+                deadline_prefix: ttl::deadline_prefix(database.as_bytes()).into(),
+                max_key_size: self.table.max_key_size,
+            },
+            trees: self.trees,
+            tree: Some(tree),
+            subscribers,
+            merge_operator: MergeOperatorSlot::default(),
+        })
+    }
+
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        let (tree, database) = self.database_name(name.as_ref())?;
+        let env = &self.env.env;
+
+        let mut txn = env.write_txn()?;
+        if !self.trees.delete(&mut txn, tree.as_bytes())? {
+            return Ok(false);
+        }
+        if let Some(db) = env.open_database::<Bytes, Bytes>(&txn, Some(&database))? {
+            db.clear(&mut txn)?;
+        }
+        let prefix = ttl::deadline_prefix(database.as_bytes());
+        clear_deadlines(&mut txn, self.table.deadlines, &prefix)?;
+        txn.commit()?;
+
+        Ok(true)
+    }
+
+    /// Returns no names if the database can't be read.
+    fn tree_names(&self) -> Vec<InlineArray> {
+        let Ok(txn) = self.env.env.read_txn() else {
+            return Vec::new();
+        };
+        let Ok(names) = self.trees.iter(&txn) else {
+            return Vec::new();
+        };
+
+        names
+            .filter_map(|name| name.ok().map(|(name, _)| InlineArray::from(name)))
+            .collect()
+    }
+}
+
+impl AymrLmdb {
+    /// Returns the name of the tree `name` and of the database holding it.
+    fn database_name(&self, name: &[u8]) -> Result<(String, String), Error> {
+        let max = self.table.max_key_size - TREE_PREFIX.len();
+        let tree = match std::str::from_utf8(name) {
+            Ok("") | Err(_) => {
+                return Err(Error::Unsupported(
+                    "`lmdb` can only name trees by non-empty UTF-8 strings".to_string(),
+                ))
+            }
+            Ok(tree) if tree.len() > max => {
+                return Err(Error::KeyTooLarge {
+                    len: tree.len(),
+                    max,
+                })
+            }
+            Ok(tree) => tree.to_string(),
+        };
+        let database = format!("{}{}", TREE_PREFIX, tree);
+
+        Ok((tree, database))
+    }
+}
+
+/// Flushes the environment to disk, which makes every commit so far durable.
+impl AymrFlush for AymrLmdb {
+    fn flush(&self) -> Result<(), Error> {
+        Ok(self.env.env.force_sync()?)
+    }
+}
+
+impl AymrOpenable for AymrLmdb {
+    type Config = LmdbConfig;
+
+    /// A `temporary` database without a `path` gets a fresh directory of its
+    /// own, which is removed once the last handle is dropped.
+    fn open(config: LmdbConfig) -> Result<Self, Error> {
+        let path = match (&config.path, config.temporary) {
+            (Some(path), _) => path.clone(),
+            (None, true) => temporary_dir(),
+            (None, false) => {
+                return Err(Error::Config(
+                    "the `lmdb` backend needs a `path` unless it is `temporary`".to_string(),
+                ))
+            }
+        };
+        fs::create_dir_all(&path)?;
+
+        let mut options = EnvOpenOptions::new().read_txn_without_tls();
+        // The default tree, the names of the trees and the deadlines of their
+        // keys take a database each.
+        options.max_dbs(config.max_trees.saturating_add(3));
+        if let Some(bytes) = config.map_size {
+            options.map_size(bytes);
+        }
+        // SAFETY: LMDB's memory map is only unsafe to use if the files it maps
+        // are changed by anything but LMDB, or if its lock file is broken. The
+        // environment is opened with locking, and Aymr writes through LMDB
+        // only. Without syncs, a system crash may lose the commits since the
+        // last flush but leaves the database intact.
+        let env = unsafe {
+            if config.durability == Durability::Buffered {
+                options.flags(EnvFlags::NO_SYNC);
+            }
+            options.open(&path)?
+        };
+
+        AymrLmdb::from_env(env, config.temporary)
+    }
+
+    fn create_new(config: LmdbConfig) -> Result<Self, Error> {
+        if config.path.as_ref().is_some_and(|path| exists(path)) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "there already is an `lmdb` database at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    fn open_existing(config: LmdbConfig) -> Result<Self, Error> {
+        if !config.path.as_ref().is_some_and(|path| exists(path)) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "there is no `lmdb` database at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    /// Opens a database in a fresh temporary directory.
+    fn temporary() -> Result<Self, Error> {
+        Self::open(LmdbConfig {
+            temporary: true,
+            ..LmdbConfig::new(temporary_dir())
+        })
+    }
+
+    fn close(self) -> Result<(), Error> {
+        AymrFlush::flush(&self)
+    }
+}
+
+/// Returns true if an LMDB environment has been created at `path`.
+fn exists(path: &Path) -> bool {
+    path.join("data.mdb").is_file()
+}
+
+/// Returns a directory no other temporary database uses.
+fn temporary_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    std::env::temp_dir().join(format!(
+        "aymr-lmdb-{}-{}-{}",
+        std::process::id(),
+        nanos,
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+fn entry((key, value): (&[u8], &[u8])) -> (InlineArray, InlineArray) {
+    (InlineArray::from(key), InlineArray::from(value))
+}
+
+impl From<heed::Error> for Error {
+    fn from(error: heed::Error) -> Self {
+        match error {
+            heed::Error::Io(e) => Error::Io(e),
+            heed::Error::Mdb(MdbError::MapFull) => Error::MapFull,
+            heed::Error::Mdb(MdbError::DbsFull) => {
+                Error::Config(
+                    "the `lmdb` database holds `max_trees` trees already, reopen it with more"
+                        .to_string(),
+                )
+            }
+            heed::Error::Mdb(MdbError::ReadersFull) => {
+                Error::Io(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "every `lmdb` reader slot is taken by an open read transaction",
+                ))
+            }
+            heed::Error::Mdb(MdbError::TxnFull) => {
+                Error::Unsupported("the `lmdb` write transaction has too many changes".to_string())
+            }
+            heed::Error::Mdb(
+                e @ (MdbError::Corrupted
+                | MdbError::PageNotFound
+                | MdbError::Invalid
+                | MdbError::Panic),
+            ) => Error::corruption(Some(e.to_string())),
+            heed::Error::Mdb(MdbError::VersionMismatch) => {
+                Error::Unsupported(
+                    "the `lmdb` database was made by an incompatible version".to_string(),
+                )
+            }
+            heed::Error::EnvAlreadyOpened => {
+                Error::Config("the `lmdb` database is already open with other settings".to_string())
+            }
+            other => Error::ReportableBug(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aymr_db::{
+        backend::BackendKind,
+        config::{
+            AymrDbBuilder,
+            LmdbConfig,
+        },
+        db::{
+            tests::test_dir,
+            AymrDb,
+        },
+        error::Error,
+        traits::{
+            AymrBatch,
+            AymrDatabase,
+            AymrTrees,
+            Batch,
+        },
+    };
+
+    fn open_temporary() -> AymrDb<Vec<u8>, Vec<u8>> {
+        AymrDbBuilder::new()
+            .backend(BackendKind::Lmdb)
+            .temporary(true)
+            .map_size(1 << 24)
+            .open()
+            .unwrap()
+    }
+
+    /// Temporary environments are removed once dropped, even at a path.
+    #[test]
+    fn test_temporary_at_path() {
+        let dir = test_dir("lmdb-temporary");
+        let mut config = LmdbConfig::new(&dir);
+        config.temporary = true;
+
+        let mut db = AymrDb::<Vec<u8>, Vec<u8>>::open(config).unwrap();
+        db.insert(vec![1], vec![1]).unwrap();
+        assert!(dir.is_dir());
+        drop(db);
+        assert!(!dir.exists());
+    }
+
+    /// A full map fails writes until it is reopened with a larger one.
+    #[test]
+    fn test_map_full() {
+        let dir = test_dir("lmdb-map-full");
+        let config = LmdbConfig {
+            map_size: Some(1 << 16),
+            ..LmdbConfig::new(&dir)
+        };
+
+        let mut db = AymrDb::<Vec<u8>, Vec<u8>>::open(config.clone()).unwrap();
+        let res = (0..64u8).try_for_each(|i| db.insert(vec![i], vec![0; 4096]).map(drop));
+        assert_eq!(res, Err(Error::MapFull));
+        let len = db.len();
+        assert!(len > 0);
+        db.close().unwrap();
+
+        let mut db = AymrDb::<Vec<u8>, Vec<u8>>::open(LmdbConfig {
+            map_size: Some(1 << 20),
+            ..config
+        })
+        .unwrap();
+        assert_eq!(db.len(), len);
+        db.insert(vec![64], vec![0; 4096]).unwrap();
+        drop(db);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Keys LMDB can't hold are rejected by writes, and simply not found by
+    /// reads.
+    #[test]
+    fn test_key_limits() {
+        let mut db = open_temporary();
+        db.insert(b"b1".into(), b"3".into()).unwrap();
+
+        assert_eq!(
+            db.insert(vec![b'a'; 512], b"4".into()),
+            Err(Error::KeyTooLarge { len: 512, max: 511 })
+        );
+        assert!(matches!(
+            db.insert(Vec::new(), b"4".into()),
+            Err(Error::Unsupported(_))
+        ));
+        db.insert(vec![b'a'; 511], b"4".into()).unwrap();
+        assert_eq!(db.get(&[b'a'; 512]).unwrap(), None);
+        assert_eq!(db.get(b"").unwrap(), None);
+        assert_eq!(
+            db.range(vec![b'a'; 512]..)
+                .unwrap()
+                .next()
+                .transpose()
+                .unwrap(),
+            Some(("b1".into(), "3".into()))
+        );
+        assert_eq!(
+            db.range(..=vec![b'a'; 512])
+                .unwrap()
+                .next_back()
+                .transpose()
+                .unwrap(),
+            Some((vec![b'a'; 511].into(), "4".into()))
+        );
+        assert_eq!(db.remove(&[b'a'; 511]).unwrap(), Some("4".into()));
+    }
+
+    /// Batches are applied in a single write transaction, so none of one is
+    /// if any op fails.
+    #[test]
+    fn test_failed_batch_applies_nothing() {
+        let mut db = open_temporary();
+        db.insert(vec![1], vec![1]).unwrap();
+
+        let mut batch = AymrBatch::new();
+        batch.clear();
+        batch.insert(vec![0; 512], vec![2]);
+        assert!(matches!(
+            db.apply_batch(batch),
+            Err(Error::KeyTooLarge { .. })
+        ));
+        assert_eq!(db.get(&[1]).unwrap(), Some(vec![1].into()));
+    }
+
+    #[test]
+    fn test_tree_names_are_utf8() {
+        let mut db = open_temporary();
+        assert!(matches!(db.open_tree([0xff]), Err(Error::Unsupported(_))));
+        assert!(db.tree_names().is_empty());
+    }
+
+    /// Handles to a dropped tree work again once it is opened again.
+    #[test]
+    fn test_dropped_tree_handles_revive() {
+        let mut db = open_temporary();
+        let mut tree = db.open_tree(b"tree").unwrap();
+        tree.insert(vec![1], vec![1]).unwrap();
+        assert!(db.drop_tree(b"tree").unwrap());
+        assert!(tree.get(&[1]).is_err());
+
+        let reopened = db.open_tree(b"tree").unwrap();
+        assert!(reopened.is_empty().unwrap());
+        assert!(tree.is_empty().unwrap());
+    }
+}
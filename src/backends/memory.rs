@@ -0,0 +1,416 @@
+//! `In-memory core`
+//!
+//! Everything the in-memory backends have in common, written once over the
+//! map their entries are stored in. `AymrBtreeMap` and `AymrHashMap` wrap a
+//! `MemoryDb` and only add what depends on the order of their keys.
+
+use std::{
+    ops::Bound,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use crate::aymr_db::{
+    error::{
+        CompareAndSwapError,
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::{
+        self,
+        ByteBounds,
+        Iter,
+    },
+    merge::{
+        MergeOperator,
+        MergeOperatorSlot,
+    },
+    traits::{
+        AymrBatch,
+        BatchOp,
+        InlineArray,
+    },
+    ttl::{
+        self,
+        ExpiryIndex,
+    },
+    watch::{
+        Event,
+        Subscriber,
+        Subscribers,
+    },
+};
+
+/// A map from bytes to bytes an in-memory backend keeps its entries in.
+///
+/// Maps are persistent: cloning one is cheap, and the clone shares every node
+/// that neither of them writes to afterwards.
+pub(crate) trait MemoryMap: Clone + Default {
+    fn get(&self, key: &[u8]) -> Option<&InlineArray>;
+
+    fn insert(&mut self, key: InlineArray, value: InlineArray) -> Option<InlineArray>;
+
+    fn remove_entry(&mut self, key: &[u8]) -> Option<(InlineArray, InlineArray)>;
+
+    fn len(&self) -> usize;
+
+    fn keys(&self) -> impl Iterator<Item = &InlineArray>;
+
+    /// Removes every key starting with `prefix`, returning the removed keys.
+    fn remove_prefix(&mut self, prefix: &[u8]) -> Vec<InlineArray>;
+
+    /// Returns the entries within `bounds` that haven't expired, in key order.
+    ///
+    /// `bounds` is never an empty range.
+    fn range<'a>(&'a self, expiry: &'a ExpiryIndex, bounds: ByteBounds<'_>) -> Iter<'a>;
+
+    /// Like `range`, but the iterator holds on to `map` and `expiry` itself.
+    fn range_owned(map: Self, expiry: ExpiryIndex, bounds: OwnedBounds) -> Iter<'static>;
+}
+
+/// Bounds of a range of keys, owned by whoever scans it.
+pub(crate) type OwnedBounds = (Bound<InlineArray>, Bound<InlineArray>);
+
+/// Entries, deadlines, merge operator and subscriptions of an in-memory
+/// backend.
+///
+/// The map and the expiry index share their nodes with any snapshots taken of
+/// them. A write while a snapshot is alive only copies the nodes on the way to
+/// the keys it changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct MemoryDb<M> {
+    db: M,
+    expiry: ExpiryIndex,
+    merge_operator: MergeOperatorSlot,
+    subscribers: Subscribers,
+}
+
+impl<M: MemoryMap> MemoryDb<M> {
+    /// Returns a database holding the entries of `map`.
+    pub(crate) fn new(map: M) -> Self {
+        MemoryDb {
+            db: map,
+            ..MemoryDb::default()
+        }
+    }
+
+    /// Drops the time to live of `key`, if it had one.
+    fn forget_ttl(&mut self, key: &[u8]) {
+        if !self.expiry.is_empty() {
+            self.expiry.unset(key);
+        }
+    }
+
+    /// Removes every expired key, publishing their removal.
+    ///
+    /// Every write calls this first, so writes never see an expired key.
+    pub(crate) fn remove_expired(&mut self) -> usize {
+        let now = Instant::now();
+        if !self.expiry.has_expired(now) {
+            return 0;
+        }
+
+        let expired = self.expiry.take_expired(now);
+        for key in &expired {
+            self.db.remove_entry(key);
+        }
+        if !self.subscribers.is_empty() {
+            self.subscribers
+                .publish(expired.iter().cloned().map(Event::remove));
+        }
+
+        expired.len()
+    }
+
+    /// Inserts `key`, publishing the insert and returning the old value.
+    fn insert_bytes(&mut self, key: InlineArray, value: InlineArray) -> Option<InlineArray> {
+        self.forget_ttl(&key);
+        let event =
+            (!self.subscribers.is_empty()).then(|| Event::insert(key.clone(), value.clone()));
+        let old = self.db.insert(key, value);
+        self.subscribers.publish(event);
+
+        old
+    }
+
+    /// Removes `key`, publishing the removal and returning the old value.
+    pub(crate) fn remove_bytes(&mut self, key: &[u8]) -> Option<InlineArray> {
+        self.forget_ttl(key);
+        let (key, old) = self.db.remove_entry(key)?;
+        self.subscribers.publish_with(|| Event::remove(key));
+
+        Some(old)
+    }
+
+    /// Returns the events `op` would publish, given the current contents of the map.
+    fn events_for<K, V>(&self, op: &BatchOp<K, V>) -> Vec<Event>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        match op {
+            BatchOp::Clear => self.db.keys().cloned().map(Event::remove).collect(),
+            BatchOp::Insert(key, value) => vec![Event::insert(key.as_ref(), value.as_ref())],
+            BatchOp::Remove(key) if self.db.get(key.as_ref()).is_some() => {
+                vec![Event::remove(key.as_ref())]
+            }
+            BatchOp::Remove(_) => Vec::new(),
+        }
+    }
+
+    /// Applies `op` to the map. Nothing here can fail.
+    fn apply_op<K, V>(&mut self, op: BatchOp<K, V>)
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        match op {
+            BatchOp::Clear => {
+                self.expiry = ExpiryIndex::default();
+                self.db = M::default();
+            }
+            BatchOp::Insert(key, value) => {
+                let key = InlineArray::from(key.as_ref());
+                self.forget_ttl(&key);
+                self.db.insert(key, value.as_ref().into());
+            }
+            BatchOp::Remove(key) => {
+                self.forget_ttl(key.as_ref());
+                self.db.remove_entry(key.as_ref());
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.remove_expired();
+        self.expiry = ExpiryIndex::default();
+        let old = std::mem::take(&mut self.db);
+        if !self.subscribers.is_empty() {
+            self.subscribers
+                .publish(old.keys().cloned().map(Event::remove));
+        }
+    }
+
+    /// Expired keys that have not been purged yet are not counted.
+    pub(crate) fn len(&self) -> usize {
+        len(&self.db, &self.expiry)
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<InlineArray> {
+        get(&self.db, &self.expiry, key)
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<InlineArray> {
+        self.remove_expired();
+        self.insert_bytes(key.into(), value.into())
+    }
+
+    pub(crate) fn remove(&mut self, key: &[u8]) -> Option<InlineArray> {
+        self.remove_expired();
+        self.remove_bytes(key)
+    }
+
+    pub(crate) fn insert_with_ttl(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Option<InlineArray> {
+        let deadline = ttl::deadline_after(ttl);
+        let key = InlineArray::from(key);
+        self.remove_expired();
+        let old = self.insert_bytes(key.clone(), value.into());
+        self.expiry.set(key, deadline);
+
+        old
+    }
+
+    pub(crate) fn ttl(&self, key: &[u8]) -> Option<Duration> {
+        self.expiry.ttl(key, Instant::now())
+    }
+
+    pub(crate) fn compare_and_swap<OV, V>(
+        &mut self,
+        key: &[u8],
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult
+    where
+        OV: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.remove_expired();
+        let current = self.db.get(key);
+        let matches = match (current, &old) {
+            (None, None) => true,
+            (Some(current), Some(old)) => current.as_ref() == old.as_ref(),
+            _ => false,
+        };
+
+        if !matches {
+            return Ok(Err(CompareAndSwapError {
+                current: current.cloned(),
+                proposed: new.map(|v| v.as_ref().into()),
+            }));
+        }
+
+        match new {
+            Some(value) => {
+                self.insert_bytes(key.into(), value.as_ref().into());
+            }
+            None => {
+                self.remove_bytes(key);
+            }
+        }
+
+        Ok(Ok(()))
+    }
+
+    pub(crate) fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.merge_operator.set(merge_operator);
+    }
+
+    pub(crate) fn merge(
+        &mut self,
+        key: &[u8],
+        operand: &[u8],
+    ) -> Result<Option<InlineArray>, Error> {
+        self.remove_expired();
+        let old = self.db.get(key).cloned();
+        let new = self.merge_operator.get()?(key, old.as_deref(), operand);
+
+        match new {
+            Some(value) => {
+                self.insert_bytes(key.into(), value.into());
+            }
+            None => {
+                self.remove_bytes(key);
+            }
+        }
+
+        Ok(old)
+    }
+
+    /// Applying an operation to an in-memory map can't fail, so the batch
+    /// always lands in full.
+    pub(crate) fn apply_batch<K, V>(&mut self, batch: AymrBatch<K, V>)
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.remove_expired();
+        let watched = !self.subscribers.is_empty();
+        let mut events = Vec::new();
+        for op in batch {
+            if watched {
+                events.extend(self.events_for(&op));
+            }
+            self.apply_op(op);
+        }
+        self.subscribers.publish(events);
+    }
+
+    /// Returns the live entries within `bounds`, in key order.
+    pub(crate) fn range(&self, bounds: ByteBounds<'_>) -> Iter<'_> {
+        range(&self.db, &self.expiry, bounds)
+    }
+
+    /// Returns the live entries within `bounds` in key order, without
+    /// borrowing the database.
+    ///
+    /// The iterator keeps the contents as of this call alive, the same way a
+    /// snapshot does.
+    pub(crate) fn range_owned(&self, bounds: ByteBounds<'_>) -> Iter<'static> {
+        if iter::is_empty_range(&bounds) {
+            return Iter::empty();
+        }
+
+        M::range_owned(
+            self.db.clone(),
+            self.expiry.clone(),
+            (
+                bounds.0.map(InlineArray::from),
+                bounds.1.map(InlineArray::from),
+            ),
+        )
+    }
+
+    /// Returns the live entries whose keys start with `prefix`, in key order.
+    pub(crate) fn scan_prefix(&self, prefix: &[u8]) -> Iter<'_> {
+        let end = iter::prefix_upper_bound(prefix);
+        self.range((Bound::Included(prefix), end.as_ref().map(Vec::as_slice)))
+    }
+
+    pub(crate) fn remove_prefix(&mut self, prefix: &[u8]) -> usize {
+        self.remove_expired();
+        let removed = self.db.remove_prefix(prefix);
+        if !self.expiry.is_empty() {
+            self.expiry.unset_prefix(prefix);
+        }
+        let count = removed.len();
+        self.subscribers
+            .publish(removed.into_iter().map(Event::remove));
+
+        count
+    }
+
+    pub(crate) fn snapshot(&self) -> MemorySnapshot<M> {
+        MemorySnapshot {
+            db: self.db.clone(),
+            expiry: self.expiry.clone(),
+        }
+    }
+
+    pub(crate) fn watch_prefix(&self, prefix: &[u8]) -> Subscriber {
+        self.subscribers.subscribe(prefix)
+    }
+}
+
+/// Point-in-time snapshot of a `MemoryDb`.
+///
+/// Taking a snapshot only bumps the reference counts of the roots of the map
+/// and the expiry index. Keys still expire while the snapshot is held.
+#[derive(Debug, Clone)]
+pub(crate) struct MemorySnapshot<M> {
+    db: M,
+    expiry: ExpiryIndex,
+}
+
+impl<M: MemoryMap> MemorySnapshot<M> {
+    pub(crate) fn len(&self) -> usize {
+        len(&self.db, &self.expiry)
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Option<InlineArray> {
+        get(&self.db, &self.expiry, key)
+    }
+
+    pub(crate) fn range(&self, bounds: ByteBounds<'_>) -> Iter<'_> {
+        range(&self.db, &self.expiry, bounds)
+    }
+}
+
+/// Returns how many keys of `db` haven't expired.
+fn len<M: MemoryMap>(db: &M, expiry: &ExpiryIndex) -> usize {
+    db.len() - expiry.expired_count(Instant::now())
+}
+
+/// Returns the value of `key` in `db`, unless it expired.
+fn get<M: MemoryMap>(db: &M, expiry: &ExpiryIndex, key: &[u8]) -> Option<InlineArray> {
+    if expiry.is_expired(key, Instant::now()) {
+        return None;
+    }
+
+    db.get(key).cloned()
+}
+
+/// Returns the live entries of `db` within `bounds`, in key order.
+fn range<'a, M: MemoryMap>(db: &'a M, expiry: &'a ExpiryIndex, bounds: ByteBounds<'_>) -> Iter<'a> {
+    if iter::is_empty_range(&bounds) {
+        return Iter::empty();
+    }
+
+    db.range(expiry, bounds)
+}
@@ -1,13 +1,31 @@
 //! # Aymr backends
-//! 
+//!
 //! This mod contains various backends that can be used with Aymr.
-//! 
+//!
 //! Each mod inside contains the necessary bindings for each backend.
 //! Currently the supported backends are:
-//! 
+//!
 //! - [BTreeMap](https://doc.rust-lang.org/std/collections/struct.BTreeMap.html)
 //! - [HashMap](https://doc.rust-lang.org/std/collections/struct.HashMap.html)
+//! - [sled](https://docs.rs/sled/0.34.7) `0.34`
+//! - [sled](https://docs.rs/sled/1.0.0-alpha.124) `1.0-alpha`
+//! - [redb](https://docs.rs/redb/4.4.0) `4`
+//! - [fjall](https://docs.rs/fjall/2.11.2) `2`
+//! - [LMDB](https://docs.rs/heed/0.22.1) through heed `0.22`
 
+#[cfg(feature = "btreemap")]
 pub mod btreemap;
+#[cfg(feature = "fjall")]
+pub mod fjall;
+#[cfg(feature = "hashmap")]
 pub mod hashmap;
+#[cfg(feature = "lmdb")]
+pub mod lmdb;
+#[cfg(any(feature = "btreemap", feature = "hashmap"))]
+mod memory;
+#[cfg(feature = "redb")]
+pub mod redb;
+#[cfg(feature = "sled_alpha")]
+pub mod sled_alpha;
+#[cfg(feature = "sled_pre")]
 pub mod sled_pre;
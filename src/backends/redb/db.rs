@@ -0,0 +1,1063 @@
+//! `redb`
+//!
+//! This is an impl of the aymr DB traits for `redb`, a pure Rust embedded
+//! database with ACID transactions.
+//!
+//! Using redb is optimal when:
+//!
+//! - You want your data on disk, in a single file, surviving restarts.
+//! - You want every write to be atomic and crash safe.
+//! - You want ordered keys, ranges and prefix scans.
+//! - You want consistent snapshots while others keep writing.
+//!
+//! Trees map onto tables, and every write, batch and transaction is a single
+//! redb write transaction. Snapshots are read transactions. redb has no expiry,
+//! so the deadlines of expiring keys live in a table of their own, and watching
+//! a prefix sees every write made through Aymr once it commits.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs,
+    io,
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
+    time::Duration,
+};
+
+use redb::{
+    ReadableDatabase,
+    ReadableTable,
+    ReadableTableMetadata,
+    TableDefinition,
+};
+
+use crate::aymr_db::{
+    config::{
+        Durability,
+        RedbConfig,
+    },
+    error::{
+        CompareAndSwapError,
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::{
+        self,
+        ByteBounds,
+        Iter,
+    },
+    merge::{
+        MergeOperator,
+        MergeOperatorSlot,
+    },
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrFlush,
+        AymrOpenable,
+        AymrSnapshot,
+        AymrTransactional,
+        AymrTrees,
+        BatchOp,
+        InlineArray,
+    },
+    transaction::{
+        self,
+        ConflictableTransactionResult,
+        PendingCommit,
+        Transaction,
+        TransactionResult,
+    },
+    ttl::{
+        self,
+        Deadline,
+    },
+    watch::{
+        Events,
+        Subscriber,
+        Subscribers,
+    },
+};
+
+type Bytes = &'static [u8];
+type ReadTable = redb::ReadOnlyTable<Bytes, Bytes>;
+type WriteTable<'txn> = redb::Table<'txn, Bytes, Bytes>;
+
+/// Table holding the default tree. Named trees live in tables prefixed with
+/// `TREE_PREFIX`, so they can't clash with it.
+const DEFAULT_TABLE: &str = "aymr";
+const TREE_PREFIX: &str = "aymr/";
+/// Table holding the deadlines of the expiring keys of every tree.
+const DEADLINES_TABLE: &str = "aymr#deadlines";
+
+/// Struct implementing `AymrDatabase` and `AymrOpenable` over a table of a
+/// `redb::Database`.
+///
+/// The database itself opens on its default table, and `open_tree` hands out
+/// handles to named ones. Handles are cheap to clone, and every clone refers
+/// to the same table.
+#[derive(Clone)]
+pub struct AymrRedb {
+    db: Arc<Database>,
+    table: String,
+    /// The `ttl::deadline_prefix` of the table.
+    deadline_prefix: InlineArray,
+    /// State of the tree of this handle.
+    state: Arc<TreeState>,
+    /// State of every tree with open handles, by table name.
+    trees: Arc<Mutex<HashMap<String, Arc<TreeState>>>>,
+    durability: Durability,
+    merge_operator: MergeOperatorSlot,
+}
+
+/// What every handle to a tree shares.
+struct TreeState {
+    /// Cleared once the tree is dropped.
+    alive: AtomicBool,
+    subscribers: Subscribers,
+}
+
+impl Default for TreeState {
+    fn default() -> Self {
+        TreeState {
+            alive: AtomicBool::new(true),
+            subscribers: Subscribers::default(),
+        }
+    }
+}
+
+/// The open database, persisted once the last handle to it goes away.
+struct Database(redb::Database);
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        // Nothing can be reported from here, `close` is the way to see errors.
+        let _ = persist(&self.0);
+    }
+}
+
+impl AymrRedb {
+    fn from_db(db: redb::Database, durability: Durability) -> Result<Self, Error> {
+        let db = AymrRedb {
+            db: Arc::new(Database(db)),
+            table: DEFAULT_TABLE.to_string(),
+            deadline_prefix: ttl::deadline_prefix(DEFAULT_TABLE.as_bytes()).into(),
+            state: Arc::default(),
+            trees: Arc::default(),
+            durability,
+            merge_operator: MergeOperatorSlot::default(),
+        };
+        db.write(|_, _| Ok(()))?;
+
+        Ok(db)
+    }
+
+    /// Fails if the tree of this handle has been dropped.
+    fn check_alive(&self) -> Result<(), Error> {
+        if self.state.alive.load(Ordering::Acquire) {
+            Ok(())
+        } else {
+            Err(Error::CollectionNotFound(
+                self.table
+                    .strip_prefix(TREE_PREFIX)
+                    .unwrap_or(&self.table)
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Opens the table of this handle for reading.
+    fn read(&self) -> Result<AymrRedbSnapshot, Error> {
+        let txn = self.db.0.begin_read()?;
+        self.check_alive()?;
+        let table = txn.open_table(definition(&self.table))?;
+        let deadlines = txn.open_table(definition(DEADLINES_TABLE))?;
+
+        Ok(AymrRedbSnapshot {
+            table: Arc::new(table),
+            deadlines: Arc::new(deadlines),
+            deadline_prefix: self.deadline_prefix.clone(),
+        })
+    }
+
+    /// Runs `f` in a write transaction, which commits if `f` succeeds and
+    /// rolls back otherwise.
+    fn transact<T>(
+        &self,
+        f: impl FnOnce(&redb::WriteTransaction) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut txn = self.db.0.begin_write()?;
+        txn.set_durability(match self.durability {
+            Durability::Buffered => redb::Durability::None,
+            Durability::Immediate => redb::Durability::Immediate,
+        })?;
+        let out = f(&txn)?;
+        txn.commit()?;
+
+        Ok(out)
+    }
+
+    /// Runs `f` on the tables of this handle in a write transaction, creating
+    /// them if needed, and publishes the events `f` records once it commits.
+    ///
+    /// `drop_tree` clears the liveness of a tree inside of its own write
+    /// transaction, and redb runs those one at a time, so a dropped tree can't
+    /// be created again by a handle that is left over.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&mut Tables<'_, '_>, &mut Events) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.state.subscribers.write(|events| {
+            self.transact(|txn| {
+                self.check_alive()?;
+                let mut tables = Tables {
+                    data: txn.open_table(definition(&self.table))?,
+                    deadlines: txn.open_table(definition(DEADLINES_TABLE))?,
+                    deadline_prefix: &self.deadline_prefix,
+                    now: Deadline::now(),
+                };
+                f(&mut tables, events)
+            })
+        })
+    }
+
+    /// Validates the read set and applies the writes of a transaction inside
+    /// of a single write transaction, returning false if it conflicted.
+    pub(crate) fn commit<K, V>(&self, pending: PendingCommit<K, V>) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.write(|tables, events| {
+            for (key, seen) in pending.reads() {
+                if tables.get(key.as_ref())?.as_deref() != seen.as_deref() {
+                    return Ok(false);
+                }
+            }
+            for (key, value) in pending.writes() {
+                match value {
+                    Some(value) => tables.put(events, key.as_ref(), value.as_ref(), None)?,
+                    None => tables.delete(events, key.as_ref())?,
+                };
+            }
+            Ok(true)
+        })
+    }
+}
+
+impl fmt::Debug for AymrRedb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrRedb")
+            .field("table", &self.table)
+            .field("durability", &self.durability)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two handles are equal if they refer to the same table of the same open
+/// database.
+impl PartialEq for AymrRedb {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.db, &other.db) && self.table == other.table
+    }
+}
+
+impl Eq for AymrRedb {}
+
+impl<K, V> AymrDatabase<K, V> for AymrRedb
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Snapshot = AymrRedbSnapshot;
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.write(|tables, events| tables.clear(events))
+    }
+
+    fn len(&self) -> usize {
+        self.read()
+            .map(|snapshot| AymrSnapshot::<K>::len(&snapshot))
+            .unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        AymrSnapshot::<K>::is_empty(&self.read()?)
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        AymrSnapshot::<K>::get(&self.read()?, key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+        self.write(|tables, events| tables.put(events, key.as_ref(), value.as_ref(), None))
+    }
+
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.write(|tables, events| tables.delete(events, key.as_ref()))
+    }
+
+    /// The deadline is written to the deadlines table in the same write
+    /// transaction as the key.
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        let deadline = Deadline::after(ttl);
+        self.write(|tables, events| {
+            tables.put(events, key.as_ref(), value.as_ref(), Some(deadline))
+        })
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        let deadline = self.read()?.deadline(key.as_ref())?;
+        Ok(deadline.and_then(|deadline| deadline.left(Deadline::now())))
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        self.write(|tables, events| tables.purge(events))
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.write(|tables, events| {
+            let current = tables.get(key.as_ref())?;
+            if current.as_deref() != old.as_ref().map(AsRef::as_ref) {
+                return Ok(Err(CompareAndSwapError {
+                    current,
+                    proposed: new.as_ref().map(|v| v.as_ref().into()),
+                }));
+            }
+            match &new {
+                Some(value) => tables.put(events, key.as_ref(), value.as_ref(), None)?,
+                None => tables.delete(events, key.as_ref())?,
+            };
+            Ok(Ok(()))
+        })
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.merge_operator.set(merge_operator);
+    }
+
+    /// Reads, merges and writes back inside of a single write transaction.
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        self.write(|tables, events| {
+            let old = tables.get(key.as_ref())?;
+            let new = self.merge_operator.get()?(key.as_ref(), old.as_deref(), operand.as_ref());
+            match new {
+                Some(value) => tables.put(events, key.as_ref(), &value, None)?,
+                None => tables.delete(events, key.as_ref())?,
+            };
+            Ok(old)
+        })
+    }
+
+    /// Applied inside of a single write transaction, so either every
+    /// operation of the batch lands or none does.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        self.write(|tables, events| {
+            for op in batch {
+                match op {
+                    BatchOp::Clear => tables.clear(events)?,
+                    BatchOp::Insert(key, value) => {
+                        tables.put(events, key.as_ref(), value.as_ref(), None)?;
+                    }
+                    BatchOp::Remove(key) => {
+                        tables.delete(events, key.as_ref())?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        AymrSnapshot::<K>::contains_key(&self.read()?, key)
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.read()?.entries((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.read()?.entries(iter::byte_bounds(&range))
+    }
+
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        let end = iter::prefix_upper_bound(prefix.as_ref());
+        self.read()?.entries((
+            Bound::Included(prefix.as_ref()),
+            end.as_ref().map(Vec::as_slice),
+        ))
+    }
+
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        let end = iter::prefix_upper_bound(prefix.as_ref());
+        self.write(|tables, events| {
+            tables.delete_range(
+                events,
+                (
+                    Bound::Included(prefix.as_ref()),
+                    end.as_ref().map(Vec::as_slice),
+                ),
+            )
+        })
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Unbounded, Bound::Excluded(key.as_ref())))?
+            .next_back()
+            .transpose()
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Excluded(key.as_ref()), Bound::Unbounded))?
+            .next()
+            .transpose()
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Unbounded, Bound::Unbounded))?
+            .next()
+            .transpose()
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.read()?
+            .entries((Bound::Unbounded, Bound::Unbounded))?
+            .next_back()
+            .transpose()
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.write(|tables, events| tables.pop(events, End::First))
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.write(|tables, events| tables.pop(events, End::Last))
+    }
+
+    /// Takes a read transaction, which keeps seeing the table as it was
+    /// until it is dropped.
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        self.read()
+    }
+
+    /// Only sees the writes made through Aymr, by any handle to the tree.
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        Ok(self.state.subscribers.subscribe(prefix.as_ref()))
+    }
+}
+
+/// Snapshot of an `AymrRedb`, holding a redb read transaction.
+///
+/// A snapshot keeps seeing its tree even after the tree is dropped. Keys
+/// still expire while it is held.
+#[derive(Clone)]
+pub struct AymrRedbSnapshot {
+    table: Arc<ReadTable>,
+    deadlines: Arc<ReadTable>,
+    deadline_prefix: InlineArray,
+}
+
+impl AymrRedbSnapshot {
+    /// Returns the entries within `bounds` that haven't expired, along with
+    /// the errors of those that can't be read from disk.
+    fn entries(&self, bounds: ByteBounds<'_>) -> Result<Iter<'static>, Error> {
+        if iter::is_empty_range(&bounds) {
+            return Ok(Iter::empty());
+        }
+
+        let entries = self
+            .table
+            .range::<&[u8]>(bounds)?
+            .map(|res| res.map(entry).map_err(Error::from));
+        if self.deadlines()?.next().is_none() {
+            return Ok(Iter::fallible(entries));
+        }
+
+        let snapshot = self.clone();
+        Ok(Iter::fallible(ttl::unexpired(entries, move |key| {
+            snapshot.deadline(key)
+        })))
+    }
+
+    /// Returns the deadline of `key`, if it has one.
+    fn deadline(&self, key: &[u8]) -> Result<Option<Deadline>, Error> {
+        let key = ttl::deadline_key(&self.deadline_prefix, key);
+        self.deadlines
+            .get(key.as_slice())?
+            .map(|deadline| Deadline::from_bytes(deadline.value()))
+            .transpose()
+    }
+
+    /// Returns the deadlines of the tree.
+    fn deadlines(&self) -> Result<redb::Range<'static, Bytes, Bytes>, Error> {
+        let end = iter::prefix_upper_bound(&self.deadline_prefix);
+        Ok(self.deadlines.range::<&[u8]>((
+            Bound::Included(self.deadline_prefix.as_ref()),
+            end.as_ref().map(Vec::as_slice),
+        ))?)
+    }
+
+    /// Counts the keys that haven't expired.
+    fn count(&self) -> Result<usize, Error> {
+        let now = Deadline::now();
+        let mut expired = 0;
+        for deadline in self.deadlines()? {
+            if Deadline::from_bytes(deadline?.1.value())?.has_passed(now) {
+                expired += 1;
+            }
+        }
+
+        Ok(self.table.len()? as usize - expired)
+    }
+}
+
+impl fmt::Debug for AymrRedbSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrRedbSnapshot").finish_non_exhaustive()
+    }
+}
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrRedbSnapshot {
+    fn len(&self) -> usize {
+        self.count().unwrap_or_default()
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        let first = self
+            .entries((Bound::Unbounded, Bound::Unbounded))?
+            .next()
+            .transpose()?;
+        Ok(first.is_none())
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        let Some(value) = self.table.get(key.as_ref())?.map(bytes) else {
+            return Ok(None);
+        };
+        let expired = self
+            .deadline(key.as_ref())?
+            .is_some_and(|deadline| deadline.has_passed(Deadline::now()));
+
+        Ok((!expired).then_some(value))
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(AymrSnapshot::<K>::get(self, key)?.is_some())
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.entries((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.entries(iter::byte_bounds(&range))
+    }
+}
+
+/// Commits inside of a single redb write transaction, which redb runs one at
+/// a time, so the read set is validated against the writes of every handle.
+impl<K, V> AymrTransactional<K, V> for AymrRedb
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |db, pending| db.commit(pending))
+    }
+}
+
+/// Trees are redb tables, so their names have to be UTF-8. Tree handles share
+/// the durability of the database they were opened from. Handles to a dropped
+/// tree keep failing with `Error::CollectionNotFound`, even once a tree of the
+/// same name is opened again.
+impl<K, V> AymrTrees<K, V> for AymrRedb
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Tree = AymrRedb;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        let table = table_name(name.as_ref())?;
+        let mut trees = lock(&self.trees)?;
+        let state = trees.entry(table.clone()).or_default();
+        let tree = AymrRedb {
+            db: Arc::clone(&self.db),
+            deadline_prefix: ttl::deadline_prefix(table.as_bytes()).into(),
+            table,
+            state: Arc::clone(state),
+            trees: Arc::clone(&self.trees),
+            durability: self.durability,
+            merge_operator: MergeOperatorSlot::default(),
+        };
+        tree.write(|_, _| Ok(()))?;
+
+        Ok(tree)
+    }
+
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        let table = table_name(name.as_ref())?;
+        let mut trees = lock(&self.trees)?;
+        let state = trees.remove(&table);
+
+        let dropped = self.transact(|txn| {
+            if let Some(state) = &state {
+                state.alive.store(false, Ordering::Release);
+            }
+            let prefix = ttl::deadline_prefix(table.as_bytes());
+            let end = iter::prefix_upper_bound(&prefix);
+            txn.open_table(definition(DEADLINES_TABLE))?
+                .retain_in::<&[u8], _>(
+                    (
+                        Bound::Included(prefix.as_slice()),
+                        end.as_ref().map(Vec::as_slice),
+                    ),
+                    |_, _| false,
+                )?;
+            Ok(txn.delete_table(definition(&table))?)
+        });
+        if let (Err(_), Some(state)) = (&dropped, state) {
+            state.alive.store(true, Ordering::Release);
+            trees.insert(table, state);
+        }
+
+        dropped
+    }
+
+    /// Returns no names if the database can't be read.
+    fn tree_names(&self) -> Vec<InlineArray> {
+        let Ok(txn) = self.db.0.begin_read() else {
+            return Vec::new();
+        };
+        let Ok(tables) = txn.list_tables() else {
+            return Vec::new();
+        };
+
+        tables
+            .filter_map(|table| {
+                redb::TableHandle::name(&table)
+                    .strip_prefix(TREE_PREFIX)
+                    .map(InlineArray::from)
+            })
+            .collect()
+    }
+}
+
+/// Commits with immediate durability, which also persists every commit made
+/// with buffered durability before it.
+impl AymrFlush for AymrRedb {
+    fn flush(&self) -> Result<(), Error> {
+        persist(&self.db.0)
+    }
+}
+
+impl AymrOpenable for AymrRedb {
+    type Config = RedbConfig;
+
+    fn open(config: RedbConfig) -> Result<Self, Error> {
+        let mut builder = redb::Builder::new();
+        if let Some(bytes) = config.cache_capacity {
+            builder.set_cache_size(usize::try_from(bytes).unwrap_or(usize::MAX));
+        }
+
+        let db = match (&config.path, config.temporary) {
+            (None, true) => builder.create_with_backend(redb::backends::InMemoryBackend::new())?,
+            (Some(path), false) => {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                builder.create(path)?
+            }
+            (Some(_), true) => {
+                return Err(Error::Config(
+                    "the `redb` backend keeps `temporary` databases in memory, so they can't \
+                     have a `path`"
+                        .to_string(),
+                ))
+            }
+            (None, false) => {
+                return Err(Error::Config(
+                    "the `redb` backend needs a `path` unless it is `temporary`".to_string(),
+                ))
+            }
+        };
+
+        AymrRedb::from_db(db, config.durability)
+    }
+
+    fn create_new(config: RedbConfig) -> Result<Self, Error> {
+        if config.path.as_ref().is_some_and(|path| path.exists()) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "there already is a file at the configured `redb` path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    fn open_existing(config: RedbConfig) -> Result<Self, Error> {
+        if !config.path.as_ref().is_some_and(|path| path.is_file()) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "there is no `redb` database at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    /// Opens a database kept in memory.
+    fn temporary() -> Result<Self, Error> {
+        Self::open(RedbConfig {
+            path: None,
+            cache_capacity: None,
+            durability: Durability::default(),
+            temporary: true,
+        })
+    }
+
+    fn close(self) -> Result<(), Error> {
+        AymrFlush::flush(&self)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, Error> {
+    mutex.lock().map_err(|_| {
+        Error::ReportableBug("redb handle lock poisoned by a panicking thread".to_string())
+    })
+}
+
+fn definition(table: &str) -> TableDefinition<'_, Bytes, Bytes> {
+    TableDefinition::new(table)
+}
+
+/// Returns the name of the table holding the tree `name`.
+fn table_name(name: &[u8]) -> Result<String, Error> {
+    let name = std::str::from_utf8(name).map_err(|_| {
+        Error::Unsupported("`redb` can only name trees by UTF-8 strings".to_string())
+    })?;
+
+    Ok(format!("{}{}", TREE_PREFIX, name))
+}
+
+/// Makes every commit so far durable.
+fn persist(db: &redb::Database) -> Result<(), Error> {
+    let mut txn = db.begin_write()?;
+    txn.set_durability(redb::Durability::Immediate)?;
+    txn.commit()?;
+
+    Ok(())
+}
+
+/// Which end of a table to pop from.
+enum End {
+    First,
+    Last,
+}
+
+/// The table of a tree and the deadlines of its keys, inside of a write
+/// transaction.
+struct Tables<'txn, 'a> {
+    data: WriteTable<'txn>,
+    deadlines: WriteTable<'txn>,
+    deadline_prefix: &'a [u8],
+    now: Deadline,
+}
+
+impl Tables<'_, '_> {
+    /// Returns the value of `key`, unless it expired.
+    fn get(&self, key: &[u8]) -> Result<Option<InlineArray>, Error> {
+        let Some(value) = self.data.get(key)?.map(bytes) else {
+            return Ok(None);
+        };
+        let key = ttl::deadline_key(self.deadline_prefix, key);
+        let expired = match self.deadlines.get(key.as_slice())? {
+            Some(deadline) => Deadline::from_bytes(deadline.value())?.has_passed(self.now),
+            None => false,
+        };
+
+        Ok((!expired).then_some(value))
+    }
+
+    /// Sets the deadline of `key`, or removes it if `deadline` is `None`,
+    /// returning true if the old one had passed.
+    fn set_deadline(&mut self, key: &[u8], deadline: Option<Deadline>) -> Result<bool, Error> {
+        let key = ttl::deadline_key(self.deadline_prefix, key);
+        let old = match deadline {
+            Some(deadline) => {
+                self.deadlines
+                    .insert(key.as_slice(), deadline.to_bytes().as_slice())?
+            }
+            None => self.deadlines.remove(key.as_slice())?,
+        };
+        match old {
+            Some(old) => Ok(Deadline::from_bytes(old.value())?.has_passed(self.now)),
+            None => Ok(false),
+        }
+    }
+
+    /// Inserts `key` with the given deadline, recording the event, and
+    /// returns the old value unless it expired.
+    fn put(
+        &mut self,
+        events: &mut Events,
+        key: &[u8],
+        value: &[u8],
+        deadline: Option<Deadline>,
+    ) -> Result<Option<InlineArray>, Error> {
+        let expired = self.set_deadline(key, deadline)?;
+        let old = self.data.insert(key, value)?.map(bytes);
+        events.insert(key, value);
+
+        Ok(old.filter(|_| !expired))
+    }
+
+    /// Removes `key`, recording the event if it was there, and returns the
+    /// old value unless it expired.
+    fn delete(&mut self, events: &mut Events, key: &[u8]) -> Result<Option<InlineArray>, Error> {
+        let expired = self.set_deadline(key, None)?;
+        let old = self.data.remove(key)?.map(bytes);
+        if old.is_some() {
+            events.remove(key);
+        }
+
+        Ok(old.filter(|_| !expired))
+    }
+
+    /// Removes every key within `bounds`, recording their events, and returns
+    /// how many hadn't expired.
+    fn delete_range(
+        &mut self,
+        events: &mut Events,
+        bounds: ByteBounds<'_>,
+    ) -> Result<usize, Error> {
+        let mut keys = Vec::new();
+        self.data.retain_in::<&[u8], _>(bounds, |key, _| {
+            keys.push(InlineArray::from(key));
+            false
+        })?;
+
+        let mut removed = 0;
+        for key in keys {
+            events.remove(&key);
+            if !self.set_deadline(&key, None)? {
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes every key and deadline, recording their events.
+    fn clear(&mut self, events: &mut Events) -> Result<(), Error> {
+        self.data.retain(|key, _| {
+            events.remove(key);
+            false
+        })?;
+        let end = iter::prefix_upper_bound(self.deadline_prefix);
+        self.deadlines.retain_in::<&[u8], _>(
+            (
+                Bound::Included(self.deadline_prefix),
+                end.as_ref().map(Vec::as_slice),
+            ),
+            |_, _| false,
+        )?;
+
+        Ok(())
+    }
+
+    /// Pops entries off `end` until one hasn't expired, recording their
+    /// removal.
+    fn pop(
+        &mut self,
+        events: &mut Events,
+        end: End,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        loop {
+            let popped = match end {
+                End::First => self.data.pop_first()?.map(entry),
+                End::Last => self.data.pop_last()?.map(entry),
+            };
+            let Some((key, value)) = popped else {
+                return Ok(None);
+            };
+            events.remove(&key);
+            if !self.set_deadline(&key, None)? {
+                return Ok(Some((key, value)));
+            }
+        }
+    }
+
+    /// Removes every expired key, recording their events, and returns how
+    /// many there were.
+    fn purge(&mut self, events: &mut Events) -> Result<usize, Error> {
+        let end = iter::prefix_upper_bound(self.deadline_prefix);
+        let mut expired = Vec::new();
+        for deadline in self.deadlines.range::<&[u8]>((
+            Bound::Included(self.deadline_prefix),
+            end.as_ref().map(Vec::as_slice),
+        ))? {
+            let (key, deadline) = deadline?;
+            if Deadline::from_bytes(deadline.value())?.has_passed(self.now) {
+                expired.push(InlineArray::from(key.value()));
+            }
+        }
+
+        for key in &expired {
+            self.deadlines.remove(key.as_ref())?;
+            let key = &key[self.deadline_prefix.len()..];
+            self.data.remove(key)?;
+            events.remove(key);
+        }
+
+        Ok(expired.len())
+    }
+}
+
+fn bytes(value: redb::AccessGuard<'_, Bytes>) -> InlineArray {
+    InlineArray::from(value.value())
+}
+
+fn entry(
+    (key, value): (redb::AccessGuard<'_, Bytes>, redb::AccessGuard<'_, Bytes>),
+) -> (InlineArray, InlineArray) {
+    (bytes(key), bytes(value))
+}
+
+impl From<redb::Error> for Error {
+    fn from(error: redb::Error) -> Self {
+        match error {
+            redb::Error::TableDoesNotExist(name) => Error::CollectionNotFound(name),
+            redb::Error::Io(e) => Error::Io(e),
+            redb::Error::Corrupted(what) => Error::corruption(Some(what)),
+            redb::Error::DatabaseAlreadyOpen => {
+                Error::Io(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "the `redb` database is already open",
+                ))
+            }
+            redb::Error::ValueTooLarge(len) => {
+                Error::Unsupported(format!("`redb` can't store values of {} bytes", len))
+            }
+            redb::Error::UpgradeRequired(version) => {
+                Error::Unsupported(format!(
+                    "the `redb` file format {} has to be upgraded first",
+                    version
+                ))
+            }
+            other => Error::ReportableBug(other.to_string()),
+        }
+    }
+}
+
+/// redb has an error type per operation, all of which go through `redb::Error`.
+macro_rules! from_redb_error {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    redb::Error::from(error).into()
+                }
+            }
+        )*
+    };
+}
+
+from_redb_error!(
+    redb::CommitError,
+    redb::DatabaseError,
+    redb::SetDurabilityError,
+    redb::StorageError,
+    redb::TableError,
+    redb::TransactionError
+);
+
+#[cfg(test)]
+mod tests {
+    use crate::aymr_db::{
+        backend::BackendKind,
+        config::AymrDbBuilder,
+        db::AymrDb,
+        error::Error,
+        traits::{
+            AymrDatabase,
+            AymrTrees,
+        },
+    };
+
+    fn open_temporary() -> AymrDb<Vec<u8>, Vec<u8>> {
+        AymrDbBuilder::new()
+            .backend(BackendKind::Redb)
+            .temporary(true)
+            .cache_capacity(1 << 20)
+            .open()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_tree_names_are_utf8() {
+        let mut db = open_temporary();
+        assert!(matches!(db.open_tree([0xff]), Err(Error::Unsupported(_))));
+        assert!(db.tree_names().is_empty());
+    }
+
+    /// Handles to a dropped tree stay dropped once it is opened again.
+    #[test]
+    fn test_dropped_tree_handles_stay_dropped() {
+        let mut db = open_temporary();
+        let mut tree = db.open_tree(b"tree").unwrap();
+        tree.insert(vec![1], vec![1]).unwrap();
+        assert!(db.drop_tree(b"tree").unwrap());
+
+        let mut reopened = db.open_tree(b"tree").unwrap();
+        reopened.insert(vec![2], vec![2]).unwrap();
+        assert_eq!(
+            tree.get(&[2]),
+            Err(Error::CollectionNotFound("tree".to_string()))
+        );
+        assert_eq!(reopened.get(&[1]).unwrap(), None);
+    }
+}
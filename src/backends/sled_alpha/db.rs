@@ -0,0 +1,1023 @@
+//! `sled_alpha`
+//!
+//! This is an impl of the aymr DB traits for the `1.0-alpha` rewrite of
+//! `sled`. It depends on sled under the name `sled1`, so it can be compiled
+//! alongside `sled_pre` and a single binary can open both formats.
+//!
+//! Using sled 1.0 is optimal when:
+//!
+//! - You want your data on disk, surviving restarts.
+//! - You want ordered keys, ranges and prefix scans.
+//! - You want multiple named trees inside of a single database.
+//! - You are moving off of sled 0.34, which can't read the new format.
+//!
+//! Trees, batches, ranges and flushing map onto sled's own. sled 1.0 has no
+//! transactions, so they commit as a single batch, and writes made through
+//! Aymr take turns so that none comes between. It can't take snapshots. It has no expiry either, so the deadlines of expiring keys live
+//! in a tree of their own, and watching a prefix sees every write made through
+//! Aymr.
+
+use std::{
+    collections::{
+        BTreeMap,
+        HashMap,
+    },
+    fmt,
+    io,
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
+    time::Duration,
+};
+
+use crate::aymr_db::{
+    config::{
+        Durability,
+        SledConfig,
+    },
+    error::{
+        CompareAndSwapError,
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::{
+        self,
+        Entry,
+        Iter,
+    },
+    merge::{
+        MergeOperator,
+        MergeOperatorSlot,
+    },
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrFlush,
+        AymrOpenable,
+        AymrSnapshot,
+        AymrTransactional,
+        AymrTrees,
+        BatchOp,
+        InlineArray,
+    },
+    transaction::{
+        self,
+        ConflictableTransactionResult,
+        PendingCommit,
+        Transaction,
+        TransactionResult,
+    },
+    ttl::{
+        self,
+        Deadline,
+    },
+    watch::{
+        Events,
+        Subscriber,
+        Subscribers,
+    },
+};
+
+/// Tree holding the deadlines of the expiring keys of every tree, which can't
+/// be opened as a tree of its own.
+const DEADLINES_TREE: &[u8] = b"__aymr__deadlines";
+
+/// Struct implementing `AymrDatabase` and `AymrOpenable` over a tree of a
+/// `sled1::Db`.
+///
+/// The database itself opens on its default tree, and `open_tree` hands out
+/// handles to named ones. Handles are cheap to clone, and every clone refers
+/// to the same tree.
+///
+/// sled 1.0 keeps its whole cache inline in both `Db` and `Tree`, so they're
+/// boxed to keep `AymrBackend` small.
+#[derive(Clone)]
+pub struct AymrSledAlpha {
+    db: Box<sled1::Db>,
+    tree: Box<sled1::Tree>,
+    /// Name of the tree, `None` for the default one. sled 1.0 trees don't
+    /// know their own names.
+    name: Option<InlineArray>,
+    deadlines: Box<sled1::Tree>,
+    /// The `ttl::deadline_prefix` of the tree. The default tree goes by the
+    /// name of the deadlines tree, which no other tree can take.
+    deadline_prefix: InlineArray,
+    /// Held while writing, shared by every handle to the database, so a key
+    /// and its deadline are written together, and compare and swap, merges
+    /// and transactions see every other write. Also tells databases apart,
+    /// as `Db` can't be compared.
+    writes: Arc<Mutex<()>>,
+    /// State of the tree of this handle.
+    state: Arc<TreeState>,
+    /// State of the trees opened so far, by name. sled 1.0 keeps handles to
+    /// a dropped tree working, so they have to be told apart here.
+    trees: Arc<Mutex<HashMap<InlineArray, Arc<TreeState>>>>,
+    durability: Durability,
+    merge_operator: MergeOperatorSlot,
+}
+
+/// What every handle to a tree shares.
+struct TreeState {
+    /// Cleared once the tree is dropped.
+    alive: AtomicBool,
+    subscribers: Subscribers,
+}
+
+impl Default for TreeState {
+    fn default() -> Self {
+        TreeState {
+            alive: AtomicBool::new(true),
+            subscribers: Subscribers::default(),
+        }
+    }
+}
+
+impl AymrSledAlpha {
+    fn from_db(db: sled1::Db, durability: Durability) -> Result<Self, Error> {
+        let tree = Box::new(sled1::Tree::clone(&db));
+        let deadlines = Box::new(db.open_tree(DEADLINES_TREE)?);
+        Ok(AymrSledAlpha {
+            db: Box::new(db),
+            tree,
+            name: None,
+            deadlines,
+            deadline_prefix: ttl::deadline_prefix(DEADLINES_TREE).into(),
+            writes: Arc::default(),
+            state: Arc::default(),
+            trees: Arc::default(),
+            durability,
+            merge_operator: MergeOperatorSlot::default(),
+        })
+    }
+
+    /// Returns the name of the tree this handle refers to, which is empty for
+    /// the default tree.
+    pub fn name(&self) -> InlineArray {
+        self.name.clone().unwrap_or_default()
+    }
+
+    /// Returns the tree of this handle, failing if it has been dropped.
+    fn tree(&self) -> Result<&sled1::Tree, Error> {
+        if self.state.alive.load(Ordering::Acquire) {
+            Ok(&self.tree)
+        } else {
+            Err(Error::CollectionNotFound(
+                String::from_utf8_lossy(&self.name()).into_owned(),
+            ))
+        }
+    }
+
+    /// Runs `f` on the tree of this handle holding the write lock, then
+    /// publishes the events it recorded.
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&sled1::Tree, &mut Events) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let _write = lock(&self.writes)?;
+        self.state
+            .subscribers
+            .write(|events| f(self.tree()?, events))
+    }
+
+    /// Flushes the write that was just made if every write has to reach the
+    /// disk before returning.
+    fn written(&self) -> Result<(), Error> {
+        if self.durability == Durability::Immediate {
+            self.tree.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Pops entries with `pop` until one hasn't expired, recording the
+    /// removal of each.
+    fn pop(
+        &self,
+        events: &mut Events,
+        mut pop: impl FnMut() -> io::Result<Option<(sled1::InlineArray, sled1::InlineArray)>>,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        let popped = loop {
+            let Some((key, value)) = pop()?.map(entry) else {
+                break None;
+            };
+            events.remove(&key);
+            if !self.set_deadline(&key, None)? {
+                break Some((key, value));
+            }
+        };
+        self.written()?;
+
+        Ok(popped)
+    }
+
+    /// Returns the deadline of `key`, if it has one.
+    fn deadline(&self, key: &[u8]) -> Result<Option<Deadline>, Error> {
+        self.deadlines
+            .get(ttl::deadline_key(&self.deadline_prefix, key))?
+            .map(|deadline| Deadline::from_bytes(&deadline))
+            .transpose()
+    }
+
+    /// Returns true if `key` has a deadline that has passed.
+    fn is_expired(&self, key: &[u8]) -> Result<bool, Error> {
+        Ok(self
+            .deadline(key)?
+            .is_some_and(|deadline| deadline.has_passed(Deadline::now())))
+    }
+
+    /// Sets or clears the deadline of `key`, returning true if the one it
+    /// replaces had passed.
+    ///
+    /// The deadline and the key are written one after the other, so a crash
+    /// in between can leave a key with the deadline of its old value.
+    fn set_deadline(&self, key: &[u8], deadline: Option<Deadline>) -> Result<bool, Error> {
+        let key = ttl::deadline_key(&self.deadline_prefix, key);
+        let old = match deadline {
+            Some(deadline) => self.deadlines.insert(key, &deadline.to_bytes()[..])?,
+            None => self.deadlines.remove(key)?,
+        };
+
+        match old {
+            Some(old) => Ok(Deadline::from_bytes(&old)?.has_passed(Deadline::now())),
+            None => Ok(false),
+        }
+    }
+
+    /// Removes `key` if it has expired, so that writes comparing against its
+    /// value find it missing.
+    fn expire(&self, tree: &sled1::Tree, events: &mut Events, key: &[u8]) -> Result<(), Error> {
+        if self.is_expired(key)? {
+            if tree.remove(key)?.is_some() {
+                events.remove(key);
+            }
+            self.set_deadline(key, None)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the deadlines of the keys of the tree, keyed by their
+    /// `ttl::deadline_key`.
+    fn deadlines(&self) -> sled1::Iter<1024> {
+        self.deadlines.scan_prefix(&*self.deadline_prefix)
+    }
+
+    /// Returns the number of keys of the tree whose deadline has passed.
+    fn expired(&self) -> Result<usize, Error> {
+        let now = Deadline::now();
+        let mut expired = 0;
+        for deadline in self.deadlines() {
+            if Deadline::from_bytes(&deadline?.1)?.has_passed(now) {
+                expired += 1;
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Wraps an iterator over the tree, skipping expired entries if any key
+    /// of the tree has a deadline.
+    fn entries(&self, iter: sled1::Iter<1024>) -> Result<Iter<'static>, Error> {
+        let entries = entries(iter);
+        if self.deadlines().next().transpose()?.is_none() {
+            return Ok(entries);
+        }
+
+        let db = self.clone();
+        Ok(Iter::fallible(ttl::unexpired(entries, move |key| {
+            db.deadline(key)
+        })))
+    }
+
+    /// Returns the first entry of `iter` that hasn't expired.
+    fn first_of(
+        &self,
+        iter: sled1::Iter<1024>,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.entries(iter)?.next().transpose()
+    }
+
+    /// Returns the last entry of `iter` that hasn't expired.
+    fn last_of(
+        &self,
+        iter: sled1::Iter<1024>,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.entries(iter)?.next_back().transpose()
+    }
+
+    /// Applies `ops` to the tree as a single `sled1::Batch`, followed by
+    /// another one clearing the deadlines of the keys it writes.
+    fn batch<K, V>(
+        &self,
+        tree: &sled1::Tree,
+        events: &mut Events,
+        ops: impl IntoIterator<Item = BatchOp<K, V>>,
+    ) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        // Only the last write to each key matters, which also tells which
+        // removals remove a key that is there.
+        let mut writes = BTreeMap::new();
+        let mut cleared = false;
+        for op in ops {
+            match op {
+                BatchOp::Clear => {
+                    writes.clear();
+                    cleared = true;
+                    for key in tree.iter().keys() {
+                        writes.insert(key?.to_vec(), None);
+                    }
+                }
+                BatchOp::Insert(key, value) => {
+                    writes.insert(key.as_ref().to_vec(), Some(value));
+                }
+                BatchOp::Remove(key) => {
+                    writes.insert(key.as_ref().to_vec(), None);
+                }
+            }
+        }
+
+        let mut native = sled1::Batch::default();
+        let mut deadlines = sled1::Batch::default();
+        if cleared {
+            for deadline in self.deadlines() {
+                deadlines.remove(deadline?.0);
+            }
+        }
+        for (key, value) in writes {
+            deadlines.remove(ttl::deadline_key(&self.deadline_prefix, &key));
+            match value {
+                Some(value) => {
+                    events.insert(&key, value.as_ref());
+                    native.insert(key, value.as_ref());
+                }
+                None => {
+                    if events.watched() && tree.contains_key(&key)? {
+                        events.remove(&key);
+                    }
+                    native.remove(key);
+                }
+            }
+        }
+        tree.apply_batch(native)?;
+        self.deadlines.apply_batch(deadlines)?;
+        self.written()
+    }
+
+    /// Validates the read set and applies the writes of a transaction as a
+    /// single batch, returning false if it conflicted.
+    ///
+    /// Every write made through Aymr takes the same lock, so none can come
+    /// between the two.
+    pub(crate) fn commit<K, V>(&self, pending: PendingCommit<K, V>) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        self.write(|tree, events| {
+            for (key, seen) in pending.reads() {
+                if AymrDatabase::<K, V>::get(self, key.as_ref())? != *seen {
+                    return Ok(false);
+                }
+            }
+            let writes = pending.writes().iter().map(|(key, value)| {
+                match value {
+                    Some(value) => BatchOp::Insert(key, value),
+                    None => BatchOp::Remove(key),
+                }
+            });
+            self.batch(tree, events, writes)?;
+
+            Ok(true)
+        })
+    }
+}
+
+impl fmt::Debug for AymrSledAlpha {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrSledAlpha")
+            .field("tree", &String::from_utf8_lossy(&self.name()))
+            .field("durability", &self.durability)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two handles are equal if they refer to the same tree of the same open
+/// database.
+impl PartialEq for AymrSledAlpha {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.writes, &other.writes) && self.name == other.name
+    }
+}
+
+impl Eq for AymrSledAlpha {}
+
+impl<K, V> AymrDatabase<K, V> for AymrSledAlpha
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Snapshot = AymrSledAlphaSnapshot;
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.write(|tree, events| {
+            if events.watched() {
+                for key in tree.iter().keys() {
+                    events.remove(&key?);
+                }
+            }
+            tree.clear()?;
+            clear_deadlines(&self.deadlines, &self.deadline_prefix)?;
+            self.written()
+        })
+    }
+
+    /// Walks the whole tree, as sled does not keep count.
+    fn len(&self) -> usize {
+        let Ok(tree) = self.tree() else {
+            return 0;
+        };
+        tree.len().saturating_sub(self.expired().unwrap_or(0))
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.first_of(self.tree()?.iter())?.is_none())
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        let Some(value) = self.tree()?.get(key.as_ref())? else {
+            return Ok(None);
+        };
+        if self.is_expired(key.as_ref())? {
+            return Ok(None);
+        }
+
+        Ok(Some(bytes(value)))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+        self.write(|tree, events| {
+            let old = tree.insert(key.as_ref(), value.as_ref())?;
+            let expired = self.set_deadline(key.as_ref(), None)?;
+            events.insert(key.as_ref(), value.as_ref());
+            self.written()?;
+            Ok(old.filter(|_| !expired).map(bytes))
+        })
+    }
+
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        self.write(|tree, events| {
+            let old = tree.remove(key.as_ref())?;
+            let expired = self.set_deadline(key.as_ref(), None)?;
+            if old.is_some() {
+                events.remove(key.as_ref());
+            }
+            self.written()?;
+            Ok(old.filter(|_| !expired).map(bytes))
+        })
+    }
+
+    /// The deadline is written before the key, so a crash in between can
+    /// leave the old value of the key with the new deadline.
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        self.write(|tree, events| {
+            let expired = self.set_deadline(key.as_ref(), Some(Deadline::after(ttl)))?;
+            let old = tree.insert(key.as_ref(), value.as_ref())?;
+            events.insert(key.as_ref(), value.as_ref());
+            self.written()?;
+            Ok(old.filter(|_| !expired).map(bytes))
+        })
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        self.tree()?;
+        let deadline = self.deadline(key.as_ref())?;
+        Ok(deadline.and_then(|deadline| deadline.left(Deadline::now())))
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        self.write(|tree, events| {
+            let now = Deadline::now();
+            let mut expired = Vec::new();
+            for deadline in self.deadlines() {
+                let (key, deadline) = deadline?;
+                if Deadline::from_bytes(&deadline)?.has_passed(now) {
+                    expired.push(key);
+                }
+            }
+
+            let mut purged = 0;
+            for deadline_key in expired {
+                let key = &deadline_key[self.deadline_prefix.len()..];
+                if tree.remove(key)?.is_some() {
+                    events.remove(key);
+                    purged += 1;
+                }
+                self.deadlines.remove(&deadline_key)?;
+            }
+            if purged > 0 {
+                self.written()?;
+            }
+
+            Ok(purged)
+        })
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        self.write(|tree, events| {
+            self.expire(tree, events, key.as_ref())?;
+            let existed = old.is_some();
+            let swapped =
+                tree.compare_and_swap(key.as_ref(), old, new.as_ref().map(|v| v.as_ref()))?;
+            if swapped.is_ok() {
+                self.set_deadline(key.as_ref(), None)?;
+                match &new {
+                    Some(value) => events.insert(key.as_ref(), value.as_ref()),
+                    None if existed => events.remove(key.as_ref()),
+                    None => {}
+                }
+                self.written()?;
+            }
+
+            Ok(swapped.map(|_| ()).map_err(|e| {
+                CompareAndSwapError {
+                    current: e.current.map(bytes),
+                    proposed: e.proposed.map(bytes),
+                }
+            }))
+        })
+    }
+
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        self.merge_operator.set(merge_operator);
+    }
+
+    /// sled 1.0 has no merge operators, so merges run through its
+    /// `fetch_and_update`.
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        let merge_operator = self.merge_operator.get()?;
+        self.write(|tree, events| {
+            self.expire(tree, events, key.as_ref())?;
+            let mut new = None;
+            let old = tree.fetch_and_update(key.as_ref(), |old| {
+                new = merge_operator(key.as_ref(), old, operand.as_ref());
+                new.clone()
+            })?;
+            self.set_deadline(key.as_ref(), None)?;
+            match &new {
+                Some(value) => events.insert(key.as_ref(), value),
+                None if old.is_some() => events.remove(key.as_ref()),
+                None => {}
+            }
+            self.written()?;
+
+            Ok(old.map(bytes))
+        })
+    }
+
+    /// Applied as a single `sled1::Batch`, followed by another one clearing
+    /// the deadlines of the keys it writes. A clear removes the keys the tree
+    /// holds when the batch is built.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        self.write(|tree, events| self.batch(tree, events, batch))
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(AymrDatabase::<K, V>::get(self, key)?.is_some())
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.entries(self.tree()?.iter())
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let bounds = iter::byte_bounds(&range);
+        if iter::is_empty_range(&bounds) {
+            return Ok(Iter::empty());
+        }
+
+        self.entries(self.tree()?.range::<&[u8], _>(bounds))
+    }
+
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        self.entries(self.tree()?.scan_prefix(prefix))
+    }
+
+    /// Expired keys are removed along with the others, but not counted.
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        self.write(|tree, events| {
+            let expiring = self.deadlines().next().is_some();
+            let mut batch = sled1::Batch::default();
+            let mut deadlines = sled1::Batch::default();
+            let mut removed = 0;
+            for key in tree.scan_prefix(prefix).keys() {
+                let key = key?;
+                if !expiring || !self.is_expired(&key)? {
+                    removed += 1;
+                }
+                if expiring {
+                    deadlines.remove(ttl::deadline_key(&self.deadline_prefix, &key));
+                }
+                events.remove(&key);
+                batch.remove(key);
+            }
+            tree.apply_batch(batch)?;
+            self.deadlines.apply_batch(deadlines)?;
+            self.written()?;
+
+            Ok(removed)
+        })
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.last_of(self.tree()?.range::<&[u8], _>(..key.as_ref()))
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        let bounds = (Bound::Excluded(key.as_ref()), Bound::Unbounded);
+        self.first_of(self.tree()?.range::<&[u8], _>(bounds))
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.first_of(self.tree()?.iter())
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.last_of(self.tree()?.iter())
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.write(|tree, events| self.pop(events, || tree.pop_first()))
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.write(|tree, events| self.pop(events, || tree.pop_last()))
+    }
+
+    /// sled 1.0 can't take snapshots, so this returns `Error::Unsupported`.
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        Err(Error::Unsupported(
+            "`sled` 1.0 can't take snapshots".to_string(),
+        ))
+    }
+
+    /// sled 1.0 has no subscriptions of its own, so this only sees the
+    /// writes made through Aymr, by any handle to the tree.
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        Ok(self.state.subscribers.subscribe(prefix.as_ref()))
+    }
+}
+
+/// Snapshot of an `AymrSledAlpha`, which can't be taken.
+///
+/// This type has no values, it only exists to fill in
+/// `AymrDatabase::Snapshot`.
+#[derive(Debug, Clone)]
+pub enum AymrSledAlphaSnapshot {}
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrSledAlphaSnapshot {
+    fn len(&self) -> usize {
+        match *self {}
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        match *self {}
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, _key: &Q) -> Result<Option<InlineArray>, Error> {
+        match *self {}
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, _key: &Q) -> Result<bool, Error> {
+        match *self {}
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        match *self {}
+    }
+
+    fn range<Q, R>(&self, _range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        match *self {}
+    }
+}
+
+/// Commits as a single batch while no other write runs, see `commit`.
+impl<K, V> AymrTransactional<K, V> for AymrSledAlpha
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |db, pending| db.commit(pending))
+    }
+}
+
+/// Trees are sled's own, and tree handles share the durability of the
+/// database they were opened from. sled's default tree is not listed.
+impl<K, V> AymrTrees<K, V> for AymrSledAlpha
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Tree = AymrSledAlpha;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        let name = tree_name(name.as_ref())?;
+        let mut trees = lock(&self.trees)?;
+        let tree = self.db.open_tree(&name)?;
+        let state = trees.entry(name.clone()).or_default();
+
+        Ok(AymrSledAlpha {
+            db: self.db.clone(),
+            tree: Box::new(tree),
+            deadlines: self.deadlines.clone(),
+            deadline_prefix: ttl::deadline_prefix(&name).into(),
+            name: Some(name),
+            writes: Arc::clone(&self.writes),
+            state: Arc::clone(state),
+            trees: Arc::clone(&self.trees),
+            durability: self.durability,
+            merge_operator: MergeOperatorSlot::default(),
+        })
+    }
+
+    /// Writes made through a handle while its tree is being dropped can be
+    /// lost.
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        let name = tree_name(name.as_ref())?;
+        let mut trees = lock(&self.trees)?;
+        if let Some(state) = trees.remove(&name) {
+            state.alive.store(false, Ordering::Release);
+        }
+
+        clear_deadlines(&self.deadlines, &ttl::deadline_prefix(&name))?;
+        Ok(self.db.drop_tree(name)?)
+    }
+
+    /// sled 1.0 only lists its trees as part of an export, which reads
+    /// nothing until its iterators are.
+    fn tree_names(&self) -> Vec<InlineArray> {
+        self.db
+            .export()
+            .into_iter()
+            .map(|(_, name, _)| InlineArray::from(name))
+            .filter(|name| **name != *DEADLINES_TREE)
+            .collect()
+    }
+}
+
+impl AymrFlush for AymrSledAlpha {
+    fn flush(&self) -> Result<(), Error> {
+        self.tree()?.flush()?;
+        Ok(())
+    }
+}
+
+impl AymrOpenable for AymrSledAlpha {
+    type Config = SledConfig;
+
+    fn open(config: SledConfig) -> Result<Self, Error> {
+        let db = native_config(&config)?.open()?;
+        AymrSledAlpha::from_db(db, config.durability)
+    }
+
+    fn create_new(config: SledConfig) -> Result<Self, Error> {
+        if config.path.as_ref().is_some_and(|path| exists(path)) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "there already is a `sled` 1.0 database at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    fn open_existing(config: SledConfig) -> Result<Self, Error> {
+        if !config.path.as_ref().is_some_and(|path| exists(path)) {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "there is no `sled` 1.0 database at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    /// Opens a database in a fresh temporary directory.
+    fn temporary() -> Result<Self, Error> {
+        Self::open(SledConfig {
+            path: None,
+            cache_capacity: None,
+            durability: Durability::default(),
+            temporary: true,
+        })
+    }
+
+    fn close(self) -> Result<(), Error> {
+        AymrFlush::flush(&self)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, Error> {
+    mutex.lock().map_err(|_| {
+        Error::ReportableBug("sled handle lock poisoned by a panicking thread".to_string())
+    })
+}
+
+/// Checks that `name` isn't the name of the deadlines tree.
+fn tree_name(name: &[u8]) -> Result<InlineArray, Error> {
+    if name == DEADLINES_TREE {
+        return Err(Error::Unsupported(format!(
+            "`{}` holds the deadlines of expiring keys and can't be opened as a tree",
+            String::from_utf8_lossy(DEADLINES_TREE)
+        )));
+    }
+
+    Ok(InlineArray::from(name))
+}
+
+/// Removes the deadlines under `prefix` as a single batch.
+fn clear_deadlines(deadlines: &sled1::Tree, prefix: &[u8]) -> Result<(), Error> {
+    let mut batch = sled1::Batch::default();
+    for deadline in deadlines.scan_prefix(prefix) {
+        batch.remove(deadline?.0);
+    }
+    Ok(deadlines.apply_batch(batch)?)
+}
+
+/// Returns true if a sled 1.0 database has been created at `path`.
+fn exists(path: &std::path::Path) -> bool {
+    path.join("metadata").is_dir()
+}
+
+/// Turns a `SledConfig` into sled's own config.
+///
+/// sled 1.0 picks the directory of temporary databases itself, so those
+/// can't be given a path. Immediate durability turns off the background
+/// flusher, as every write flushes anyway.
+fn native_config(config: &SledConfig) -> Result<sled1::Config, Error> {
+    let mut native = match (&config.path, config.temporary) {
+        (None, true) => sled1::Config::tmp()?,
+        (Some(path), false) => sled1::Config::new().path(path),
+        (Some(_), true) => {
+            return Err(Error::Config(
+                "the `sled_alpha` backend can't keep a `temporary` database at a `path`"
+                    .to_string(),
+            ))
+        }
+        (None, false) => {
+            return Err(Error::Config(
+                "the `sled_alpha` backend needs a `path` unless it is `temporary`".to_string(),
+            ))
+        }
+    };
+    if let Some(bytes) = config.cache_capacity {
+        native = native.cache_capacity_bytes(usize::try_from(bytes).unwrap_or(usize::MAX));
+    }
+    if config.durability == Durability::Immediate {
+        native = native.flush_every_ms(None);
+    }
+
+    Ok(native)
+}
+
+fn bytes(value: sled1::InlineArray) -> InlineArray {
+    InlineArray::from(&*value)
+}
+
+fn entry((key, value): (sled1::InlineArray, sled1::InlineArray)) -> (InlineArray, InlineArray) {
+    (bytes(key), bytes(value))
+}
+
+/// Wraps a sled iterator, passing on the errors of entries that can't be
+/// read from disk.
+fn entries(iter: sled1::Iter<1024>) -> Iter<'static> {
+    Iter::fallible(Entries {
+        iter,
+        front: None,
+        back: None,
+    })
+}
+
+/// sled 1.0 walks a range from either end on its own, so an iterator read
+/// from both ends would yield every entry twice. This one stops where the
+/// two ends meet.
+struct Entries {
+    iter: sled1::Iter<1024>,
+    /// The last key yielded from the front.
+    front: Option<InlineArray>,
+    /// The last key yielded from the back.
+    back: Option<InlineArray>,
+}
+
+impl Iterator for Entries {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = match self.iter.next()? {
+            Ok(res) => entry(res),
+            Err(e) => return Some(Err(e.into())),
+        };
+        if self.back.as_ref().is_some_and(|back| key >= *back) {
+            return None;
+        }
+        self.front = Some(key.clone());
+
+        Some(Ok((key, value)))
+    }
+}
+
+impl DoubleEndedIterator for Entries {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (key, value) = match self.iter.next_back()? {
+            Ok(res) => entry(res),
+            Err(e) => return Some(Err(e.into())),
+        };
+        if self.front.as_ref().is_some_and(|front| key <= *front) {
+            return None;
+        }
+        self.back = Some(key.clone());
+
+        Some(Ok((key, value)))
+    }
+}
+
+#[cfg(all(test, feature = "sled_pre"))]
+mod tests {
+    use crate::aymr_db::{
+        backend::BackendKind,
+        config::AymrDbBuilder,
+        traits::{
+            AymrBatch,
+            AymrDatabase,
+            AymrFlush,
+            Batch,
+        },
+    };
+
+    /// One binary can open both sled formats and copy data across.
+    #[test]
+    fn test_sled_migration() {
+        let mut old = AymrDbBuilder::new()
+            .backend(BackendKind::Sled)
+            .temporary(true)
+            .open::<Vec<u8>, Vec<u8>>()
+            .unwrap();
+        let mut new = AymrDbBuilder::new()
+            .backend(BackendKind::SledAlpha)
+            .temporary(true)
+            .open::<Vec<u8>, Vec<u8>>()
+            .unwrap();
+        for i in 0..100u8 {
+            old.insert(vec![i], vec![i, i]).unwrap();
+        }
+
+        let mut batch = AymrBatch::new();
+        for (key, value) in old.iter().unwrap().map(Result::unwrap) {
+            batch.insert(key.into(), value.into());
+        }
+        new.apply_batch(batch).unwrap();
+        new.flush().unwrap();
+
+        assert_eq!(
+            new.iter().unwrap().collect::<Vec<_>>(),
+            old.iter().unwrap().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,1015 @@
+//! `sled_pre`
+//!
+//! This is an impl of the aymr DB traits for `sled` 0.34, the last stable
+//! release before the `1.0-alpha` rewrite.
+//!
+//! Using sled is optimal when:
+//!
+//! - You want your data on disk, surviving restarts.
+//! - You want ordered keys, ranges and prefix scans.
+//! - You want multiple named trees inside of a single database.
+//! - Several threads write at once, as sled is lock-free.
+//!
+//! Trees, batches, transactions, merge operators and watching prefixes all map
+//! onto sled's own. sled 0.34 can't take snapshots. It has no expiry either,
+//! so the deadlines of expiring keys live in a tree of their own, written in
+//! the same sled transaction as the keys.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    io,
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    pin::Pin,
+    sync::{
+        mpsc::RecvTimeoutError,
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+    },
+    task::{
+        Context,
+        Poll,
+        Wake,
+        Waker,
+    },
+    thread::{
+        self,
+        Thread,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use sled::{
+    transaction::{
+        ConflictableTransactionError,
+        TransactionError,
+        TransactionalTree,
+    },
+    Transactional,
+};
+
+use crate::aymr_db::{
+    config::{
+        Durability,
+        SledConfig,
+    },
+    error::{
+        CompareAndSwapError,
+        CompareAndSwapResult,
+        Error,
+    },
+    iter::{
+        self,
+        Iter,
+    },
+    merge::{
+        MergeOperator,
+        MergeOperatorSlot,
+    },
+    traits::{
+        AymrBatch,
+        AymrDatabase,
+        AymrFlush,
+        AymrOpenable,
+        AymrSnapshot,
+        AymrTransactional,
+        AymrTrees,
+        Batch,
+        BatchOp,
+        InlineArray,
+    },
+    transaction::{
+        self,
+        ConflictableTransactionResult,
+        PendingCommit,
+        Transaction,
+        TransactionResult,
+    },
+    ttl::{
+        self,
+        Deadline,
+    },
+    watch::{
+        Event,
+        EventSource,
+        Subscriber,
+    },
+};
+
+/// Tree holding the deadlines of the expiring keys of every tree, which can't
+/// be opened as a tree of its own.
+const DEADLINES_TREE: &[u8] = b"__aymr__deadlines";
+
+/// Struct implementing `AymrDatabase` and `AymrOpenable` over a tree of a
+/// `sled::Db`.
+///
+/// The database itself opens on its default tree, and `open_tree` hands out
+/// handles to named ones. Handles are cheap to clone, and every clone refers
+/// to the same tree.
+#[derive(Clone)]
+pub struct AymrSled {
+    db: Arc<sled::Db>,
+    tree: sled::Tree,
+    deadlines: sled::Tree,
+    /// The `ttl::deadline_prefix` of the tree.
+    deadline_prefix: InlineArray,
+    durability: Durability,
+    /// Merge operators of the trees of the database, by name, shared by
+    /// every handle to it.
+    merge_operators: Arc<Mutex<HashMap<InlineArray, MergeOperatorSlot>>>,
+}
+
+impl AymrSled {
+    fn from_db(db: sled::Db, durability: Durability) -> Result<Self, Error> {
+        let tree = sled::Tree::clone(&db);
+        let deadlines = db.open_tree(DEADLINES_TREE)?;
+        Ok(AymrSled {
+            db: Arc::new(db),
+            deadline_prefix: ttl::deadline_prefix(&tree.name()).into(),
+            tree,
+            deadlines,
+            durability,
+            merge_operators: Arc::default(),
+        })
+    }
+
+    /// Returns the name of the tree this handle refers to.
+    pub fn name(&self) -> InlineArray {
+        bytes(self.tree.name())
+    }
+
+    /// Flushes the write that was just made if every write has to reach the
+    /// disk before returning.
+    fn written(&self) -> Result<(), Error> {
+        if self.durability == Durability::Immediate {
+            self.tree.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` on the tree and the deadlines of its keys inside of a sled
+    /// transaction, which sled retries on conflicts.
+    fn atomically<T>(
+        &self,
+        f: impl Fn(&TreeTxn<'_>) -> Result<T, ConflictableTransactionError<Error>>,
+    ) -> Result<T, Error> {
+        let now = Deadline::now();
+        (&self.tree, &self.deadlines)
+            .transaction(|(tree, deadlines)| {
+                f(&TreeTxn {
+                    tree,
+                    deadlines,
+                    deadline_prefix: &self.deadline_prefix,
+                    now,
+                })
+            })
+            .map_err(|e| {
+                match e {
+                    TransactionError::Abort(e) => e,
+                    TransactionError::Storage(e) => Error::from(e),
+                }
+            })
+    }
+
+    /// Returns the deadline of `key`, if it has one.
+    fn deadline(&self, key: &[u8]) -> Result<Option<Deadline>, Error> {
+        self.deadlines
+            .get(ttl::deadline_key(&self.deadline_prefix, key))?
+            .map(|deadline| Deadline::from_bytes(&deadline))
+            .transpose()
+    }
+
+    /// Returns the deadlines of the keys of the tree, keyed by their
+    /// `ttl::deadline_key`.
+    fn deadlines(&self) -> sled::Iter {
+        self.deadlines.scan_prefix(&*self.deadline_prefix)
+    }
+
+    /// Returns the number of keys of the tree whose deadline has passed.
+    fn expired(&self) -> Result<usize, Error> {
+        let now = Deadline::now();
+        let mut expired = 0;
+        for deadline in self.deadlines() {
+            if Deadline::from_bytes(&deadline?.1)?.has_passed(now) {
+                expired += 1;
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Wraps an iterator over the tree, skipping expired entries if any key
+    /// of the tree has a deadline.
+    fn entries(&self, iter: sled::Iter) -> Result<Iter<'static>, Error> {
+        let entries = entries(iter);
+        if self.deadlines().next().transpose()?.is_none() {
+            return Ok(entries);
+        }
+
+        let db = self.clone();
+        Ok(Iter::fallible(ttl::unexpired(entries, move |key| {
+            db.deadline(key)
+        })))
+    }
+
+    /// Returns the first entry of `iter` that hasn't expired.
+    fn first_of(&self, iter: sled::Iter) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.entries(iter)?.next().transpose()
+    }
+
+    /// Returns the last entry of `iter` that hasn't expired.
+    fn last_of(&self, iter: sled::Iter) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.entries(iter)?.next_back().transpose()
+    }
+
+    /// Pops the entry `end` returns until one hasn't expired.
+    fn pop(
+        &self,
+        end: impl Fn() -> sled::Result<Option<(sled::IVec, sled::IVec)>>,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        let mut popped = None;
+        while let Some((key, _)) = end()? {
+            // Nothing is popped if the key has expired, or if another write
+            // removed it first. Either way, the next one is up.
+            if let Some(value) = self.atomically(|txn| txn.put(&key, None, None))? {
+                popped = Some((bytes(key), bytes(value)));
+                break;
+            }
+        }
+        self.written()?;
+
+        Ok(popped)
+    }
+
+    /// Validates and applies the writes of a transaction inside of a sled
+    /// transaction, returning false if it conflicted.
+    pub(crate) fn commit<K, V>(&self, pending: PendingCommit<K, V>) -> Result<bool, Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let committed = self.atomically(|txn| {
+            for (key, seen) in pending.reads() {
+                if txn.get(key.as_ref())?.as_deref() != seen.as_deref() {
+                    return Ok(false);
+                }
+            }
+            for (key, value) in pending.writes() {
+                txn.put(key.as_ref(), value.as_ref().map(AsRef::as_ref), None)?;
+            }
+            Ok(true)
+        })?;
+        if committed {
+            self.written()?;
+        }
+
+        Ok(committed)
+    }
+}
+
+/// The tree of a handle and the deadlines of its keys, as seen by a sled
+/// transaction.
+struct TreeTxn<'a> {
+    tree: &'a TransactionalTree,
+    deadlines: &'a TransactionalTree,
+    deadline_prefix: &'a [u8],
+    /// When the transaction started, which decides what has expired.
+    now: Deadline,
+}
+
+impl TreeTxn<'_> {
+    /// Returns the value of `key` unless it has expired.
+    fn get(&self, key: &[u8]) -> Result<Option<sled::IVec>, ConflictableTransactionError<Error>> {
+        let value = self.tree.get(key)?;
+        if value.is_some() && self.is_expired(key)? {
+            return Ok(None);
+        }
+        Ok(value)
+    }
+
+    /// Returns true if `key` has a deadline that has passed.
+    fn is_expired(&self, key: &[u8]) -> Result<bool, ConflictableTransactionError<Error>> {
+        match self.deadlines.get(self.deadline_key(key))? {
+            Some(deadline) => {
+                Ok(Deadline::from_bytes(&deadline)
+                    .map_err(ConflictableTransactionError::Abort)?
+                    .has_passed(self.now))
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn deadline_key(&self, key: &[u8]) -> Vec<u8> {
+        ttl::deadline_key(self.deadline_prefix, key)
+    }
+
+    /// Writes `value` under `key` with the given deadline, or removes it
+    /// along with its deadline if `value` is `None`. Returns the old value
+    /// unless it had expired.
+    fn put(
+        &self,
+        key: &[u8],
+        value: Option<&[u8]>,
+        deadline: Option<Deadline>,
+    ) -> Result<Option<sled::IVec>, ConflictableTransactionError<Error>> {
+        let expired = self.is_expired(key)?;
+        let old = match value {
+            Some(value) => self.tree.insert(key, value)?,
+            None => self.tree.remove(key)?,
+        };
+        match deadline {
+            Some(deadline) => {
+                self.deadlines
+                    .insert(self.deadline_key(key), &deadline.to_bytes()[..])?
+            }
+            None => self.deadlines.remove(self.deadline_key(key))?,
+        };
+
+        Ok(old.filter(|_| !expired))
+    }
+}
+
+impl fmt::Debug for AymrSled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AymrSled")
+            .field("tree", &String::from_utf8_lossy(&self.tree.name()))
+            .field("durability", &self.durability)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two handles are equal if they refer to the same tree of the same open
+/// database.
+impl PartialEq for AymrSled {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.db, &other.db) && self.tree.name() == other.tree.name()
+    }
+}
+
+impl Eq for AymrSled {}
+
+impl<K, V> AymrDatabase<K, V> for AymrSled
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Snapshot = AymrSledSnapshot;
+
+    /// Removes the keys the tree holds when it starts, in a single sled
+    /// transaction along with their deadlines.
+    fn clear(&mut self) -> Result<(), Error> {
+        let mut batch = AymrBatch::<K, V>::new();
+        batch.clear();
+        self.apply_batch(batch)
+    }
+
+    /// Walks the whole tree, as sled does not keep count.
+    fn len(&self) -> usize {
+        self.tree.len().saturating_sub(self.expired().unwrap_or(0))
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.first_of(self.tree.iter())?.is_none())
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        let Some(value) = self.tree.get(key.as_ref())? else {
+            return Ok(None);
+        };
+        let expired = self
+            .deadline(key.as_ref())?
+            .is_some_and(|deadline| deadline.has_passed(Deadline::now()));
+        if expired {
+            return Ok(None);
+        }
+
+        Ok(Some(bytes(value)))
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Result<Option<InlineArray>, Error> {
+        let old = self.atomically(|txn| txn.put(key.as_ref(), Some(value.as_ref()), None))?;
+        self.written()?;
+        Ok(old.map(bytes))
+    }
+
+    fn remove<Q: AsRef<[u8]> + ?Sized>(&mut self, key: &Q) -> Result<Option<InlineArray>, Error> {
+        let old = self.atomically(|txn| txn.put(key.as_ref(), None, None))?;
+        self.written()?;
+        Ok(old.map(bytes))
+    }
+
+    fn insert_with_ttl(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Option<InlineArray>, Error> {
+        let deadline = Deadline::after(ttl);
+        let old =
+            self.atomically(|txn| txn.put(key.as_ref(), Some(value.as_ref()), Some(deadline)))?;
+        self.written()?;
+        Ok(old.map(bytes))
+    }
+
+    fn ttl<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<Option<Duration>, Error> {
+        let deadline = self.deadline(key.as_ref())?;
+        Ok(deadline.and_then(|deadline| deadline.left(Deadline::now())))
+    }
+
+    /// Each expired key is removed in a sled transaction of its own.
+    fn purge_expired(&mut self) -> Result<usize, Error> {
+        let now = Deadline::now();
+        let mut expired = Vec::new();
+        for deadline in self.deadlines() {
+            let (key, deadline) = deadline?;
+            if Deadline::from_bytes(&deadline)?.has_passed(now) {
+                expired.push(key);
+            }
+        }
+
+        let mut purged = 0;
+        for deadline_key in expired {
+            let key = &deadline_key[self.deadline_prefix.len()..];
+            // The key may have been written again since.
+            let removed = self.atomically(|txn| {
+                if !txn.is_expired(key)? {
+                    return Ok(false);
+                }
+                let existed = txn.tree.get(key)?.is_some();
+                txn.put(key, None, None)?;
+                Ok(existed)
+            })?;
+            if removed {
+                purged += 1;
+            }
+        }
+        if purged > 0 {
+            self.written()?;
+        }
+
+        Ok(purged)
+    }
+
+    fn compare_and_swap<OV: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        old: Option<OV>,
+        new: Option<V>,
+    ) -> CompareAndSwapResult {
+        let old = old.as_ref().map(AsRef::as_ref);
+        let new = new.as_ref().map(AsRef::as_ref);
+        let swapped = self.atomically(|txn| {
+            let current = txn.get(key.as_ref())?;
+            if current.as_deref() != old {
+                return Ok(Err(CompareAndSwapError {
+                    current: current.map(bytes),
+                    proposed: new.map(InlineArray::from),
+                }));
+            }
+            txn.put(key.as_ref(), new, None)?;
+            Ok(Ok(()))
+        })?;
+        if swapped.is_ok() {
+            self.written()?;
+        }
+
+        Ok(swapped)
+    }
+
+    /// The operator is shared by every handle to the tree, and also handed
+    /// to sled, so its own merges run it too.
+    fn set_merge_operator(&mut self, merge_operator: impl MergeOperator + 'static) {
+        let merge_operator = Arc::new(merge_operator);
+        let native = Arc::clone(&merge_operator);
+        self.tree
+            .set_merge_operator(move |key: &[u8], old: Option<&[u8]>, operand: &[u8]| {
+                native(key, old, operand)
+            });
+        lock(&self.merge_operators)
+            .entry(self.name())
+            .or_default()
+            .set(move |key: &[u8], old: Option<&[u8]>, operand: &[u8]| {
+                merge_operator(key, old, operand)
+            });
+    }
+
+    /// sled's own `merge` returns the merged value rather than the old one,
+    /// and can't clear the deadline of the key, so merges run the operator
+    /// inside of a sled transaction instead.
+    fn merge<O: AsRef<[u8]>>(&mut self, key: K, operand: O) -> Result<Option<InlineArray>, Error> {
+        let slot = lock(&self.merge_operators)
+            .get(&self.name())
+            .cloned()
+            .unwrap_or_default();
+        let merge_operator = slot.get()?;
+        let old = self.atomically(|txn| {
+            let old = txn.get(key.as_ref())?;
+            let new = merge_operator(key.as_ref(), old.as_deref(), operand.as_ref());
+            txn.put(key.as_ref(), new.as_deref(), None)?;
+            Ok(old)
+        })?;
+        self.written()?;
+
+        Ok(old.map(bytes))
+    }
+
+    /// Applied as a single `sled::Batch`, in the same sled transaction as
+    /// another one clearing the deadlines of the keys it writes. A clear
+    /// removes the keys the tree holds when the batch is built.
+    fn apply_batch(&mut self, batch: AymrBatch<K, V>) -> Result<(), Error> {
+        let mut native = sled::Batch::default();
+        let mut deadlines = sled::Batch::default();
+        for op in batch {
+            match op {
+                BatchOp::Clear => {
+                    native = sled::Batch::default();
+                    for key in self.tree.iter().keys() {
+                        native.remove(key?);
+                    }
+                    for deadline in self.deadlines() {
+                        deadlines.remove(deadline?.0);
+                    }
+                }
+                BatchOp::Insert(key, value) => {
+                    deadlines.remove(ttl::deadline_key(&self.deadline_prefix, key.as_ref()));
+                    native.insert(key.as_ref(), value.as_ref());
+                }
+                BatchOp::Remove(key) => {
+                    deadlines.remove(ttl::deadline_key(&self.deadline_prefix, key.as_ref()));
+                    native.remove(key.as_ref());
+                }
+            }
+        }
+        self.atomically(|txn| {
+            txn.tree.apply_batch(&native)?;
+            txn.deadlines.apply_batch(&deadlines)?;
+            Ok(())
+        })?;
+        self.written()
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, key: &Q) -> Result<bool, Error> {
+        Ok(AymrDatabase::<K, V>::get(self, key)?.is_some())
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        self.entries(self.tree.iter())
+    }
+
+    fn range<Q, R>(&self, range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let bounds = iter::byte_bounds(&range);
+        if iter::is_empty_range(&bounds) {
+            return Ok(Iter::empty());
+        }
+
+        self.entries(self.tree.range::<&[u8], _>(bounds))
+    }
+
+    fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Iter<'_>, Error> {
+        self.entries(self.tree.scan_prefix(prefix))
+    }
+
+    /// Removes the keys the tree holds under `prefix` when it starts, in a
+    /// single sled transaction. Expired keys are removed too, but not
+    /// counted.
+    fn remove_prefix<P: AsRef<[u8]>>(&mut self, prefix: P) -> Result<usize, Error> {
+        let keys = self
+            .tree
+            .scan_prefix(prefix)
+            .keys()
+            .collect::<Result<Vec<_>, _>>()?;
+        let removed = self.atomically(|txn| {
+            let mut removed = 0;
+            for key in &keys {
+                if txn.put(key, None, None)?.is_some() {
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        })?;
+        self.written()?;
+
+        Ok(removed)
+    }
+
+    fn get_lt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.last_of(self.tree.range::<&[u8], _>(..key.as_ref()))
+    }
+
+    fn get_gt<Q: AsRef<[u8]> + ?Sized>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        let bounds = (Bound::Excluded(key.as_ref()), Bound::Unbounded);
+        self.first_of(self.tree.range::<&[u8], _>(bounds))
+    }
+
+    fn first(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.first_of(self.tree.iter())
+    }
+
+    fn last(&self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.last_of(self.tree.iter())
+    }
+
+    fn pop_min(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.pop(|| self.tree.first())
+    }
+
+    fn pop_max(&mut self) -> Result<Option<(InlineArray, InlineArray)>, Error> {
+        self.pop(|| self.tree.last())
+    }
+
+    /// sled 0.34 can't take snapshots, so this returns `Error::Unsupported`.
+    fn snapshot(&self) -> Result<Self::Snapshot, Error> {
+        Err(Error::Unsupported(
+            "`sled` 0.34 can't take snapshots".to_string(),
+        ))
+    }
+
+    /// Wraps a sled subscription, which sees the writes of every handle to
+    /// the tree.
+    fn watch_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<Subscriber, Error> {
+        Ok(Subscriber::new(self.tree.watch_prefix(prefix)))
+    }
+}
+
+impl EventSource for sled::Subscriber {
+    fn next(&mut self) -> Option<Event> {
+        wait(self, None).ok()
+    }
+
+    fn next_timeout(&mut self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        wait(self, Instant::now().checked_add(timeout))
+    }
+}
+
+/// Waits for the next event of a sled subscription until `deadline`, or for
+/// as long as it takes without one.
+///
+/// sled's own `next_timeout` keeps failing once a write it reserved an event
+/// for turns out to change nothing, like removing a missing key. Polling the
+/// subscription as a future skips those, so the thread parks in between.
+fn wait(
+    subscriber: &mut sled::Subscriber,
+    deadline: Option<Instant>,
+) -> Result<Event, RecvTimeoutError> {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    // sled only registers the waker after finding nothing to receive, so the
+    // first poll is repeated to catch events sent in between.
+    let mut registered = false;
+
+    loop {
+        match Pin::new(&mut *subscriber).poll(&mut cx) {
+            Poll::Ready(Some(e)) => return Ok(event(e)),
+            Poll::Ready(None) => return Err(RecvTimeoutError::Disconnected),
+            Poll::Pending if !registered => {
+                registered = true;
+                continue;
+            }
+            Poll::Pending => {}
+        }
+
+        match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+                thread::park_timeout(left);
+            }
+            None => thread::park(),
+        }
+    }
+}
+
+/// Wakes a thread parked by `wait`.
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Snapshot of an `AymrSled`, which can't be taken.
+///
+/// This type has no values, it only exists to fill in
+/// `AymrDatabase::Snapshot`.
+#[derive(Debug, Clone)]
+pub enum AymrSledSnapshot {}
+
+impl<K: AsRef<[u8]>> AymrSnapshot<K> for AymrSledSnapshot {
+    fn len(&self) -> usize {
+        match *self {}
+    }
+
+    fn is_empty(&self) -> Result<bool, Error> {
+        match *self {}
+    }
+
+    fn get<Q: AsRef<[u8]> + ?Sized>(&self, _key: &Q) -> Result<Option<InlineArray>, Error> {
+        match *self {}
+    }
+
+    fn contains_key<Q: AsRef<[u8]> + ?Sized>(&self, _key: &Q) -> Result<bool, Error> {
+        match *self {}
+    }
+
+    fn iter(&self) -> Result<Iter<'_>, Error> {
+        match *self {}
+    }
+
+    fn range<Q, R>(&self, _range: R) -> Result<Iter<'_>, Error>
+    where
+        Q: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        match *self {}
+    }
+}
+
+/// Commits through a sled transaction, so the read set is validated against
+/// the writes of every handle to the tree.
+impl<K, V> AymrTransactional<K, V> for AymrSled
+where
+    K: AsRef<[u8]> + Clone,
+    V: AsRef<[u8]>,
+{
+    fn transaction<F, A, E>(&mut self, f: F) -> TransactionResult<A, E>
+    where
+        F: FnMut(&mut Transaction<'_, K, V, Self>) -> ConflictableTransactionResult<A, E>,
+    {
+        transaction::run(self, f, |db, pending| db.commit(pending))
+    }
+}
+
+/// Trees are sled's own, and tree handles share the durability of the
+/// database they were opened from. sled's default tree is not listed.
+impl<K, V> AymrTrees<K, V> for AymrSled
+where
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    type Tree = AymrSled;
+
+    fn open_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<Self::Tree, Error> {
+        let name = tree_name(name.as_ref())?;
+        Ok(AymrSled {
+            db: Arc::clone(&self.db),
+            tree: self.db.open_tree(name)?,
+            deadlines: self.deadlines.clone(),
+            deadline_prefix: ttl::deadline_prefix(name).into(),
+            durability: self.durability,
+            merge_operators: Arc::clone(&self.merge_operators),
+        })
+    }
+
+    /// The merge operator of the tree is dropped along with it.
+    fn drop_tree<N: AsRef<[u8]>>(&mut self, name: N) -> Result<bool, Error> {
+        let name = tree_name(name.as_ref())?;
+        lock(&self.merge_operators).remove(name);
+        let mut deadlines = sled::Batch::default();
+        for deadline in self.deadlines.scan_prefix(ttl::deadline_prefix(name)) {
+            deadlines.remove(deadline?.0);
+        }
+        self.deadlines.apply_batch(deadlines)?;
+
+        Ok(self.db.drop_tree(name)?)
+    }
+
+    /// sled lists its trees in no particular order, so they're sorted here.
+    fn tree_names(&self) -> Vec<InlineArray> {
+        let default = self.db.name();
+        let mut names: Vec<InlineArray> = self
+            .db
+            .tree_names()
+            .into_iter()
+            .filter(|name| *name != default && name != DEADLINES_TREE)
+            .map(bytes)
+            .collect();
+        names.sort();
+
+        names
+    }
+}
+
+impl AymrFlush for AymrSled {
+    fn flush(&self) -> Result<(), Error> {
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+impl AymrOpenable for AymrSled {
+    type Config = SledConfig;
+
+    fn open(config: SledConfig) -> Result<Self, Error> {
+        let db = open_native(&native_config(&config)?)?;
+        AymrSled::from_db(db, config.durability)
+    }
+
+    fn create_new(config: SledConfig) -> Result<Self, Error> {
+        let db = open_native(&native_config(&config)?.create_new(true))?;
+        AymrSled::from_db(db, config.durability)
+    }
+
+    fn open_existing(config: SledConfig) -> Result<Self, Error> {
+        let exists = config
+            .path
+            .as_ref()
+            .is_some_and(|path| path.join("db").is_file());
+        if !exists {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "there is no `sled` database at the configured path",
+            )));
+        }
+
+        Self::open(config)
+    }
+
+    /// Opens a database in a fresh temporary directory.
+    fn temporary() -> Result<Self, Error> {
+        Self::open(SledConfig {
+            path: None,
+            cache_capacity: None,
+            durability: Durability::default(),
+            temporary: true,
+        })
+    }
+
+    fn close(self) -> Result<(), Error> {
+        AymrFlush::flush(&self)
+    }
+}
+
+/// Locks the merge operators of a database. They are only ever replaced
+/// whole, so a panicking thread can't leave them half written.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Checks that `name` isn't the name of the deadlines tree.
+fn tree_name(name: &[u8]) -> Result<&[u8], Error> {
+    if name == DEADLINES_TREE {
+        return Err(Error::Unsupported(format!(
+            "`{}` holds the deadlines of expiring keys and can't be opened as a tree",
+            String::from_utf8_lossy(DEADLINES_TREE)
+        )));
+    }
+
+    Ok(name)
+}
+
+/// Opens a sled database, waiting up to a second for the lock on its files.
+///
+/// sled finishes writing in the background, so a database closed by this
+/// process can still be locked for a little while after its last handle is
+/// dropped.
+fn open_native(native: &sled::Config) -> Result<sled::Db, Error> {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        match native.open() {
+            Err(sled::Error::Io(e)) if is_locked(&e) && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            res => return Ok(res?),
+        }
+    }
+}
+
+/// Returns true if sled failed to open a database as it is locked.
+fn is_locked(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::Other && error.to_string().starts_with("could not acquire lock")
+}
+
+/// Turns a `SledConfig` into sled's own config.
+fn native_config(config: &SledConfig) -> Result<sled::Config, Error> {
+    let mut native = sled::Config::new().temporary(config.temporary);
+    match &config.path {
+        Some(path) => native = native.path(path),
+        None if config.temporary => {}
+        None => {
+            return Err(Error::Config(
+                "the `sled` backend needs a `path` unless it is `temporary`".to_string(),
+            ))
+        }
+    }
+    if let Some(bytes) = config.cache_capacity {
+        native = native.cache_capacity(bytes);
+    }
+
+    Ok(native)
+}
+
+fn bytes(value: sled::IVec) -> InlineArray {
+    InlineArray::from(&*value)
+}
+
+fn entry((key, value): (sled::IVec, sled::IVec)) -> (InlineArray, InlineArray) {
+    (bytes(key), bytes(value))
+}
+
+fn event(event: sled::Event) -> Event {
+    match event {
+        sled::Event::Insert { key, value } => Event::insert(&*key, &*value),
+        sled::Event::Remove { key } => Event::remove(&*key),
+    }
+}
+
+/// Wraps a sled iterator, passing on the errors of entries that can't be
+/// read from disk.
+fn entries(iter: sled::Iter) -> Iter<'static> {
+    Iter::fallible(iter.map(|res| res.map(entry).map_err(Error::from)))
+}
+
+impl From<sled::Error> for Error {
+    fn from(error: sled::Error) -> Self {
+        match error {
+            sled::Error::CollectionNotFound(name) => {
+                Error::CollectionNotFound(String::from_utf8_lossy(&name).into_owned())
+            }
+            sled::Error::Unsupported(why) => Error::Unsupported(why),
+            sled::Error::ReportableBug(what) => Error::ReportableBug(what),
+            sled::Error::Io(e) => Error::Io(e),
+            sled::Error::Corruption { at, .. } => {
+                Error::corruption(at.map(|at| format!("{:?}", at)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn concatenate(_key: &[u8], old: Option<&[u8]>, operand: &[u8]) -> Option<Vec<u8>> {
+        let mut value = old.unwrap_or_default().to_vec();
+        value.extend_from_slice(operand);
+        Some(value)
+    }
+
+    /// Every handle to a tree merges with the operator set through any of
+    /// them.
+    #[test]
+    fn test_merge_operator_is_shared() {
+        let mut db = AymrSled::temporary().unwrap();
+        let mut tree = AymrTrees::<&[u8], &[u8]>::open_tree(&mut db, b"tree").unwrap();
+        let mut again = AymrTrees::<&[u8], &[u8]>::open_tree(&mut db, b"tree").unwrap();
+        AymrDatabase::<&[u8], &[u8]>::set_merge_operator(&mut tree, concatenate);
+
+        AymrDatabase::<&[u8], &[u8]>::merge(&mut again, b"key", b"a").unwrap();
+        AymrDatabase::<&[u8], &[u8]>::merge(&mut tree.clone(), b"key", b"b").unwrap();
+        assert_eq!(
+            AymrDatabase::<&[u8], &[u8]>::get(&tree, b"key").unwrap(),
+            Some("ab".into())
+        );
+
+        // Other trees, and the tree once dropped, have no operator.
+        let res = AymrDatabase::<&[u8], &[u8]>::merge(&mut db, b"key", b"a");
+        assert!(matches!(res, Err(Error::Unsupported(_))));
+        AymrTrees::<&[u8], &[u8]>::drop_tree(&mut db, b"tree").unwrap();
+        let mut tree = AymrTrees::<&[u8], &[u8]>::open_tree(&mut db, b"tree").unwrap();
+        let res = AymrDatabase::<&[u8], &[u8]>::merge(&mut tree, b"key", b"a");
+        assert!(matches!(res, Err(Error::Unsupported(_))));
+    }
+
+    /// Merge operators are registered with sled, so its own merges run them.
+    #[test]
+    fn test_merge_operator_is_native() {
+        let mut db = AymrSled::open(SledConfig {
+            path: None,
+            temporary: true,
+            ..SledConfig::new("")
+        })
+        .unwrap();
+        AymrDatabase::<&[u8], &[u8]>::set_merge_operator(&mut db, concatenate);
+
+        assert_eq!(db.tree.merge(b"key", b"a").unwrap(), Some("a".into()));
+        assert_eq!(
+            AymrDatabase::<&[u8], &[u8]>::merge(&mut db, b"key", b"b").unwrap(),
+            Some("a".into())
+        );
+        assert_eq!(
+            AymrDatabase::<&[u8], &[u8]>::get(&db, b"key").unwrap(),
+            Some("ab".into())
+        );
+    }
+}
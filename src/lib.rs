@@ -24,6 +24,11 @@
 //!
 //! - [BTreeMap](https://doc.rust-lang.org/std/collections/struct.BTreeMap.html)
 //! - [HashMap](https://doc.rust-lang.org/std/collections/struct.HashMap.html)
+//! - [sled](https://docs.rs/sled/0.34.7) `0.34`, with the `sled_pre` feature
+//! - [sled](https://docs.rs/sled/1.0.0-alpha.124) `1.0-alpha`, with the `sled_alpha` feature
+//! - [redb](https://docs.rs/redb/4.4.0) `4`, with the `redb` feature
+//! - [fjall](https://docs.rs/fjall/2.11.2) `2`, with the `fjall` feature
+//! - [LMDB](https://docs.rs/heed/0.22.1) through heed `0.22`, with the `lmdb` feature
 //!
 //! Because database configs have little in common with each other, the config for every
 //! Aymr Db will be different. Please consult the Aymr docs for more info in how to set up and run each DB.
@@ -37,4 +42,4 @@
 //! ```
 
 pub mod aymr_db;
-mod backends;
+pub mod backends;